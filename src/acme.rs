@@ -0,0 +1,618 @@
+//! Zone の `acmeCertificateProvider` による証明書の自動取得と更新
+//!
+//! Zone 配下の Subdomain を参照する VirtualHost の FQDN ごとに ACME 注文を行い、
+//! 取得した証明書と秘密鍵を VirtualHost の `certificate` / `key` に書き込む。
+//! 注文の状態は `/acme/orders/{realm}/{zone}/{fqdn}` に保存され、API から参照できる。
+use crate::acme_client::{self, AccountKey, Session};
+use crate::ca;
+use crate::db::{AppState, REALM_PREFIX};
+use crate::dns;
+use crate::dns_provider;
+use crate::error::ApiError;
+use crate::secret::{Secret, SecretFields};
+use crate::host_index::{self, IndexedWrite};
+use crate::subdomain::{self, Subdomain};
+use crate::urn::Urn;
+use crate::utils::{get_from_etcd, get_secret_document};
+use crate::validation::{self, RealmName, SubdomainName, VirtualHostName, ZoneName};
+use crate::virtual_host::VirtualHost;
+use crate::zone::{self, Zone};
+use anyhow::{anyhow, Context};
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL, Engine};
+use rcgen::{CertificateParams, DistinguishedName, KeyPair};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::sync::Mutex;
+use std::time::Duration;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::{info, warn};
+
+pub const ACME_ACCOUNT_PREFIX: &str = "/acme/accounts/";
+const HTTP01_PREFIX: &str = "/acme/http-01/";
+
+/// 期限切れ前に更新を始める既定の日数
+const DEFAULT_RENEW_BEFORE_DAYS: i64 = 30;
+/// 更新チェックの既定の間隔 (秒)
+const DEFAULT_RENEW_INTERVAL_SECS: u64 = 12 * 60 * 60;
+/// 証明書の書き込み中に VirtualHost が更新された場合の再試行回数
+const STORE_RETRIES: usize = 3;
+
+/// ACME の実行設定 (環境変数から読み込む)
+///
+/// - `ACME_CA_BUNDLE`: ACME サーバーの TLS 証明書を検証する追加の CA (PEM ファイル)
+/// - `ACME_INSECURE_SKIP_VERIFY`: `true` なら ACME サーバーの TLS 証明書を検証しない (テスト用)
+/// - `ACME_RENEW_BEFORE_DAYS`: 残り日数がこれ以下になったら更新する (既定 30)
+/// - `ACME_RENEW_INTERVAL_SECS`: 自動更新チェックの間隔 (既定 12 時間、0 で無効)
+/// - `ACME_DNS01_CHALLTESTSRV_URL`: DNS プロバイダーで管理していない Zone の DNS-01 の TXT レコードを設定する
+///   challtestsrv 互換の管理 API (テスト用)
+pub struct AcmeSettings {
+    http: reqwest::Client,
+    renew_before_days: i64,
+    renew_interval: Duration,
+    challtestsrv_url: Option<String>,
+    // 実行中の注文 (注文キー)
+    in_flight: Mutex<HashSet<String>>,
+}
+
+impl AcmeSettings {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut builder = reqwest::Client::builder().timeout(Duration::from_secs(30));
+        if let Ok(path) = env::var("ACME_CA_BUNDLE") {
+            let pem = std::fs::read(&path).with_context(|| format!("Failed to read ACME_CA_BUNDLE '{}'", path))?;
+            for cert in reqwest::Certificate::from_pem_bundle(&pem)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if env::var("ACME_INSECURE_SKIP_VERIFY").is_ok_and(|v| v == "true") {
+            warn!("ACME_INSECURE_SKIP_VERIFY is set; ACME server certificates are not verified");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        let renew_before_days = match env::var("ACME_RENEW_BEFORE_DAYS") {
+            Ok(v) => v.parse().context("ACME_RENEW_BEFORE_DAYS must be a number of days")?,
+            Err(_) => DEFAULT_RENEW_BEFORE_DAYS,
+        };
+        let renew_interval = match env::var("ACME_RENEW_INTERVAL_SECS") {
+            Ok(v) => v.parse().context("ACME_RENEW_INTERVAL_SECS must be a number of seconds")?,
+            Err(_) => DEFAULT_RENEW_INTERVAL_SECS,
+        };
+        Ok(AcmeSettings {
+            http: builder.build()?,
+            renew_before_days,
+            renew_interval: Duration::from_secs(renew_interval),
+            challtestsrv_url: env::var("ACME_DNS01_CHALLTESTSRV_URL").ok().filter(|v| !v.is_empty()),
+            in_flight: Mutex::new(HashSet::new()),
+        })
+    }
+}
+
+/// ACME チャレンジの種類
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum AcmeChallengeType {
+    #[default]
+    #[serde(rename = "http-01")]
+    Http01,
+    #[serde(rename = "dns-01")]
+    Dns01,
+}
+
+impl AcmeChallengeType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AcmeChallengeType::Http01 => "http-01",
+            AcmeChallengeType::Dns01 => "dns-01",
+        }
+    }
+}
+
+/// 注文の状態
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum AcmeOrderStatus {
+    // 受け付け済みで未実行
+    Pending,
+    // ACME サーバーとやり取り中
+    Processing,
+    // 証明書を取得し VirtualHost に保存済み
+    Valid,
+    // 失敗 (理由は `error`)
+    Invalid,
+}
+
+/// FQDN ごとの ACME 注文の状態
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AcmeOrder {
+    pub fqdn: String,
+    pub zone: String,
+    // 証明書の保存先 (`urn:chip-in:virtual-host:{realm}:{name}`)
+    pub virtual_hosts: Vec<String>,
+    pub directory_url: String,
+    pub challenge_type: AcmeChallengeType,
+    pub status: AcmeOrderStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<String>,
+    pub updated_at: String,
+}
+
+/// ACME サーバー (ディレクトリ URL) ごとのアカウント
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct AcmeAccount {
+    pub directory_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_url: Option<String>,
+    // PKCS#8 PEM 形式のアカウント鍵
+    pub key: Secret,
+}
+
+impl SecretFields for AcmeAccount {
+    fn redact(&mut self) {
+        self.key = Secret::new("");
+    }
+
+    fn retain_secrets(&mut self, _stored: &Self) {}
+
+    fn secrets_mut(&mut self) -> Vec<&mut Secret> {
+        vec![&mut self.key]
+    }
+}
+
+/// 注文の開始リクエスト (本文は省略可能)
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct StartOrderRequest {
+    // 指定した FQDN だけを対象にする
+    #[serde(default)]
+    pub fqdn: Option<String>,
+    // 証明書の残り日数に関わらず再取得する
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Zone 単位の ACME エンドポイント (/realms/{realm}/zones/{zone}/acme)
+pub fn zone_routes() -> Router<AppState> {
+    Router::new()
+        .route("/orders", get(list_orders).post(start_orders))
+        .route("/orders/{fqdn}", get(get_order))
+}
+
+fn order_key(realm: &str, zone: &str, fqdn: &str) -> String {
+    format!("/acme/orders/{}/{}/{}", realm, zone, fqdn)
+}
+
+fn order_prefix(realm: &str, zone: &str) -> String {
+    format!("/acme/orders/{}/{}/", realm, zone)
+}
+
+fn account_key(directory_url: &str) -> String {
+    format!("{}{}", ACME_ACCOUNT_PREFIX, BASE64URL.encode(digest(&SHA256, directory_url.as_bytes())))
+}
+
+fn now() -> String {
+    OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default()
+}
+
+/// GET /realms/{realm}/zones/{zone}/acme/orders
+async fn list_orders(
+    State(state): State<AppState>,
+    Path((realm, zone_name)): Path<(String, String)>,
+) -> Result<Json<Vec<AcmeOrder>>, ApiError> {
+    let mut client = state.etcd_client.clone();
+    let (realm, zone_name) = (RealmName::parse("realm", &realm)?, ZoneName::parse("zone", &zone_name)?);
+    let resp = client.get(order_prefix(&realm, &zone_name), Some(etcd_client::GetOptions::new().with_prefix())).await?;
+    let orders = resp
+        .kvs()
+        .iter()
+        .filter_map(|kv| serde_json::from_slice(kv.value()).ok())
+        .collect();
+    Ok(Json(orders))
+}
+
+/// GET /realms/{realm}/zones/{zone}/acme/orders/{fqdn}
+async fn get_order(
+    State(state): State<AppState>,
+    Path((realm, zone_name, fqdn)): Path<(String, String, String)>,
+) -> Result<Json<AcmeOrder>, ApiError> {
+    let (realm, zone_name) = (RealmName::parse("realm", &realm)?, ZoneName::parse("zone", &zone_name)?);
+    let fqdn = validation::domain_name("fqdn", &fqdn, true)?;
+    match get_from_etcd(&state, &order_key(&realm, &zone_name, &fqdn)).await?.kvs().first() {
+        Some(kv) => Ok(Json(serde_json::from_slice(kv.value())?)),
+        None => Err(ApiError::NotFound(format!("No ACME order for '{}' in zone '{}'", fqdn, zone_name))),
+    }
+}
+
+/// POST /realms/{realm}/zones/{zone}/acme/orders
+///
+/// 証明書が無いか期限が近い FQDN の注文を開始する (`force` なら全て)。処理はバックグラウンドで進み、
+/// 受け付けた注文を 202 で返す。
+async fn start_orders(
+    State(state): State<AppState>,
+    Path((realm, zone_name)): Path<(String, String)>,
+    request: Option<Json<StartOrderRequest>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Json(mut request) = request.unwrap_or_default();
+    request.fqdn = request.fqdn.map(|fqdn| validation::domain_name("fqdn", &fqdn, true)).transpose()?;
+    let zone = zone::load_zone(&state, &realm, &zone_name).await?;
+    let orders = start_zone_orders(&state, &realm, &zone, &request).await?;
+    Ok((StatusCode::ACCEPTED, Json(orders)))
+}
+
+/// GET /.well-known/acme-challenge/{token}
+///
+/// HTTP-01 チャレンジのキー認可を返す。Hub はこのパスを API サーバーへ転送する。
+pub async fn http01_response(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    match get_from_etcd(&state, &format!("{}{}", HTTP01_PREFIX, token)).await?.kvs().first() {
+        Some(kv) => Ok(([(header::CONTENT_TYPE, "text/plain")], kv.value().to_vec())),
+        None => Err(ApiError::NotFound(format!("Unknown ACME challenge token '{}'", token))),
+    }
+}
+
+/// 証明書の保存先となる VirtualHost (Realm 名と VirtualHost)
+type Targets = BTreeMap<String, Vec<(String, VirtualHost)>>;
+
+/// Zone 配下の Subdomain を参照する VirtualHost を FQDN ごとにまとめる
+async fn zone_targets(state: &AppState, realm: &str, zone_name: &str) -> Result<Targets, ApiError> {
+    let (realm, zone_name) = (RealmName::parse("realm", realm)?, ZoneName::parse("zone", zone_name)?);
+    let mut client = state.etcd_client.clone();
+    let resp = client.get(subdomain::subdomain_prefix(&realm, &zone_name), Some(etcd_client::GetOptions::new().with_prefix())).await?;
+
+    // Subdomain を配信する VirtualHost は索引 (`/index/bindings/`) で引く (無効化された VirtualHost は含まれない)
+    let mut targets = Targets::new();
+    for kv in resp.kvs() {
+        let Ok(sub) = serde_json::from_slice::<Subdomain>(kv.value()) else {
+            continue;
+        };
+        let (Some(fqdn), Ok(name)) = (sub.fqdn, SubdomainName::parse("name", &sub.name)) else {
+            continue;
+        };
+        let reference = Urn::Subdomain(realm.clone(), zone_name.clone(), name).to_string();
+        let Some(binding) = get_from_etcd(state, &host_index::binding_key(&reference)).await?.kvs().first().cloned() else {
+            continue;
+        };
+        let Ok(host_urn @ Urn::VirtualHost(..)) = Urn::parse("virtualHost", binding.value_str()?) else {
+            continue;
+        };
+        if let Some(host) = get_secret_document::<VirtualHost>(state, &host_urn.key()).await?.filter(|host| !host.disabled) {
+            targets.entry(fqdn).or_default().push((host_urn.realm().to_string(), host));
+        }
+    }
+    Ok(targets)
+}
+
+/// Zone の注文を開始し、受け付けた注文を返す
+async fn start_zone_orders(state: &AppState, realm: &str, zone: &Zone, request: &StartOrderRequest) -> Result<Vec<AcmeOrder>, ApiError> {
+    let Some(directory_url) = zone.acme_certificate_provider.clone() else {
+        return Err(ApiError::Unprocessable(format!(
+            "acmeCertificateProvider: zone '{}' has no ACME certificate provider",
+            zone.zone
+        )));
+    };
+
+    let mut targets = zone_targets(state, realm, &zone.zone).await?;
+    if let Some(fqdn) = &request.fqdn {
+        targets.retain(|name, _| name == fqdn);
+        if targets.is_empty() {
+            return Err(ApiError::NotFound(format!("No virtual host in zone '{}' serves '{}'", zone.zone, fqdn)));
+        }
+    }
+    // ワイルドカードの証明書は DNS-01 でしか検証できない (RFC 8555 7.1.3)
+    let challenge_type = zone.acme_challenge_type.unwrap_or_default();
+    if challenge_type == AcmeChallengeType::Http01 {
+        if let Some(fqdn) = request.fqdn.as_ref().filter(|f| f.starts_with("*.")) {
+            return Err(ApiError::Unprocessable(format!(
+                "acmeChallengeType: wildcard '{}' requires the dns-01 challenge",
+                fqdn
+            )));
+        }
+        targets.retain(|fqdn, _| {
+            let wildcard = fqdn.starts_with("*.");
+            if wildcard {
+                warn!("Skipping ACME order for wildcard '{}': zone '{}' uses http-01", fqdn, zone.zone);
+            }
+            !wildcard
+        });
+    }
+
+    let zone_urn = Urn::Zone(RealmName::parse("realm", realm)?, ZoneName::parse("zone", &zone.zone)?);
+    let mut orders = Vec::new();
+    for (fqdn, hosts) in targets {
+        let renew = request.force
+            || hosts.iter().any(|(_, h)| ca::needs_renewal(&h.certificate.join("\n"), state.acme.renew_before_days));
+        if !renew {
+            continue;
+        }
+        let key = order_key(realm, &zone.zone, &fqdn);
+        if !state.acme.in_flight.lock().unwrap().insert(key.clone()) {
+            continue;
+        }
+
+        let order = AcmeOrder {
+            fqdn: fqdn.clone(),
+            zone: zone_urn.to_string(),
+            virtual_hosts: hosts
+                .iter()
+                .map(|(r, h)| Ok(Urn::VirtualHost(RealmName::parse("realm", r)?, VirtualHostName::parse("name", &h.name)?).to_string()))
+                .collect::<Result<_, ApiError>>()?,
+            directory_url: directory_url.clone(),
+            challenge_type,
+            status: AcmeOrderStatus::Pending,
+            order_url: None,
+            error: None,
+            not_after: None,
+            updated_at: now(),
+        };
+        if let Err(err) = save_order(state, &key, &order).await {
+            state.acme.in_flight.lock().unwrap().remove(&key);
+            return Err(err);
+        }
+        tokio::spawn(run_order(state.clone(), key, order.clone()));
+        orders.push(order);
+    }
+    Ok(orders)
+}
+
+async fn save_order(state: &AppState, key: &str, order: &AcmeOrder) -> Result<(), ApiError> {
+    let mut client = state.etcd_client.clone();
+    client.put(key, serde_json::to_vec(order)?, None).await?;
+    Ok(())
+}
+
+/// 注文を最後まで実行し、結果を注文の状態として保存する
+async fn run_order(state: AppState, key: String, mut order: AcmeOrder) {
+    order.status = AcmeOrderStatus::Processing;
+    order.updated_at = now();
+    let result = match save_order(&state, &key, &order).await {
+        Ok(()) => obtain_certificate(&state, &key, &mut order).await,
+        Err(e) => Err(anyhow!("Failed to record order state: {}", e)),
+    };
+    match result {
+        Ok(()) => {
+            info!("ACME certificate for '{}' issued", order.fqdn);
+            order.status = AcmeOrderStatus::Valid;
+            order.error = None;
+        }
+        Err(err) => {
+            warn!("ACME order for '{}' failed: {:#}", order.fqdn, err);
+            order.status = AcmeOrderStatus::Invalid;
+            order.error = Some(format!("{:#}", err));
+        }
+    }
+    order.updated_at = now();
+    if let Err(e) = save_order(&state, &key, &order).await {
+        warn!("Failed to record ACME order state for '{}': {:#}", order.fqdn, e);
+    }
+    state.acme.in_flight.lock().unwrap().remove(&key);
+}
+
+/// アカウント鍵を読み込む (無ければ生成する)
+async fn load_account(state: &AppState, directory_url: &str) -> anyhow::Result<AcmeAccount> {
+    let stored = get_secret_document::<AcmeAccount>(state, &account_key(directory_url))
+        .await
+        .map_err(|_| anyhow!("Failed to load ACME account"))?;
+    match stored {
+        Some(account) => Ok(account),
+        None => {
+            let key = AccountKey::generate()?;
+            let pem = ::pem::encode(&::pem::Pem::new("PRIVATE KEY", key.pkcs8().to_vec()));
+            Ok(AcmeAccount { directory_url: directory_url.to_string(), account_url: None, key: Secret::new(pem) })
+        }
+    }
+}
+
+async fn save_account(state: &AppState, account: &AcmeAccount) -> anyhow::Result<()> {
+    let value = state.keyring.encode(account).map_err(|_| anyhow!("Failed to encrypt ACME account key"))?;
+    let mut client = state.etcd_client.clone();
+    client.put(account_key(&account.directory_url), value, None).await?;
+    Ok(())
+}
+
+async fn obtain_certificate(state: &AppState, key: &str, order: &mut AcmeOrder) -> anyhow::Result<()> {
+    let mut account = load_account(state, &order.directory_url).await?;
+    let account_key = AccountKey::from_pkcs8(::pem::parse(account.key.expose())?.contents())?;
+
+    let mut session = Session::connect(&state.acme.http, &order.directory_url, &account_key, account.account_url.clone()).await?;
+    let kid = session.ensure_account().await?;
+    if account.account_url.as_deref() != Some(kid.as_str()) {
+        account.account_url = Some(kid);
+        save_account(state, &account).await?;
+    }
+
+    let (order_url, acme_order) = session.new_order(&order.fqdn).await?;
+    order.order_url = Some(order_url.clone());
+    order.updated_at = now();
+    save_order(state, key, order).await.map_err(|_| anyhow!("Failed to record order state"))?;
+
+    for authz_url in &acme_order.authorizations {
+        let authz = session.authorization(authz_url).await?;
+        if authz.status == "valid" {
+            continue;
+        }
+        let challenge = authz
+            .challenges
+            .iter()
+            .find(|c| c.challenge_type == order.challenge_type.as_str())
+            .ok_or_else(|| anyhow!("ACME server offered no {} challenge for '{}'", order.challenge_type.as_str(), authz.identifier.value))?;
+        let key_authorization = account_key.key_authorization(&challenge.token);
+
+        present_challenge(state, order, &authz.identifier.value, &challenge.token, &key_authorization).await?;
+        let result = match session.respond_challenge(&challenge.url).await {
+            Ok(()) => session.poll_authorization(authz_url).await.map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = cleanup_challenge(state, order, &authz.identifier.value, &challenge.token).await {
+            warn!("Failed to clean up {} challenge for '{}': {:#}", order.challenge_type.as_str(), authz.identifier.value, err);
+        }
+        result?;
+    }
+
+    let cert_key = KeyPair::generate()?;
+    let mut params = CertificateParams::new(vec![order.fqdn.clone()])?;
+    params.distinguished_name = DistinguishedName::new();
+    let csr = params.serialize_request(&cert_key)?;
+    let chain_pem = session.finalize(&order_url, &acme_order, csr.der()).await?;
+
+    let chain: Vec<String> = ::pem::parse_many(&chain_pem)?.iter().map(::pem::encode).collect();
+    let leaf = crate::pki::parse_certificates("certificate", &chain_pem).map_err(|_| anyhow!("ACME server returned an unreadable certificate"))?;
+    order.not_after = leaf[0].parsed().validity().not_after.to_datetime().format(&Rfc3339).ok();
+
+    store_certificate(state, order, chain, Secret::new(cert_key.serialize_pem())).await
+}
+
+/// 取得した証明書を注文対象の全 VirtualHost に書き込む
+async fn store_certificate(state: &AppState, order: &AcmeOrder, chain: Vec<String>, cert_key: Secret) -> anyhow::Result<()> {
+    for urn in &order.virtual_hosts {
+        let Ok(reference @ Urn::VirtualHost(..)) = Urn::parse("virtualHosts", urn) else {
+            warn!("Skipping malformed virtual host reference '{}'", urn);
+            continue;
+        };
+        store_on_host(state, urn, &reference.key(), &chain, &cert_key).await?;
+    }
+    Ok(())
+}
+
+/// 証明書を 1 つの VirtualHost に書き込む (読み取った後に更新されていれば読み直して再試行する)
+async fn store_on_host(state: &AppState, urn: &str, host_key: &str, chain: &[String], cert_key: &Secret) -> anyhow::Result<()> {
+    for _ in 0..STORE_RETRIES {
+        let resp = get_from_etcd(state, host_key).await.map_err(|e| anyhow!("Failed to load virtual host '{}': {}", urn, e))?;
+        let Some(kv) = resp.kvs().first() else {
+            warn!("Virtual host '{}' was removed before its certificate was issued", urn);
+            return Ok(());
+        };
+        let mut host: VirtualHost = state.keyring.decode(kv.value()).map_err(|e| anyhow!("Failed to load virtual host '{}': {}", urn, e))?;
+        host.certificate = chain.to_vec();
+        host.key = Some(cert_key.clone());
+        let value = state.keyring.encode(&host).map_err(|e| anyhow!("Failed to encrypt key for '{}': {}", urn, e))?;
+        let mut write = IndexedWrite::new();
+        write.require_revision(host_key, kv.mod_revision());
+        write.put(host_key, value);
+        match write.commit(state).await {
+            Ok(_) => return Ok(()),
+            Err(ApiError::Conflict(_)) => warn!("Virtual host '{}' was updated while storing its certificate; retrying", urn),
+            Err(e) => return Err(anyhow!("Failed to store the certificate for '{}': {}", urn, e)),
+        }
+    }
+    Err(anyhow!("Virtual host '{}' kept changing while storing its certificate", urn))
+}
+
+/// チャレンジに応答できるようにキー認可を配置する
+///
+/// DNS-01 の TXT レコードは Zone の DNS プロバイダーに反映する。Zone が DNS プロバイダーで管理されていなければ
+/// `ACME_DNS01_CHALLTESTSRV_URL` (テスト用) に設定する。
+async fn present_challenge(state: &AppState, order: &AcmeOrder, fqdn: &str, token: &str, key_authorization: &str) -> anyhow::Result<()> {
+    match order.challenge_type {
+        AcmeChallengeType::Http01 => {
+            let mut client = state.etcd_client.clone();
+            client.put(format!("{}{}", HTTP01_PREFIX, token), key_authorization, None).await?;
+        }
+        AcmeChallengeType::Dns01 => {
+            let value = acme_client::dns01_value(key_authorization);
+            let (realm, zone) = order_zone(state, order).await?;
+            let published = dns::publish_challenge(state, &realm, &zone, fqdn, &value)
+                .await
+                .map_err(|_| anyhow!("Failed to publish the dns-01 challenge for '{}' in zone '{}'", fqdn, zone.zone))?;
+            if !published {
+                let body = json!({ "host": format!("_acme-challenge.{}.", fqdn), "value": value });
+                let url = format!("{}/set-txt", challtestsrv_url(state, &zone)?);
+                state.acme.http.post(url).json(&body).send().await?.error_for_status()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn cleanup_challenge(state: &AppState, order: &AcmeOrder, fqdn: &str, token: &str) -> anyhow::Result<()> {
+    match order.challenge_type {
+        AcmeChallengeType::Http01 => {
+            let mut client = state.etcd_client.clone();
+            client.delete(format!("{}{}", HTTP01_PREFIX, token), None).await?;
+        }
+        AcmeChallengeType::Dns01 => {
+            let (realm, zone) = order_zone(state, order).await?;
+            if dns_provider::for_zone(&zone).is_ok_and(|provider| provider.is_some()) {
+                dns::withdraw_challenge(state, &realm, &zone, fqdn)
+                    .await
+                    .map_err(|_| anyhow!("Failed to withdraw the dns-01 challenge for '{}' in zone '{}'", fqdn, zone.zone))?;
+            } else {
+                let body = json!({ "host": format!("_acme-challenge.{}.", fqdn) });
+                let url = format!("{}/clear-txt", challtestsrv_url(state, &zone)?);
+                state.acme.http.post(url).json(&body).send().await?.error_for_status()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 注文の対象の Zone を読み込む
+async fn order_zone(state: &AppState, order: &AcmeOrder) -> anyhow::Result<(String, Zone)> {
+    let Ok(Urn::Zone(realm, zone_name)) = Urn::parse("zone", &order.zone) else {
+        return Err(anyhow!("Order for '{}' refers to '{}', which is not a zone URN", order.fqdn, order.zone));
+    };
+    let zone = zone::load_zone(state, &realm, &zone_name).await.map_err(|_| anyhow!("Failed to load zone '{}'", order.zone))?;
+    Ok((realm.to_string(), zone))
+}
+
+fn challtestsrv_url<'a>(state: &'a AppState, zone: &Zone) -> anyhow::Result<&'a str> {
+    state.acme.challtestsrv_url.as_deref().map(|url| url.trim_end_matches('/')).ok_or_else(|| {
+        anyhow!(
+            "Zone '{}' has no rfc2136 dnsProvider to publish the dns-01 challenge (or set ACME_DNS01_CHALLTESTSRV_URL for tests)",
+            zone.zone
+        )
+    })
+}
+
+/// 全 Zone の証明書を定期的に確認し、期限が近いものを更新する
+pub async fn renewal_task(state: AppState) {
+    if state.acme.renew_interval.is_zero() {
+        return;
+    }
+    let mut ticker = tokio::time::interval(state.acme.renew_interval);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = renew_all(&state).await {
+            warn!("ACME renewal check failed: {:#}", e);
+        }
+    }
+}
+
+async fn renew_all(state: &AppState) -> Result<(), ApiError> {
+    let mut client = state.etcd_client.clone();
+    let resp = client.get(REALM_PREFIX, Some(etcd_client::GetOptions::new().with_prefix())).await?;
+    for kv in resp.kvs() {
+        let key = kv.key_str()?;
+        let segments: Vec<&str> = key.trim_start_matches(REALM_PREFIX).split('/').collect();
+        let [realm, "zones", _] = segments.as_slice() else {
+            continue;
+        };
+        let Ok(zone) = state.keyring.decode::<Zone>(kv.value()) else {
+            continue;
+        };
+        if zone.acme_certificate_provider.is_none() {
+            continue;
+        }
+        match start_zone_orders(state, realm, &zone, &StartOrderRequest::default()).await {
+            Ok(orders) if !orders.is_empty() => info!("Started {} ACME renewal order(s) for zone '{}'", orders.len(), zone.zone),
+            Ok(_) => {}
+            Err(e) => warn!("Failed to start ACME renewal orders for zone '{}': {:#}", zone.zone, e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,271 @@
+//! ACME (RFC 8555) プロトコルクライアント
+//!
+//! アカウント鍵は ECDSA P-256 (ES256) 固定。注文処理の流れ (チャレンジの配置や保存) は `acme` モジュールが扱う。
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL, Engine};
+use reqwest::{header, Response, StatusCode};
+use ring::{
+    digest::{digest, SHA256},
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+const JOSE_CONTENT_TYPE: &str = "application/jose+json";
+const POLL_ATTEMPTS: usize = 30;
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// ACME ディレクトリ
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Directory {
+    pub new_nonce: String,
+    pub new_account: String,
+    pub new_order: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Order {
+    pub status: String,
+    #[serde(default)]
+    pub authorizations: Vec<String>,
+    pub finalize: String,
+    #[serde(default)]
+    pub certificate: Option<String>,
+    #[serde(default)]
+    pub error: Option<Value>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Authorization {
+    pub status: String,
+    pub identifier: Identifier,
+    #[serde(default)]
+    pub challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Identifier {
+    pub value: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Challenge {
+    #[serde(rename = "type")]
+    pub challenge_type: String,
+    pub url: String,
+    pub token: String,
+    #[serde(default)]
+    pub error: Option<Value>,
+}
+
+/// ACME アカウント鍵 (PKCS#8 で保存する)
+pub struct AccountKey {
+    pair: EcdsaKeyPair,
+    pkcs8: Vec<u8>,
+}
+
+impl AccountKey {
+    pub fn generate() -> anyhow::Result<Self> {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).map_err(|_| anyhow!("Failed to generate account key"))?;
+        Self::from_pkcs8(pkcs8.as_ref())
+    }
+
+    pub fn from_pkcs8(pkcs8: &[u8]) -> anyhow::Result<Self> {
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8, &SystemRandom::new())
+            .map_err(|e| anyhow!("Invalid ACME account key: {}", e))?;
+        Ok(AccountKey { pair, pkcs8: pkcs8.to_vec() })
+    }
+
+    pub fn pkcs8(&self) -> &[u8] {
+        &self.pkcs8
+    }
+
+    fn jwk(&self) -> Value {
+        // 非圧縮形式の公開鍵: 0x04 || x (32 バイト) || y (32 バイト)
+        let point = self.pair.public_key().as_ref();
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": BASE64URL.encode(&point[1..33]),
+            "y": BASE64URL.encode(&point[33..65]),
+        })
+    }
+
+    /// RFC 7638 の JWK サムプリント
+    fn thumbprint(&self) -> String {
+        // 必須メンバーを辞書順に並べ、空白を含めない正規形
+        let jwk = self.jwk();
+        let canonical = format!(r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#, jwk["x"].as_str().unwrap_or_default(), jwk["y"].as_str().unwrap_or_default());
+        BASE64URL.encode(digest(&SHA256, canonical.as_bytes()))
+    }
+
+    /// チャレンジに対するキー認可文字列
+    pub fn key_authorization(&self, token: &str) -> String {
+        format!("{}.{}", token, self.thumbprint())
+    }
+
+    fn sign(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let sig = self.pair.sign(&SystemRandom::new(), data).map_err(|_| anyhow!("Failed to sign JWS"))?;
+        Ok(sig.as_ref().to_vec())
+    }
+}
+
+/// DNS-01 で `_acme-challenge` TXT レコードに設定する値
+pub fn dns01_value(key_authorization: &str) -> String {
+    BASE64URL.encode(digest(&SHA256, key_authorization.as_bytes()))
+}
+
+/// 1 つの ACME サーバーとのセッション (アカウント URL と nonce を保持する)
+pub struct Session<'a> {
+    http: &'a reqwest::Client,
+    directory: Directory,
+    key: &'a AccountKey,
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+impl<'a> Session<'a> {
+    pub async fn connect(http: &'a reqwest::Client, directory_url: &str, key: &'a AccountKey, kid: Option<String>) -> anyhow::Result<Self> {
+        let directory = http
+            .get(directory_url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to fetch ACME directory '{}'", directory_url))?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Malformed ACME directory")?;
+        Ok(Session { http, directory, key, kid, nonce: None })
+    }
+
+    async fn fresh_nonce(&mut self) -> anyhow::Result<String> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+        let resp = self.http.head(&self.directory.new_nonce).send().await?;
+        replay_nonce(&resp).ok_or_else(|| anyhow!("ACME server did not return a nonce"))
+    }
+
+    /// JWS で署名した POST を送る (`payload` が `None` なら POST-as-GET)。badNonce は 1 度だけ再試行する。
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> anyhow::Result<Response> {
+        let mut retried = false;
+        loop {
+            let nonce = self.fresh_nonce().await?;
+            let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+            match &self.kid {
+                Some(kid) => protected["kid"] = json!(kid),
+                None => protected["jwk"] = self.key.jwk(),
+            }
+            let protected = BASE64URL.encode(protected.to_string());
+            let payload = payload.map(|p| BASE64URL.encode(p.to_string())).unwrap_or_default();
+            let signature = BASE64URL.encode(self.key.sign(format!("{}.{}", protected, payload).as_bytes())?);
+
+            let resp = self
+                .http
+                .post(url)
+                .header(header::CONTENT_TYPE, JOSE_CONTENT_TYPE)
+                .body(json!({ "protected": protected, "payload": payload, "signature": signature }).to_string())
+                .send()
+                .await?;
+            self.nonce = replay_nonce(&resp);
+
+            if resp.status().is_success() {
+                return Ok(resp);
+            }
+            let status = resp.status();
+            let problem: Value = resp.json().await.unwrap_or(Value::Null);
+            if !retried && status == StatusCode::BAD_REQUEST && problem["type"] == "urn:ietf:params:acme:error:badNonce" {
+                retried = true;
+                continue;
+            }
+            bail!("ACME request to '{}' failed ({}): {}", url, status, describe_problem(&problem));
+        }
+    }
+
+    async fn post_json<T: DeserializeOwned>(&mut self, url: &str, payload: Option<&Value>) -> anyhow::Result<(T, Option<String>)> {
+        let resp = self.post(url, payload).await?;
+        let location = resp.headers().get(header::LOCATION).and_then(|v| v.to_str().ok()).map(str::to_string);
+        Ok((resp.json().await?, location))
+    }
+
+    /// アカウントを登録 (既存なら取得) してアカウント URL を保持する
+    pub async fn ensure_account(&mut self) -> anyhow::Result<String> {
+        if let Some(kid) = &self.kid {
+            return Ok(kid.clone());
+        }
+        let url = self.directory.new_account.clone();
+        let (_, location) = self.post_json::<Value>(&url, Some(&json!({ "termsOfServiceAgreed": true }))).await?;
+        let kid = location.ok_or_else(|| anyhow!("ACME server did not return an account URL"))?;
+        self.kid = Some(kid.clone());
+        Ok(kid)
+    }
+
+    /// DNS 識別子 1 つの注文を作成し、注文 URL と内容を返す
+    pub async fn new_order(&mut self, fqdn: &str) -> anyhow::Result<(String, Order)> {
+        let url = self.directory.new_order.clone();
+        let payload = json!({ "identifiers": [{ "type": "dns", "value": fqdn }] });
+        let (order, location) = self.post_json::<Order>(&url, Some(&payload)).await?;
+        Ok((location.ok_or_else(|| anyhow!("ACME server did not return an order URL"))?, order))
+    }
+
+    pub async fn authorization(&mut self, url: &str) -> anyhow::Result<Authorization> {
+        Ok(self.post_json(url, None).await?.0)
+    }
+
+    /// チャレンジの検証を要求する
+    pub async fn respond_challenge(&mut self, url: &str) -> anyhow::Result<()> {
+        self.post(url, Some(&json!({}))).await?;
+        Ok(())
+    }
+
+    /// 認可が valid / invalid になるまで待つ
+    pub async fn poll_authorization(&mut self, url: &str) -> anyhow::Result<Authorization> {
+        for _ in 0..POLL_ATTEMPTS {
+            let authz = self.authorization(url).await?;
+            match authz.status.as_str() {
+                "pending" => tokio::time::sleep(POLL_INTERVAL).await,
+                "valid" => return Ok(authz),
+                status => {
+                    let reason = authz.challenges.iter().find_map(|c| c.error.as_ref()).map(describe_problem).unwrap_or_default();
+                    bail!("Authorization for '{}' is {}: {}", authz.identifier.value, status, reason);
+                }
+            }
+        }
+        bail!("Timed out waiting for authorization '{}'", url)
+    }
+
+    /// CSR (DER) を送って注文を確定し、証明書が発行されるまで待って PEM チェーンを返す
+    pub async fn finalize(&mut self, order_url: &str, order: &Order, csr_der: &[u8]) -> anyhow::Result<String> {
+        self.post(&order.finalize, Some(&json!({ "csr": BASE64URL.encode(csr_der) }))).await?;
+        for _ in 0..POLL_ATTEMPTS {
+            let (order, _) = self.post_json::<Order>(order_url, None).await?;
+            match (order.status.as_str(), &order.certificate) {
+                ("valid", Some(cert_url)) => {
+                    let resp = self.post(cert_url, None).await?;
+                    return Ok(resp.text().await?);
+                }
+                ("invalid", _) => bail!("Order is invalid: {}", order.error.as_ref().map(describe_problem).unwrap_or_default()),
+                _ => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+        bail!("Timed out waiting for order '{}' to be issued", order_url)
+    }
+}
+
+fn replay_nonce(resp: &Response) -> Option<String> {
+    resp.headers().get("Replay-Nonce").and_then(|v| v.to_str().ok()).map(str::to_string)
+}
+
+fn describe_problem(problem: &Value) -> String {
+    match (problem["type"].as_str(), problem["detail"].as_str()) {
+        (Some(t), Some(d)) => format!("{} ({})", d, t),
+        (None, Some(d)) => d.to_string(),
+        (Some(t), None) => t.to_string(),
+        (None, None) => problem.to_string(),
+    }
+}
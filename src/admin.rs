@@ -0,0 +1,133 @@
+use crate::acme::{AcmeAccount, ACME_ACCOUNT_PREFIX};
+use crate::db::{AppState, REALM_PREFIX};
+use crate::error::ApiError;
+use crate::host_index::{self, RebuildReport};
+use crate::hub::Hub;
+use crate::keyring::Keyring;
+use crate::realm::Realm;
+use crate::secret::{self, SecretFields};
+use crate::urn::{self, MigrationReport};
+use crate::virtual_host::VirtualHost;
+use crate::zone::Zone;
+use axum::{
+    extract::{FromRequestParts, State},
+    http::{header::AUTHORIZATION, request::Parts},
+    routing::post,
+    Json, Router,
+};
+use etcd_client::{Compare, CompareOp, GetOptions, Txn, TxnOp};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+/// 管理用エンドポイントの呼び出し許可
+///
+/// `ADMIN_TOKEN` に設定したトークンを `Authorization: Bearer` ヘッダーで提示する必要がある。
+pub struct AdminAuth;
+
+impl FromRequestParts<AppState> for AdminAuth {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let presented = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        match (&state.admin_token, presented) {
+            (Some(expected), Some(token)) if secret::token_matches(expected, token) => Ok(AdminAuth),
+            (None, _) => Err(ApiError::Forbidden("Admin endpoints are disabled; set ADMIN_TOKEN to enable them.".to_string())),
+            _ => Err(ApiError::Forbidden("Admin endpoints require a valid admin token.".to_string())),
+        }
+    }
+}
+
+/// 管理用エンドポイントをまとめたルーターを返す
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/reencrypt-secrets", post(reencrypt_secrets))
+        .route("/rebuild-host-index", post(rebuild_host_index))
+        .route("/migrate-urns", post(migrate_urns))
+}
+
+/// POST /admin/reencrypt-secrets
+///
+/// 保存済みの秘密情報を現在のマスターキーで暗号化し直す。
+/// 平文のまま保存されている値や古いバージョンのキーで暗号化された値が対象となる。
+async fn reencrypt_secrets(_: AdminAuth, State(state): State<AppState>) -> Result<Json<Value>, ApiError> {
+    let Some(current) = state.keyring.current_version() else {
+        return Err(ApiError::BadRequest("No master key is configured.".to_string()));
+    };
+
+    let mut client = state.etcd_client.clone();
+    let resp = client.get(REALM_PREFIX, Some(GetOptions::new().with_prefix())).await?;
+    let accounts = client.get(ACME_ACCOUNT_PREFIX, Some(GetOptions::new().with_prefix())).await?;
+
+    let (mut reencrypted, mut unchanged, mut conflicts) = (0, 0, 0);
+    for kv in resp.kvs().iter().chain(accounts.kvs()) {
+        let key = kv.key_str()?;
+        let value = if key.starts_with(ACME_ACCOUNT_PREFIX) {
+            reseal::<AcmeAccount>(&state, kv.value(), current)?
+        } else {
+            let segments: Vec<&str> = key.trim_start_matches(REALM_PREFIX).split('/').collect();
+            match segments.as_slice() {
+                [_] => reseal::<Realm>(&state, kv.value(), current)?,
+                [_, "zones", _] => reseal::<Zone>(&state, kv.value(), current)?,
+                [_, "hubs", _] => reseal::<Hub>(&state, kv.value(), current)?,
+                [_, "virtual-hosts", _] => reseal::<VirtualHost>(&state, kv.value(), current)?,
+                _ => continue,
+            }
+        };
+        let Some(value) = value else {
+            unchanged += 1;
+            continue;
+        };
+
+        // 読み取り後に更新されたドキュメントは上書きしない
+        let txn = Txn::new()
+            .when([Compare::mod_revision(key, CompareOp::Equal, kv.mod_revision())])
+            .and_then([TxnOp::put(key, value, None)]);
+        if client.txn(txn).await?.succeeded() {
+            reencrypted += 1;
+        } else {
+            conflicts += 1;
+        }
+    }
+
+    Ok(Json(json!({
+        "keyVersion": current,
+        "reencrypted": reencrypted,
+        "unchanged": unchanged,
+        "conflicts": conflicts,
+    })))
+}
+
+/// POST /admin/rebuild-host-index
+///
+/// 保存されているリソースからホスト名の索引を作り直し、同じ名前を主張するリソースの一覧を返す。
+async fn rebuild_host_index(_: AdminAuth, State(state): State<AppState>) -> Result<Json<RebuildReport>, ApiError> {
+    Ok(Json(host_index::rebuild(&state).await?))
+}
+
+/// POST /admin/migrate-urns
+///
+/// 名前だけで保存されている参照フィールドを URN に変換する。
+async fn migrate_urns(_: AdminAuth, State(state): State<AppState>) -> Result<Json<MigrationReport>, ApiError> {
+    Ok(Json(urn::migrate(&state).await?))
+}
+
+/// 現在のキーで暗号化されていない秘密情報があれば、暗号化し直した値を返す
+fn reseal<T>(state: &AppState, value: &[u8], current: u32) -> Result<Option<Vec<u8>>, ApiError>
+where
+    T: SecretFields + Serialize + DeserializeOwned + Clone,
+{
+    let mut stored: T = serde_json::from_slice(value)?;
+    let up_to_date = stored
+        .secrets_mut()
+        .iter()
+        .all(|secret| Keyring::version_of(secret.expose()) == Some(current));
+    if up_to_date {
+        return Ok(None);
+    }
+    let doc: T = state.keyring.decode(value)?;
+    Ok(Some(state.keyring.encode(&doc)?))
+}
@@ -0,0 +1,198 @@
+//! Realm ごとに保存する小さなファイル (`Respond` アクションの応答本文など)
+//!
+//! 本文はリクエストのボディをそのまま保存し、`Content-Type` ヘッダーを合わせて記録する。
+use crate::db::AppState;
+use crate::error::ApiError;
+use crate::host_index::{self, IndexedWrite};
+use crate::routing_chain::{self, RoutingChain};
+use crate::routing_chain_version::{self, ChainVersion};
+use crate::utils::{decode_children, get_from_etcd, parse_documents};
+use crate::validation::{BlobName, RealmName};
+use crate::virtual_host::{self, VirtualHost};
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, HeaderMap},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+
+/// 保存できる本文の上限 (バイト)
+pub const MAX_BLOB_SIZE: usize = 512 * 1024;
+
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// etcd に保存する形式
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredBlob {
+    content_type: String,
+    // Base64 で符号化した本文
+    data: String,
+    // 本文のバイト数 (これを記録する前に保存された Blob にはない)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<usize>,
+}
+
+/// 一覧や書き込みの結果で返す情報
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobInfo {
+    pub name: String,
+    pub content_type: String,
+    pub size: usize,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_blobs))
+        .route("/{blob_name}", get(get_blob).put(put_blob).delete(delete_blob))
+}
+
+pub fn blob_key(realm: &RealmName, name: &BlobName) -> String {
+    format!("/realms/{}/blobs/{}", realm, name)
+}
+
+fn blob_prefix(realm: &RealmName) -> String {
+    format!("/realms/{}/blobs/", realm)
+}
+
+fn decode(value: &[u8]) -> Result<(String, Vec<u8>), ApiError> {
+    let stored: StoredBlob = serde_json::from_slice(value)?;
+    let data = BASE64.decode(&stored.data).map_err(|e| ApiError::Internal(e.into()))?;
+    Ok((stored.content_type, data))
+}
+
+fn info(key: &str, content_type: String, size: usize) -> BlobInfo {
+    let name = key.rsplit('/').next().unwrap_or_default().to_string();
+    BlobInfo { name, content_type, size }
+}
+
+/// Blob が存在するかを返す
+pub async fn exists(state: &AppState, realm: &RealmName, name: &BlobName) -> Result<bool, ApiError> {
+    Ok(!get_from_etcd(state, &blob_key(realm, name)).await?.kvs().is_empty())
+}
+
+async fn list_blobs(State(state): State<AppState>, Path(realm): Path<String>) -> Result<Json<Vec<BlobInfo>>, ApiError> {
+    let mut client = state.etcd_client.clone();
+    let prefix = blob_prefix(&RealmName::parse("realm", &realm)?);
+    let resp = client.get(prefix, Some(etcd_client::GetOptions::new().with_prefix())).await?;
+    let mut blobs = Vec::new();
+    for kv in resp.kvs() {
+        let stored: StoredBlob = serde_json::from_slice(kv.value())?;
+        let size = match stored.size {
+            Some(size) => size,
+            None => decode(kv.value())?.1.len(),
+        };
+        blobs.push(info(kv.key_str()?, stored.content_type, size));
+    }
+    Ok(Json(blobs))
+}
+
+async fn get_blob(
+    State(state): State<AppState>,
+    Path((realm, name)): Path<(String, String)>,
+) -> Result<impl IntoResponse, ApiError> {
+    let key = blob_key(&RealmName::parse("realm", &realm)?, &BlobName::parse("blob", &name)?);
+    match get_from_etcd(&state, &key).await?.kvs().first() {
+        Some(kv) => {
+            let (content_type, data) = decode(kv.value())?;
+            Ok(([(header::CONTENT_TYPE, content_type)], data))
+        }
+        None => Err(ApiError::NotFound(format!("Blob '{}' not found in realm '{}'", name, realm))),
+    }
+}
+
+/// PUT /realms/{realm}/blobs/{blob_name}
+///
+/// リクエストのボディを保存する (同じ名前の Blob は置き換える)。
+async fn put_blob(
+    State(state): State<AppState>,
+    Path((realm, name)): Path<(String, String)>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<BlobInfo>, ApiError> {
+    let key = blob_key(&RealmName::parse("realm", &realm)?, &BlobName::parse("blob", &name)?);
+    if body.len() > MAX_BLOB_SIZE {
+        return Err(ApiError::BadRequest(format!("Blob '{}' is larger than {} bytes", name, MAX_BLOB_SIZE)));
+    }
+    let content_type = match headers.get(header::CONTENT_TYPE) {
+        Some(value) => value
+            .to_str()
+            .map_err(|_| ApiError::BadRequest("Content-Type must be visible ASCII".to_string()))?
+            .to_string(),
+        None => DEFAULT_CONTENT_TYPE.to_string(),
+    };
+    let stored = StoredBlob { content_type: content_type.clone(), data: BASE64.encode(&body), size: Some(body.len()) };
+    let mut client = state.etcd_client.clone();
+    client.put(key.clone(), serde_json::to_vec(&stored)?, None).await?;
+    Ok(Json(info(&key, content_type, body.len())))
+}
+
+/// プレフィックス配下を読み、削除までに変わっていないことを `write` の条件に加える
+async fn scan(state: &AppState, prefix: &str, write: &mut IndexedWrite) -> Result<etcd_client::GetResponse, ApiError> {
+    let mut client = state.etcd_client.clone();
+    let resp = client.get(prefix, Some(etcd_client::GetOptions::new().with_prefix())).await?;
+    if let Some(header) = resp.header() {
+        write.require_prefix_unchanged(prefix, header.revision());
+    }
+    Ok(resp)
+}
+
+/// Blob を本文に使っているリソースを返す
+///
+/// RoutingChain (保存されている各版を含む) の `Respond` と、VirtualHost のメンテナンス応答を調べる。
+/// 調べたドキュメントが削除までに変わっていないことを `write` の条件に加える。
+async fn referrers(state: &AppState, realm: &RealmName, name: &str, write: &mut IndexedWrite) -> Result<Vec<String>, ApiError> {
+    let mut found = Vec::new();
+    let resp = scan(state, &routing_chain::routing_chain_prefix(realm), write).await?;
+    for chain in parse_documents::<RoutingChain>(resp.kvs()) {
+        if routing_chain::uses_blob(&chain, name) {
+            found.push(format!("routing chain '{}'", chain.name));
+        }
+    }
+    let resp = scan(state, &routing_chain_version::realm_versions_prefix(realm), write).await?;
+    for version in parse_documents::<ChainVersion>(resp.kvs()) {
+        if routing_chain::uses_blob(&version.chain, name) {
+            found.push(format!("routing chain '{}' version {}", version.chain.name, version.version));
+        }
+    }
+    let prefix = virtual_host::virtual_host_prefix(realm);
+    let resp = scan(state, &prefix, write).await?;
+    let hosts: Vec<VirtualHost> = decode_children(state, &prefix, resp.kvs());
+    for host in hosts {
+        if host.maintenance.is_some_and(|maintenance| routing_chain::is_blob(&maintenance.response, name)) {
+            found.push(format!("virtual host '{}'", host.name));
+        }
+    }
+    Ok(found)
+}
+
+/// DELETE /realms/{realm}/blobs/{blob_name}
+///
+/// RoutingChain や VirtualHost が本文に使っている Blob は削除できない (409)。
+async fn delete_blob(
+    State(state): State<AppState>,
+    Path((realm, name)): Path<(String, String)>,
+) -> Result<Json<BlobInfo>, ApiError> {
+    let realm_name = RealmName::parse("realm", &realm)?;
+    let key = blob_key(&realm_name, &BlobName::parse("blob", &name)?);
+    let mut write = IndexedWrite::new();
+    let used_by = referrers(&state, &realm_name, &name, &mut write).await?;
+    if !used_by.is_empty() {
+        return Err(ApiError::Conflict(format!("Blob '{}' is used by {}", name, used_by.join(", "))));
+    }
+    // 調べた後に Blob を使い始めたリソースがあれば 409 になる
+    write.delete(&key);
+    let resp = write.commit(&state).await?;
+    match host_index::deleted_value(&resp) {
+        Some(value) => {
+            let (content_type, data) = decode(&value)?;
+            Ok(Json(info(&key, content_type, data.len())))
+        }
+        None => Err(ApiError::NotFound(format!("Blob '{}' not found in realm '{}'", name, realm))),
+    }
+}
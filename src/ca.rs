@@ -0,0 +1,126 @@
+use crate::db::AppState;
+use crate::error::ApiError;
+use crate::pki;
+use crate::realm;
+use crate::secret::Secret;
+use crate::validation;
+use rcgen::{
+    BasicConstraints, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa, Issuer, KeyPair,
+    KeyUsagePurpose, SanType,
+};
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+
+/// Realm CA 証明書の有効期間
+const CA_VALIDITY_DAYS: i64 = 3650;
+/// 発行するサーバー証明書の既定の有効期間
+pub const DEFAULT_SERVER_VALIDITY_DAYS: i64 = 90;
+/// `validityDays` / `renewWithinDays` に指定できる最大の日数
+const MAX_SERVER_VALIDITY_DAYS: i64 = 825;
+
+/// 証明書発行リクエスト (本文は省略可能)
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct IssueCertificateRequest {
+    // 発行する証明書の有効日数 (既定 90 日、最大 825 日)
+    #[serde(default)]
+    pub validity_days: Option<i64>,
+    // 指定した場合、現在の証明書の残り日数がこれより多ければ再発行しない (最大 825 日)
+    #[serde(default)]
+    pub renew_within_days: Option<i64>,
+}
+
+fn internal(err: rcgen::Error) -> ApiError {
+    ApiError::Internal(err.into())
+}
+
+/// Realm 用の CA 鍵ペアと自己署名 CA 証明書を生成する (証明書 PEM と秘密鍵 PEM を返す)
+pub fn generate_ca(realm: &str) -> Result<(String, Secret), ApiError> {
+    let key = KeyPair::generate().map_err(internal)?;
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::OrganizationName, "chip-in");
+    params.distinguished_name.push(DnType::CommonName, format!("{} Realm CA", realm));
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign, KeyUsagePurpose::DigitalSignature];
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::hours(1);
+    params.not_after = now + Duration::days(CA_VALIDITY_DAYS);
+
+    let cert = params.self_signed(&key).map_err(internal)?;
+    Ok((cert.pem(), Secret::new(key.serialize_pem())))
+}
+
+/// Realm CA でホスト名用のサーバー証明書を発行する (証明書 PEM と秘密鍵 PEM を返す)
+pub fn issue_server_certificate(ca_cert: &str, ca_key: &Secret, hostname: &str, validity_days: i64) -> Result<(String, Secret), ApiError> {
+    let (pkcs8, _) = pki::parse_private_key("caKey", ca_key)?;
+    let ca_key = KeyPair::try_from(pkcs8)
+        .map_err(|e| ApiError::Unprocessable(format!("caKey: signing key check failed: {}", e)))?;
+    let issuer = Issuer::from_ca_cert_pem(ca_cert, ca_key)
+        .map_err(|e| ApiError::Unprocessable(format!("cacert: CA check failed: {}", e)))?;
+
+    let key = KeyPair::generate().map_err(internal)?;
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, hostname);
+    params.subject_alt_names = vec![SanType::DnsName(
+        hostname
+            .try_into()
+            .map_err(|e| ApiError::Unprocessable(format!("fqdn: SAN check failed: '{}' is not a valid DNS name: {}", hostname, e)))?,
+    )];
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature, KeyUsagePurpose::KeyEncipherment];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    params.use_authority_key_identifier_extension = true;
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::hours(1);
+    params.not_after = now
+        .checked_add(Duration::days(validity_days))
+        .ok_or_else(|| validation::invalid("validityDays", format!("must be at most {}", MAX_SERVER_VALIDITY_DAYS)))?;
+
+    let cert = params.signed_by(&key, &issuer).map_err(internal)?;
+    Ok((cert.pem(), Secret::new(key.serialize_pem())))
+}
+
+/// 現在の証明書が `within_days` 日以内に失効する (または読み込めない) 場合に `true`
+pub fn needs_renewal(cert_pem: &str, within_days: i64) -> bool {
+    let Ok(chain) = pki::parse_certificates("certificate", cert_pem) else {
+        return true;
+    };
+    let not_after = chain[0].parsed().validity().not_after.to_datetime();
+    match OffsetDateTime::now_utc().checked_add(Duration::days(within_days)) {
+        Some(limit) => not_after <= limit,
+        None => true,
+    }
+}
+
+/// Realm CA でホスト名用の証明書を発行する。再発行が不要なら `None` を返す。
+pub async fn issue_for_host(
+    state: &AppState,
+    realm_name: &str,
+    hostname: &str,
+    current_cert: &str,
+    request: &IssueCertificateRequest,
+) -> Result<Option<(String, Secret)>, ApiError> {
+    let validity_days = request.validity_days.unwrap_or(DEFAULT_SERVER_VALIDITY_DAYS);
+    if !(1..=MAX_SERVER_VALIDITY_DAYS).contains(&validity_days) {
+        return Err(validation::invalid("validityDays", format!("must be between 1 and {}", MAX_SERVER_VALIDITY_DAYS)));
+    }
+    if let Some(within) = request.renew_within_days {
+        if !(0..=MAX_SERVER_VALIDITY_DAYS).contains(&within) {
+            return Err(validation::invalid("renewWithinDays", format!("must be between 0 and {}", MAX_SERVER_VALIDITY_DAYS)));
+        }
+        if !needs_renewal(current_cert, within) {
+            return Ok(None);
+        }
+    }
+
+    let realm = realm::load_realm(state, realm_name).await?;
+    let Some(ca_key) = &realm.ca_key else {
+        return Err(ApiError::Unprocessable(format!(
+            "caKey: CA check failed: realm '{}' has no CA key to issue certificates with",
+            realm_name
+        )));
+    };
+    issue_server_certificate(&realm.cacert, ca_key, hostname, validity_days).map(Some)
+}
@@ -0,0 +1,202 @@
+use crate::db::{AppState, REALM_PREFIX};
+use crate::error::ApiError;
+use crate::hub::Hub;
+use crate::pki;
+use crate::realm::Realm;
+use crate::validation::RealmName;
+use crate::virtual_host::VirtualHost;
+use axum::{
+    extract::{Path, Query, State},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// 証明書インベントリの 1 エントリ
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificateEntry {
+    pub owner: CertificateOwner,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sans: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days_remaining: Option<i64>,
+    // 保存済みの値が証明書として読み込めなかった場合の理由
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip)]
+    expires_at: Option<OffsetDateTime>,
+}
+
+/// 証明書を保持しているリソース
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificateOwner {
+    pub kind: String,
+    pub realm: String,
+    pub name: String,
+    pub field: String,
+    // チェーン内の位置 (0 がリーフ)
+    pub index: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InventoryParams {
+    expiring_within: Option<String>,
+}
+
+/// 全 Realm の証明書インベントリ (GET /certificates)
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/", get(list_all_certificates))
+}
+
+/// Realm 単位の証明書インベントリ (GET /realms/{realm}/certificates)
+pub fn realm_routes() -> Router<AppState> {
+    Router::new().route("/", get(list_realm_certificates))
+}
+
+/// `expiringWithin` に指定できる最長の期間 (10 年)
+const MAX_EXPIRING_WITHIN_HOURS: i64 = 3650 * 24;
+
+/// `30d` / `12h` / `30` (日) 形式の期間を時間数に変換する
+fn parse_expiring_within(value: &str) -> Result<i64, ApiError> {
+    let invalid = || {
+        ApiError::BadRequest(format!("Invalid expiringWithin '{}'; expected e.g. '30d' or '12h', up to 3650 days", value))
+    };
+    let (number, unit_hours) = match value.strip_suffix('d') {
+        Some(n) => (n, 24),
+        None => match value.strip_suffix('h') {
+            Some(n) => (n, 1),
+            None => (value, 24),
+        },
+    };
+    let number: i64 = number.parse().map_err(|_| invalid())?;
+    match number.checked_mul(unit_hours) {
+        Some(hours) if (0..=MAX_EXPIRING_WITHIN_HOURS).contains(&hours) => Ok(hours),
+        _ => Err(invalid()),
+    }
+}
+
+/// GET /certificates
+async fn list_all_certificates(
+    State(state): State<AppState>,
+    Query(params): Query<InventoryParams>,
+) -> Result<Json<Vec<CertificateEntry>>, ApiError> {
+    Ok(Json(inventory(&state, REALM_PREFIX.to_string(), params).await?))
+}
+
+/// GET /realms/{realm}/certificates
+async fn list_realm_certificates(
+    State(state): State<AppState>,
+    Path(realm): Path<String>,
+    Query(params): Query<InventoryParams>,
+) -> Result<Json<Vec<CertificateEntry>>, ApiError> {
+    // `/realms/{realm}` 自体と配下のリソースだけを対象にする
+    let realm = RealmName::parse("realm", &realm)?;
+    let entries = inventory(&state, format!("{}{}", REALM_PREFIX, realm), params).await?;
+    Ok(Json(entries.into_iter().filter(|e| e.owner.realm == *realm).collect()))
+}
+
+async fn inventory(state: &AppState, prefix: String, params: InventoryParams) -> Result<Vec<CertificateEntry>, ApiError> {
+    let within_hours = params.expiring_within.as_deref().map(parse_expiring_within).transpose()?;
+
+    let mut client = state.etcd_client.clone();
+    let resp = client.get(prefix, Some(etcd_client::GetOptions::new().with_prefix())).await?;
+
+    let now = OffsetDateTime::now_utc();
+    let mut entries = Vec::new();
+    for kv in resp.kvs() {
+        let key = kv.key_str()?;
+        let segments: Vec<&str> = key.trim_start_matches(REALM_PREFIX).split('/').collect();
+        match segments.as_slice() {
+            [realm] => {
+                if let Ok(doc) = serde_json::from_slice::<Realm>(kv.value()) {
+                    let owner = owner("Realm", realm, &doc.name, "cacert");
+                    entries.extend(describe(owner, &doc.cacert, now));
+                }
+            }
+            [realm, "hubs", _] => {
+                if let Ok(doc) = serde_json::from_slice::<Hub>(kv.value()) {
+                    let owner = owner("Hub", realm, &doc.name, "serverCert");
+                    entries.extend(describe(owner, &doc.server_cert, now));
+                }
+            }
+            [realm, "virtual-hosts", _] => {
+                if let Ok(doc) = serde_json::from_slice::<VirtualHost>(kv.value()) {
+                    let owner = owner("VirtualHost", realm, &doc.name, "certificate");
+                    entries.extend(describe(owner, &doc.certificate.join("\n"), now));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(hours) = within_hours {
+        let limit = now
+            .checked_add(time::Duration::hours(hours))
+            .ok_or_else(|| ApiError::BadRequest(format!("expiringWithin of {} hours is out of range", hours)))?;
+        entries.retain(|e| e.expires_at.is_some_and(|t| t <= limit));
+    }
+    entries.sort_by_key(|e| e.expires_at);
+    Ok(entries)
+}
+
+fn owner(kind: &str, realm: &str, name: &str, field: &str) -> CertificateOwner {
+    CertificateOwner { kind: kind.to_string(), realm: realm.to_string(), name: name.to_string(), field: field.to_string(), index: 0 }
+}
+
+/// フィールドに保存された PEM をチェーン内の証明書ごとのエントリに変換する
+fn describe(owner: CertificateOwner, pem_text: &str, now: OffsetDateTime) -> Vec<CertificateEntry> {
+    if pem_text.trim().is_empty() {
+        return Vec::new();
+    }
+    let chain = match pki::parse_certificates(&owner.field, pem_text) {
+        Ok(chain) => chain,
+        Err(ApiError::Unprocessable(msg)) => return vec![error_entry(owner, msg)],
+        Err(_) => return vec![error_entry(owner, "unreadable certificate".to_string())],
+    };
+    chain
+        .iter()
+        .enumerate()
+        .map(|(index, cert)| {
+            let cert = cert.parsed();
+            let validity = cert.validity();
+            let not_after = validity.not_after.to_datetime();
+            CertificateEntry {
+                owner: CertificateOwner { index, ..owner.clone() },
+                subject: Some(cert.subject().to_string()),
+                sans: pki::dns_names(&cert),
+                issuer: Some(cert.issuer().to_string()),
+                not_before: validity.not_before.to_datetime().format(&Rfc3339).ok(),
+                not_after: not_after.format(&Rfc3339).ok(),
+                days_remaining: Some((not_after - now).whole_days()),
+                error: None,
+                expires_at: Some(not_after),
+            }
+        })
+        .collect()
+}
+
+fn error_entry(owner: CertificateOwner, error: String) -> CertificateEntry {
+    CertificateEntry {
+        owner,
+        subject: None,
+        sans: Vec::new(),
+        issuer: None,
+        not_before: None,
+        not_after: None,
+        days_remaining: None,
+        error: Some(error),
+        expires_at: None,
+    }
+}
@@ -1,8 +1,22 @@
+use crate::acme::AcmeSettings;
+use crate::dns::DnsSettings;
+use crate::keyring::Keyring;
 use etcd_client::Client;
+use std::sync::Arc;
 
 pub const REALM_PREFIX: &str = "/realms/";
 
 #[derive(Clone)]
 pub struct AppState {
     pub etcd_client: Client,
+    /// `?reveal=secrets` で秘密情報を開示するためのトークン (未設定なら開示不可)
+    pub reveal_secrets_token: Option<String>,
+    /// `/admin` の管理用エンドポイントを呼び出すためのトークン (未設定なら呼び出し不可)
+    pub admin_token: Option<String>,
+    /// 秘密情報の暗号化に使うマスターキー
+    pub keyring: Arc<Keyring>,
+    /// ACME による証明書取得の設定
+    pub acme: Arc<AcmeSettings>,
+    /// DNS プロバイダーへの同期の設定
+    pub dns: Arc<DnsSettings>,
 }
\ No newline at end of file
@@ -0,0 +1,560 @@
+//! Zone の DNS レコードの算出と DNS プロバイダーへの同期
+//!
+//! 各 Subdomain の FQDN を、それを配信する Hub に向けるレコードを算出する。
+//! 配信する Hub は、Subdomain を参照する VirtualHost の Realm (無ければ `destinationRealm`) の Hub とする。
+//! Subdomain に `records` で明示したレコードがあればそれも加え、A / AAAA / CNAME を明示した場合は Hub より優先する。
+//! 最後に反映したレコードは `/dns/state/{realm}/{zone}` に保存し、次回の差分計算に使う。
+//! ACME DNS-01 のチャレンジ中は `/dns/acme-challenges/{realm}/{zone}/{fqdn}` の TXT レコードも加える。
+use crate::db::{AppState, REALM_PREFIX};
+use crate::dns_provider::{self, DnsProvider};
+use crate::error::ApiError;
+use crate::hub::Hub;
+use crate::idn;
+use crate::subdomain::{self, Subdomain};
+use crate::urn::Urn;
+use crate::utils::get_from_etcd;
+use crate::virtual_host::VirtualHost;
+use crate::zone::{self, Zone};
+use crate::zonefile;
+use hickory_proto::rr::{Name, RData};
+use hickory_proto::serialize::txt::RDataParser;
+use axum::{
+    extract::{Path, State},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::{info, warn};
+
+/// レコードの既定の TTL (秒)
+const DEFAULT_TTL: u32 = 300;
+/// ACME DNS-01 のチャレンジの TXT レコードの TTL (秒)
+const CHALLENGE_TTL: u32 = 60;
+const CHALLENGE_PREFIX: &str = "/dns/acme-challenges/";
+
+/// DNS の設定 (環境変数から読み込む)
+///
+/// - `DNS_DEFAULT_TTL`: 算出したレコードの TTL (既定 300 秒)
+/// - `DNS_SOA_MNAME` / `DNS_SOA_RNAME`: ゾーンファイルの SOA (既定 `ns1.{zone}.` / `hostmaster.{zone}.`)
+/// - `DNS_SOA_REFRESH` / `DNS_SOA_RETRY` / `DNS_SOA_EXPIRE` / `DNS_SOA_MINIMUM`: SOA のタイマー (秒)
+/// - `DNS_NAMESERVERS`: ゾーンファイルの NS (カンマ区切り、既定は SOA の MNAME)
+pub struct DnsSettings {
+    pub default_ttl: u32,
+    pub soa: SoaDefaults,
+    // 同期処理を直列化する
+    lock: tokio::sync::Mutex<()>,
+}
+
+/// ゾーンファイルの SOA / NS の既定値 (`{zone}` は Zone 名に置き換える)
+pub struct SoaDefaults {
+    pub mname: String,
+    pub rname: String,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+    pub nameservers: Vec<String>,
+}
+
+impl DnsSettings {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let default_ttl = env_number("DNS_DEFAULT_TTL", DEFAULT_TTL)?;
+        let soa = SoaDefaults {
+            mname: env::var("DNS_SOA_MNAME").unwrap_or_else(|_| "ns1.{zone}.".to_string()),
+            rname: env::var("DNS_SOA_RNAME").unwrap_or_else(|_| "hostmaster.{zone}.".to_string()),
+            refresh: env_number("DNS_SOA_REFRESH", 3600)?,
+            retry: env_number("DNS_SOA_RETRY", 600)?,
+            expire: env_number("DNS_SOA_EXPIRE", 1_209_600)?,
+            minimum: env_number("DNS_SOA_MINIMUM", default_ttl)?,
+            nameservers: env::var("DNS_NAMESERVERS")
+                .map(|v| v.split(',').map(str::trim).filter(|ns| !ns.is_empty()).map(str::to_string).collect())
+                .unwrap_or_default(),
+        };
+        Ok(DnsSettings { default_ttl, soa, lock: tokio::sync::Mutex::new(()) })
+    }
+}
+
+fn env_number(name: &str, default: u32) -> anyhow::Result<u32> {
+    match env::var(name) {
+        Ok(v) => v.parse().map_err(|_| anyhow::anyhow!("{} must be a number of seconds", name)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// レコードの種類
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum RecordType {
+    A,
+    AAAA,
+    CNAME,
+    TXT,
+    CAA,
+    MX,
+}
+
+impl RecordType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecordType::A => "A",
+            RecordType::AAAA => "AAAA",
+            RecordType::CNAME => "CNAME",
+            RecordType::TXT => "TXT",
+            RecordType::CAA => "CAA",
+            RecordType::MX => "MX",
+        }
+    }
+
+    /// 名前の解決先 (アドレスまたは別名) を表すレコードか
+    pub fn is_address(&self) -> bool {
+        matches!(self, RecordType::A | RecordType::AAAA | RecordType::CNAME)
+    }
+}
+
+/// TXT の文字列 1 つの最大長 (RFC 1035 3.3)
+const TXT_CHUNK_LEN: usize = 255;
+
+/// 入力されたレコードのデータを種類ごとに検証し、プレゼンテーション形式に正規化する
+///
+/// - A / AAAA: IP アドレス
+/// - CNAME: ドメイン名 (末尾のドットは省略可)
+/// - MX: `{preference} {exchange}`
+/// - TXT: 引用符なしの文字列 (255 バイトごとに分割する)
+/// - CAA: `{flags} {tag} {value}` (tag は issue / issuewild / iodef)
+pub fn normalize_data(record_type: RecordType, data: &str) -> Result<String, String> {
+    let data = data.trim();
+    let normalized = match record_type {
+        RecordType::A => data.parse::<Ipv4Addr>().map_err(|_| format!("'{}' is not an IPv4 address", data))?.to_string(),
+        RecordType::AAAA => data.parse::<Ipv6Addr>().map_err(|_| format!("'{}' is not an IPv6 address", data))?.to_string(),
+        RecordType::CNAME => absolute_name(data)?,
+        RecordType::MX => {
+            let Some((preference, exchange)) = data.split_once(char::is_whitespace) else {
+                return Err(format!("MX data must be '<preference> <exchange>', got '{}'", data));
+            };
+            let preference: u16 = preference.parse().map_err(|_| format!("MX preference must be 0-65535, got '{}'", preference))?;
+            format!("{} {}", preference, absolute_name(exchange.trim())?)
+        }
+        RecordType::TXT => {
+            if data.chars().any(|c| !(' '..='~').contains(&c)) {
+                return Err("TXT data must be printable ASCII".to_string());
+            }
+            let escaped: Vec<String> = data
+                .as_bytes()
+                .chunks(TXT_CHUNK_LEN)
+                .map(|chunk| format!("\"{}\"", String::from_utf8_lossy(chunk).replace('\\', "\\\\").replace('"', "\\\"")))
+                .collect();
+            if escaped.is_empty() { "\"\"".to_string() } else { escaped.join(" ") }
+        }
+        RecordType::CAA => {
+            let mut fields = data.splitn(3, char::is_whitespace);
+            let (Some(flags), Some(tag), Some(value)) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(format!("CAA data must be '<flags> <tag> <value>', got '{}'", data));
+            };
+            let flags: u8 = flags.parse().map_err(|_| format!("CAA flags must be 0-255, got '{}'", flags))?;
+            if !matches!(tag, "issue" | "issuewild" | "iodef") {
+                return Err(format!("CAA tag must be one of issue, issuewild, iodef, got '{}'", tag));
+            }
+            let value = value.trim().trim_matches('"');
+            if value.contains('"') || value.chars().any(|c| !(' '..='~').contains(&c)) {
+                return Err("CAA value must be printable ASCII without quotes".to_string());
+            }
+            format!("{} {} \"{}\"", flags, tag, value)
+        }
+    };
+    // DNS UPDATE で送れる形式か確認する
+    let hickory_type: hickory_proto::rr::RecordType = record_type.as_str().parse().map_err(|e| format!("{}", e))?;
+    RData::try_from_str(hickory_type, &normalized).map_err(|e| format!("invalid {} data '{}': {}", record_type.as_str(), data, e))?;
+    Ok(normalized)
+}
+
+/// ドメイン名を検証し、末尾のドット付きで返す
+fn absolute_name(name: &str) -> Result<String, String> {
+    let parsed = Name::from_ascii(name).map_err(|e| format!("'{}' is not a valid domain name: {}", name, e))?;
+    if parsed.is_root() || name.contains(char::is_whitespace) {
+        return Err(format!("'{}' is not a valid domain name", name));
+    }
+    Ok(format!("{}.", name.trim_end_matches('.')))
+}
+
+/// DNS レコード
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct DnsRecord {
+    // 末尾のドットを含まない FQDN
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: RecordType,
+    pub ttl: u32,
+    // プレゼンテーション形式のデータ (ドメイン名は末尾のドット付き)
+    pub data: String,
+}
+
+/// Zone について算出したレコード
+pub struct ZoneRecords {
+    pub records: Vec<DnsRecord>,
+    pub warnings: Vec<String>,
+    // 算出時点の etcd のリビジョン
+    pub revision: i64,
+}
+
+/// 同期の計画 (dry-run の結果)
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DnsPlan {
+    pub zone: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_provider: Option<String>,
+    pub create: Vec<DnsRecord>,
+    pub delete: Vec<DnsRecord>,
+    pub unchanged: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    #[serde(skip)]
+    desired: Vec<DnsRecord>,
+}
+
+impl DnsPlan {
+    pub fn is_empty(&self) -> bool {
+        self.create.is_empty() && self.delete.is_empty()
+    }
+}
+
+/// 最後に反映したレコードと同期の状態
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DnsSyncState {
+    pub records: Vec<DnsRecord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synced_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Zone 単位の DNS エンドポイント (/realms/{realm}/zones/{zone}/dns)
+pub fn zone_routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(get_sync_state))
+        .route("/plan", get(get_plan))
+        .route("/sync", post(sync))
+}
+
+fn state_key(realm: &str, zone: &str) -> String {
+    format!("/dns/state/{}/{}", realm, zone)
+}
+
+async fn load_sync_state(state: &AppState, realm: &str, zone: &str) -> Result<DnsSyncState, ApiError> {
+    match get_from_etcd(state, &state_key(realm, zone)).await?.kvs().first() {
+        Some(kv) => Ok(serde_json::from_slice(kv.value())?),
+        None => Ok(DnsSyncState::default()),
+    }
+}
+
+async fn save_sync_state(state: &AppState, realm: &str, zone: &str, sync_state: &DnsSyncState) -> Result<(), ApiError> {
+    let mut client = state.etcd_client.clone();
+    client.put(state_key(realm, zone), serde_json::to_vec(sync_state)?, None).await?;
+    Ok(())
+}
+
+fn challenge_prefix(realm: &str, zone: &str) -> String {
+    format!("{}{}/{}/", CHALLENGE_PREFIX, realm, zone)
+}
+
+/// ACME DNS-01 の TXT レコード (`_acme-challenge.{fqdn}`) を Zone のレコードに加えて DNS プロバイダーに反映する
+///
+/// Zone が DNS プロバイダーで管理されていなければ何もせず `false` を返す。
+pub async fn publish_challenge(state: &AppState, realm: &str, zone: &Zone, fqdn: &str, value: &str) -> Result<bool, ApiError> {
+    if dns_provider::for_zone(zone)?.is_none() {
+        return Ok(false);
+    }
+    let mut client = state.etcd_client.clone();
+    client.put(format!("{}{}", challenge_prefix(realm, &zone.zone), fqdn), value, None).await?;
+    sync_zone(state, realm, &zone.zone).await?;
+    Ok(true)
+}
+
+/// [`publish_challenge`] で加えた TXT レコードを取り除いて DNS プロバイダーに反映する
+pub async fn withdraw_challenge(state: &AppState, realm: &str, zone: &Zone, fqdn: &str) -> Result<(), ApiError> {
+    let mut client = state.etcd_client.clone();
+    client.delete(format!("{}{}", challenge_prefix(realm, &zone.zone), fqdn), None).await?;
+    sync_zone(state, realm, &zone.zone).await?;
+    Ok(())
+}
+
+/// Zone の削除前に、反映済みのレコードを DNS プロバイダーから削除する
+pub async fn unpublish_zone(state: &AppState, realm: &str, zone: &Zone) -> Result<(), ApiError> {
+    let _guard = state.dns.lock.lock().await;
+    let Some(provider) = dns_provider::for_zone(zone)? else {
+        return Ok(());
+    };
+    let mut sync_state = load_sync_state(state, realm, &zone.zone).await?;
+    if sync_state.records.is_empty() {
+        return Ok(());
+    }
+    let plan = DnsPlan {
+        zone: zone.zone.clone(),
+        dns_provider: zone.dns_provider.clone(),
+        create: Vec::new(),
+        delete: sync_state.records.clone(),
+        unchanged: 0,
+        warnings: Vec::new(),
+        desired: Vec::new(),
+    };
+    if let Err(err) = provider.apply(&zone.zone, &plan).await {
+        return Err(ApiError::BadGateway(format!("Removing the DNS records of zone '{}' failed: {:#}", zone.zone, err)));
+    }
+    sync_state.records.clear();
+    sync_state.synced_at = OffsetDateTime::now_utc().format(&Rfc3339).ok();
+    sync_state.error = None;
+    save_sync_state(state, realm, &zone.zone, &sync_state).await
+}
+
+/// Zone の削除時に、反映済みレコードの記録を削除する
+pub async fn forget_zone(state: &AppState, realm: &str, zone: &str) -> Result<(), ApiError> {
+    let mut client = state.etcd_client.clone();
+    client.delete(state_key(realm, zone), None).await?;
+    client.delete(challenge_prefix(realm, zone), Some(etcd_client::DeleteOptions::new().with_prefix())).await?;
+    client.delete(zonefile::serial_key(realm, zone), None).await?;
+    Ok(())
+}
+
+/// GET /realms/{realm}/zones/{zone}/dns
+async fn get_sync_state(
+    State(state): State<AppState>,
+    Path((realm, zone_name)): Path<(String, String)>,
+) -> Result<Json<DnsSyncState>, ApiError> {
+    let zone = zone::load_zone(&state, &realm, &zone_name).await?;
+    Ok(Json(load_sync_state(&state, &realm, &zone.zone).await?))
+}
+
+/// GET /realms/{realm}/zones/{zone}/dns/plan
+///
+/// DNS サーバーに反映する変更を返す (反映はしない)。
+async fn get_plan(
+    State(state): State<AppState>,
+    Path((realm, zone_name)): Path<(String, String)>,
+) -> Result<Json<DnsPlan>, ApiError> {
+    let zone = zone::load_zone(&state, &realm, &zone_name).await?;
+    Ok(Json(plan(&state, &realm, &zone).await?))
+}
+
+/// POST /realms/{realm}/zones/{zone}/dns/sync
+///
+/// 計画した変更を DNS プロバイダーに反映し、反映した計画を返す。
+async fn sync(
+    State(state): State<AppState>,
+    Path((realm, zone_name)): Path<(String, String)>,
+) -> Result<Json<DnsPlan>, ApiError> {
+    Ok(Json(sync_zone(&state, &realm, &idn::canonical(&zone_name)).await?))
+}
+
+/// Zone の Subdomain ごとに、配信する Hub へ向けるレコードを算出する
+pub async fn zone_records(state: &AppState, realm: &str, zone: &Zone) -> Result<ZoneRecords, ApiError> {
+    let mut client = state.etcd_client.clone();
+    let resp = client.get(REALM_PREFIX, Some(etcd_client::GetOptions::new().with_prefix())).await?;
+
+    let mut subdomains = Vec::new();
+    // Subdomain 名 -> 配信する Realm
+    let mut serving: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut hubs: BTreeMap<String, Vec<Hub>> = BTreeMap::new();
+    for kv in resp.kvs() {
+        let key = kv.key_str()?;
+        let segments: Vec<&str> = key.trim_start_matches(REALM_PREFIX).split('/').collect();
+        match segments.as_slice() {
+            [r, "zones", z, "subdomains", _] if *r == realm && *z == zone.zone => {
+                if let Ok(doc) = serde_json::from_slice::<Subdomain>(kv.value()) {
+                    subdomains.push(doc);
+                }
+            }
+            [host_realm, "virtual-hosts", _] => {
+                let Ok(host) = state.keyring.decode::<VirtualHost>(kv.value()) else {
+                    continue;
+                };
+                if let Some((r, z, name)) = subdomain::parse_reference(&host.subdomain) {
+                    if !host.disabled && *r == *realm && *z == *zone.zone {
+                        serving.entry(name.to_string()).or_default().insert(host_realm.to_string());
+                    }
+                }
+            }
+            [hub_realm, "hubs", _] => {
+                if let Ok(hub) = state.keyring.decode::<Hub>(kv.value()) {
+                    hubs.entry(hub_realm.to_string()).or_default().push(hub);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let ttl = state.dns.default_ttl;
+    let mut records = BTreeSet::new();
+    let mut warnings = Vec::new();
+    for sub in &subdomains {
+        let Some(fqdn) = &sub.fqdn else {
+            continue;
+        };
+        let realms = match serving.remove(&sub.name) {
+            Some(realms) => realms,
+            None => sub
+                .destination_realm
+                .iter()
+                .filter_map(|r| match Urn::parse("destinationRealm", r) {
+                    Ok(Urn::Realm(realm)) => Some(realm.to_string()),
+                    _ => None,
+                })
+                .collect(),
+        };
+        for explicit in &sub.records {
+            match normalize_data(explicit.record_type, &explicit.data) {
+                Ok(data) => {
+                    records.insert(DnsRecord { name: fqdn.clone(), record_type: explicit.record_type, ttl: explicit.ttl.unwrap_or(ttl), data });
+                }
+                Err(e) => warnings.push(format!("'{}': {}; skipped", fqdn, e)),
+            }
+        }
+        // アドレスを明示した Subdomain は Hub に向けない
+        if sub.records.iter().any(|r| r.record_type.is_address()) {
+            continue;
+        }
+        let targets: Vec<&Hub> = realms.iter().flat_map(|r| hubs.get(r).into_iter().flatten()).collect();
+        if targets.is_empty() {
+            continue;
+        }
+        for record in hub_records(fqdn, sub.name == "@", &targets, ttl, &mut warnings) {
+            if record.record_type == RecordType::CNAME && !sub.records.is_empty() {
+                warnings.push(format!("'{}' has explicit records and cannot be a CNAME to hub '{}'; skipped", fqdn, record.data));
+                continue;
+            }
+            records.insert(record);
+        }
+    }
+    let prefix = challenge_prefix(realm, &zone.zone);
+    for kv in client.get(prefix.as_str(), Some(etcd_client::GetOptions::new().with_prefix())).await?.kvs() {
+        let fqdn = kv.key_str()?.trim_start_matches(prefix.as_str());
+        let name = format!("_acme-challenge.{}", fqdn.trim_start_matches("*."));
+        match normalize_data(RecordType::TXT, kv.value_str()?) {
+            Ok(data) => {
+                records.insert(DnsRecord { name, record_type: RecordType::TXT, ttl: CHALLENGE_TTL, data });
+            }
+            Err(e) => warnings.push(format!("'{}': {}; skipped", name, e)),
+        }
+    }
+    let revision = resp.header().map(|h| h.revision()).unwrap_or_default();
+    Ok(ZoneRecords { records: records.into_iter().collect(), warnings, revision })
+}
+
+/// Hub の FQDN が IP アドレスなら A / AAAA、ホスト名なら CNAME を返す
+fn hub_records(fqdn: &str, apex: bool, hubs: &[&Hub], ttl: u32, warnings: &mut Vec<String>) -> Vec<DnsRecord> {
+    let record = |record_type, data: String| DnsRecord { name: fqdn.to_string(), record_type, ttl, data };
+    let addresses: Vec<DnsRecord> = hubs
+        .iter()
+        .filter_map(|hub| match hub.fqdn.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => Some(record(RecordType::A, ip.to_string())),
+            Ok(IpAddr::V6(ip)) => Some(record(RecordType::AAAA, ip.to_string())),
+            Err(_) => None,
+        })
+        .collect();
+    if !addresses.is_empty() {
+        return addresses;
+    }
+
+    let mut names: Vec<&str> = hubs.iter().map(|hub| hub.fqdn.trim_end_matches('.')).collect();
+    names.sort_unstable();
+    names.dedup();
+    if apex {
+        warnings.push(format!("'{}' is the zone apex and cannot be a CNAME to hub '{}'; skipped", fqdn, names[0]));
+        return Vec::new();
+    }
+    if names.len() > 1 {
+        warnings.push(format!("'{}' is served by several hubs ({}); pointing at '{}'", fqdn, names.join(", "), names[0]));
+    }
+    vec![record(RecordType::CNAME, format!("{}.", names[0]))]
+}
+
+/// 最後に反映したレコードとの差分を計画する
+pub async fn plan(state: &AppState, realm: &str, zone: &Zone) -> Result<DnsPlan, ApiError> {
+    let ZoneRecords { records, warnings, .. } = zone_records(state, realm, zone).await?;
+    let applied: BTreeSet<DnsRecord> = load_sync_state(state, realm, &zone.zone).await?.records.into_iter().collect();
+    let desired: BTreeSet<DnsRecord> = records.iter().cloned().collect();
+    Ok(DnsPlan {
+        zone: zone.zone.clone(),
+        dns_provider: zone.dns_provider.clone(),
+        create: desired.difference(&applied).cloned().collect(),
+        delete: applied.difference(&desired).cloned().collect(),
+        unchanged: desired.intersection(&applied).count(),
+        warnings,
+        desired: records,
+    })
+}
+
+/// Zone のレコードを DNS プロバイダーに反映する
+pub async fn sync_zone(state: &AppState, realm: &str, zone_name: &str) -> Result<DnsPlan, ApiError> {
+    let _guard = state.dns.lock.lock().await;
+    let zone = zone::load_zone(state, realm, zone_name).await?;
+    let Some(provider) = dns_provider::for_zone(&zone)? else {
+        return Err(ApiError::Unprocessable(format!(
+            "dnsProvider: zone '{}' is not managed by a supported DNS provider",
+            zone.zone
+        )));
+    };
+
+    let plan = plan(state, realm, &zone).await?;
+    let mut sync_state = load_sync_state(state, realm, &zone.zone).await?;
+    if !plan.is_empty() {
+        if let Err(err) = provider.apply(&zone.zone, &plan).await {
+            sync_state.error = Some(format!("{:#}", err));
+            save_sync_state(state, realm, &zone.zone, &sync_state).await?;
+            return Err(ApiError::BadGateway(format!("DNS update for zone '{}' failed: {:#}", zone.zone, err)));
+        }
+    }
+    sync_state.records = plan.desired.clone();
+    sync_state.synced_at = OffsetDateTime::now_utc().format(&Rfc3339).ok();
+    sync_state.error = None;
+    save_sync_state(state, realm, &zone.zone, &sync_state).await?;
+    Ok(plan)
+}
+
+/// ゾーンファイルのシリアルを更新し、Zone が DNS プロバイダーで管理されていれば、バックグラウンドで同期する
+pub fn schedule_sync(state: &AppState, realm: &str, zone_name: &str) {
+    zonefile::schedule_serial_refresh(state, realm, zone_name);
+    let (state, realm, zone_name) = (state.clone(), realm.to_string(), zone_name.to_string());
+    tokio::spawn(async move {
+        let managed = match zone::load_zone(&state, &realm, &zone_name).await {
+            Ok(zone) => matches!(dns_provider::for_zone(&zone), Ok(Some(_))),
+            Err(_) => false,
+        };
+        if !managed {
+            return;
+        }
+        match sync_zone(&state, &realm, &zone_name).await {
+            Ok(plan) if !plan.is_empty() => {
+                info!("Synced DNS for zone '{}' ({} created, {} deleted)", zone_name, plan.create.len(), plan.delete.len())
+            }
+            Ok(_) => {}
+            Err(e) => warn!("DNS sync for zone '{}' failed: {}", zone_name, e),
+        }
+    });
+}
+
+/// Subdomain の参照 (URN) が指す Zone を同期する
+pub fn schedule_sync_for_subdomain(state: &AppState, reference: &str) {
+    if let Some((realm, zone_name, _)) = subdomain::parse_reference(reference) {
+        schedule_sync(state, &realm, &zone_name);
+    }
+}
+
+/// すべての Zone を同期する (Hub の変更時など)
+pub async fn schedule_sync_all(state: &AppState) -> Result<(), ApiError> {
+    let mut client = state.etcd_client.clone();
+    let resp = client.get(REALM_PREFIX, Some(etcd_client::GetOptions::new().with_keys_only().with_prefix())).await?;
+    for kv in resp.kvs() {
+        let key = kv.key_str()?;
+        let segments: Vec<&str> = key.trim_start_matches(REALM_PREFIX).split('/').collect();
+        if let [realm, "zones", zone_name] = segments.as_slice() {
+            schedule_sync(state, realm, zone_name);
+        }
+    }
+    Ok(())
+}
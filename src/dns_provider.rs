@@ -0,0 +1,159 @@
+//! DNS プロバイダーの抽象化と RFC 2136 (DNS UPDATE) による実装
+//!
+//! Zone の `dnsProvider` が `rfc2136://{server}[:{port}]` の場合に、そのサーバーへ動的更新を送る。
+//! それ以外の値 (外部で管理する DNS を指す URN など) の Zone は同期の対象外とする。
+use crate::dns::{DnsPlan, DnsRecord};
+use crate::error::ApiError;
+use crate::validation;
+use crate::zone::{TsigKey, Zone};
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hickory_proto::dnssec::rdata::tsig::TsigAlgorithm;
+use hickory_proto::dnssec::tsig::TSigner;
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode, UpdateMessage};
+use hickory_proto::rr::{DNSClass, Name, RData, Record, RecordType};
+use hickory_proto::serialize::txt::RDataParser;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const RFC2136_SCHEME: &str = "rfc2136://";
+const DEFAULT_DNS_PORT: u16 = 53;
+const TSIG_FUDGE: u16 = 300;
+const UPDATE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// DNS プロバイダー
+pub trait DnsProvider {
+    /// 計画した変更 (削除と追加) を DNS に反映する
+    async fn apply(&self, zone: &str, plan: &DnsPlan) -> anyhow::Result<()>;
+}
+
+/// Zone の設定から DNS プロバイダーを作る (同期の対象外なら `None`)
+pub fn for_zone(zone: &Zone) -> Result<Option<Rfc2136Provider>, ApiError> {
+    let Some(server) = zone.dns_provider.as_deref().and_then(|p| p.strip_prefix(RFC2136_SCHEME)) else {
+        return Ok(None);
+    };
+    let server = server.trim_end_matches('/');
+    if server.is_empty() || server.contains('/') {
+        return Err(validation::invalid(
+            "dnsProvider",
+            format!("must be '{}host[:port]', got '{}'", RFC2136_SCHEME, zone.dns_provider.as_deref().unwrap_or_default()),
+        ));
+    }
+    let server = if server.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok()) && !server.ends_with(']') {
+        server.to_string()
+    } else {
+        format!("{}:{}", server, DEFAULT_DNS_PORT)
+    };
+    let signer = zone.dns_tsig_key.as_ref().map(tsig_signer).transpose()?;
+    Ok(Some(Rfc2136Provider { server, signer }))
+}
+
+/// TSIG 鍵の設定を確認して署名器を作る
+pub fn tsig_signer(key: &TsigKey) -> Result<TSigner, ApiError> {
+    let algorithm = match key.algorithm.as_deref().unwrap_or("hmac-sha256") {
+        "hmac-sha256" => TsigAlgorithm::HmacSha256,
+        "hmac-sha384" => TsigAlgorithm::HmacSha384,
+        "hmac-sha512" => TsigAlgorithm::HmacSha512,
+        other => {
+            return Err(validation::invalid(
+                "dnsTsigKey.algorithm",
+                format!("must be one of hmac-sha256, hmac-sha384, hmac-sha512, got '{}'", other),
+            ))
+        }
+    };
+    let Some(secret) = &key.secret else {
+        return Err(validation::invalid("dnsTsigKey.secret", "is required"));
+    };
+    let secret = BASE64
+        .decode(secret.expose().trim())
+        .map_err(|_| validation::invalid("dnsTsigKey.secret", "must be base64"))?;
+    let name = Name::from_ascii(&key.name).map_err(|e| validation::invalid("dnsTsigKey.name", format!("is invalid: {}", e)))?;
+    TSigner::new(secret, algorithm, name, TSIG_FUDGE).map_err(|e| validation::invalid("dnsTsigKey", format!("is invalid: {}", e)))
+}
+
+/// RFC 2136 の動的更新を送るプロバイダー
+pub struct Rfc2136Provider {
+    server: String,
+    signer: Option<TSigner>,
+}
+
+impl DnsProvider for Rfc2136Provider {
+    async fn apply(&self, zone: &str, plan: &DnsPlan) -> anyhow::Result<()> {
+        let origin = fqdn_name(zone)?;
+
+        let mut id = [0u8; 2];
+        SystemRandom::new().fill(&mut id).map_err(|_| anyhow!("Failed to generate message id"))?;
+        let mut message = Message::new();
+        message
+            .set_id(u16::from_be_bytes(id))
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Update)
+            .set_recursion_desired(false);
+        let mut query = Query::new();
+        query.set_name(origin).set_query_class(DNSClass::IN).set_query_type(RecordType::SOA);
+        message.add_zone(query);
+
+        // 削除 (RFC 2136 2.5.4: CLASS NONE, TTL 0) を先に、追加をその後に並べる
+        for record in &plan.delete {
+            let mut rr = to_record(record)?;
+            rr.set_dns_class(DNSClass::NONE).set_ttl(0);
+            message.add_update(rr);
+        }
+        for record in &plan.create {
+            message.add_update(to_record(record)?);
+        }
+
+        let mut verifier = match &self.signer {
+            Some(signer) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+                message.finalize(signer, now)?
+            }
+            None => None,
+        };
+
+        let request = message.to_vec()?;
+        let response = tokio::time::timeout(UPDATE_TIMEOUT, exchange(&self.server, &request))
+            .await
+            .map_err(|_| anyhow!("Timed out waiting for '{}'", self.server))??;
+        let response = match verifier.as_mut() {
+            Some(verify) => verify(&response)?.into_message(),
+            None => Message::from_vec(&response)?,
+        };
+        if response.id() != message.id() {
+            bail!("DNS server '{}' answered with a mismatched message id", self.server);
+        }
+        match response.response_code() {
+            ResponseCode::NoError => Ok(()),
+            code => bail!("DNS server '{}' rejected the update: {}", self.server, code),
+        }
+    }
+}
+
+/// TCP で 1 メッセージをやり取りする (RFC 1035 4.2.2 の長さ接頭辞付き)
+async fn exchange(server: &str, request: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(server).await.with_context(|| format!("Failed to connect to DNS server '{}'", server))?;
+    let mut frame = (request.len() as u16).to_be_bytes().to_vec();
+    frame.extend_from_slice(request);
+    stream.write_all(&frame).await?;
+
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).await?;
+    let mut response = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+fn fqdn_name(name: &str) -> anyhow::Result<Name> {
+    let mut name = Name::from_ascii(name).with_context(|| format!("Invalid domain name '{}'", name))?;
+    name.set_fqdn(true);
+    Ok(name)
+}
+
+fn to_record(record: &DnsRecord) -> anyhow::Result<Record> {
+    let record_type: RecordType = record.record_type.as_str().parse()?;
+    let rdata = RData::try_from_str(record_type, &record.data)
+        .map_err(|e| anyhow!("Invalid {} data '{}' for '{}': {}", record.record_type.as_str(), record.data, record.name, e))?;
+    Ok(Record::from_rdata(fqdn_name(&record.name)?, record.ttl, rdata))
+}
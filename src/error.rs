@@ -3,28 +3,84 @@ use axum::{
     response::{Response, IntoResponse}, 
     Json,
 };
-use serde_json::json;
+use serde_json::{json, Value};
+use std::fmt;
 
 pub enum ApiError {
     NotFound(String),
     Conflict(String), 
     BadRequest(String),
+    Forbidden(String),
+    Unprocessable(String),
+    BadGateway(String),
+    // 入力の特定のフィールドが不正 (400、レスポンスに `field` を含める)
+    InvalidField { field: String, message: String },
+    // 422 に詳細情報を付ける (`details` のキーはレスポンスの本文に展開する)
+    UnprocessableDetails { message: String, details: Value },
     Internal(anyhow::Error),
 }
 
-impl IntoResponse for ApiError {
-    fn into_response(self) -> Response {
+impl ApiError {
+    /// ステータスコードとエラーレスポンスの本文 (`{"code", "message", "field"?, ...}`) を返す
+    pub fn into_status_and_body(self) -> (StatusCode, Value) {
+        let mut field = None;
+        let mut details = None;
         let (status, error_message) = match self {
             ApiError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
             ApiError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
             ApiError::Conflict(msg) => (StatusCode::CONFLICT, msg),
+            ApiError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
+            ApiError::Unprocessable(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg),
+            ApiError::BadGateway(msg) => (StatusCode::BAD_GATEWAY, msg),
+            ApiError::InvalidField { field: name, message } => {
+                let msg = format!("{}: {}", name, message);
+                field = Some(name);
+                (StatusCode::BAD_REQUEST, msg)
+            }
+            ApiError::UnprocessableDetails { message, details: extra } => {
+                details = Some(extra);
+                (StatusCode::UNPROCESSABLE_ENTITY, message)
+            }
             ApiError::Internal(err) => {
                 tracing::error!("Internal server error: {:?}", err);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string())
             }
         };
 
-        (status, Json(json!({ "code": status.as_u16().to_string(), "message": error_message }))).into_response()
+        let mut body = json!({ "code": status.as_u16().to_string(), "message": error_message });
+        if let Some(field) = field {
+            body["field"] = json!(field);
+        }
+        if let Some(Value::Object(extra)) = details {
+            for (key, value) in extra {
+                body[key] = value;
+            }
+        }
+        (status, body)
+    }
+}
+
+/// ログ出力用 (内部エラーは原因まで含める)
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::NotFound(msg)
+            | ApiError::Conflict(msg)
+            | ApiError::BadRequest(msg)
+            | ApiError::Forbidden(msg)
+            | ApiError::Unprocessable(msg)
+            | ApiError::BadGateway(msg)
+            | ApiError::UnprocessableDetails { message: msg, .. } => write!(f, "{}", msg),
+            ApiError::InvalidField { field, message } => write!(f, "{}: {}", field, message),
+            ApiError::Internal(err) => write!(f, "{:#}", err),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, body) = self.into_status_and_body();
+        (status, Json(body)).into_response()
     }
 }
 
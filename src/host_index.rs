@@ -0,0 +1,363 @@
+//! ホスト名の一意性を保証する索引
+//!
+//! Subdomain の FQDN、VirtualHost が配信する Subdomain、Hub の待ち受け (FQDN:ポート) は全 Realm を通して
+//! それぞれ 1 つのリソースしか持てない。etcd に次の索引を置き、リソースの書き込みと同じトランザクションで更新する。
+//! 値は所有するリソースの URN。
+//!
+//! - `/index/hosts/{fqdn}`: Subdomain (ワイルドカードは `*.` 付きの FQDN)
+//! - `/index/bindings/{subdomain}`: Subdomain を配信する VirtualHost (無効化された VirtualHost は含めない)
+//! - `/index/hub-endpoints/{fqdn}:{port}`: Hub
+//!
+//! 索引ができる前のデータは起動時と `POST /admin/rebuild-host-index` で取り込む。
+use crate::db::{AppState, REALM_PREFIX};
+use crate::error::ApiError;
+use crate::hub::Hub;
+use crate::subdomain::{self, Subdomain};
+use crate::utils::get_from_etcd;
+use crate::urn::Urn;
+use crate::validation::{self, HubName, RealmName, SubdomainName, VirtualHostName, ZoneName};
+use crate::virtual_host::VirtualHost;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use etcd_client::{Compare, CompareOp, DeleteOptions, GetOptions, Txn, TxnOp, TxnOpResponse, TxnResponse};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tracing::{info, warn};
+
+pub const INDEX_PREFIX: &str = "/index/";
+const HOST_PREFIX: &str = "/index/hosts/";
+const BINDING_PREFIX: &str = "/index/bindings/";
+const HUB_ENDPOINT_PREFIX: &str = "/index/hub-endpoints/";
+/// `serverPort` を省略した Hub のポート
+pub const DEFAULT_HUB_PORT: i32 = 443;
+
+/// ホスト名の検索結果
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HostLookup {
+    pub hostname: String,
+    /// 一致した Subdomain の URN
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subdomain: Option<String>,
+    /// 一致した Subdomain の FQDN (ワイルドカードの場合は `*.` 付き)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fqdn: Option<String>,
+    pub wildcard: bool,
+    /// Subdomain を配信する VirtualHost の URN
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub virtual_host: Option<String>,
+    /// ホスト名で待ち受ける Hub
+    pub hubs: Vec<HubEndpoint>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HubEndpoint {
+    pub hub: String,
+    pub port: i32,
+}
+
+/// 索引の再構築の結果
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RebuildReport {
+    pub added: usize,
+    pub removed: usize,
+    /// 複数のリソースが同じエントリを主張しているもの (先に見つかったリソースが所有する)
+    pub conflicts: Vec<IndexConflict>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexConflict {
+    pub key: String,
+    pub owners: Vec<String>,
+}
+
+pub fn host_key(fqdn: &str) -> String {
+    format!("{}{}", HOST_PREFIX, fqdn)
+}
+
+pub fn binding_key(subdomain: &str) -> String {
+    format!("{}{}", BINDING_PREFIX, subdomain)
+}
+
+pub fn hub_endpoint_key(fqdn: &str, port: Option<i32>) -> String {
+    format!("{}{}:{}", HUB_ENDPOINT_PREFIX, fqdn, port.unwrap_or(DEFAULT_HUB_PORT))
+}
+
+/// リソースの書き込みと索引の更新をまとめた 1 つのトランザクション
+///
+/// 索引は読み取った時点の状態を条件にするため、その後に他の書き込みで変わっていれば全体が失敗する (409)。
+#[derive(Default)]
+pub struct IndexedWrite {
+    compares: Vec<Compare>,
+    ops: Vec<TxnOp>,
+}
+
+impl IndexedWrite {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// キーがまだ存在しないことを条件にする
+    pub fn require_absent(&mut self, key: &str) {
+        self.compares.push(Compare::version(key, CompareOp::Equal, 0));
+    }
+
+    /// キーが読み取った時点から変わっていないことを条件にする
+    pub fn require_revision(&mut self, key: &str, revision: i64) {
+        self.compares.push(Compare::mod_revision(key, CompareOp::Equal, revision));
+    }
+
+    /// プレフィックス配下のキーが `revision` より後に書き込まれていないことを条件にする (作成も含む)
+    pub fn require_prefix_unchanged(&mut self, prefix: &str, revision: i64) {
+        self.compares.push(Compare::mod_revision(prefix, CompareOp::Less, revision + 1).with_prefix());
+    }
+
+    pub fn put(&mut self, key: &str, value: Vec<u8>) {
+        self.ops.push(TxnOp::put(key, value, None));
+    }
+
+    /// キーを削除する (削除した値は [`deleted_value`] で取り出す)
+    pub fn delete(&mut self, key: &str) {
+        self.ops.push(TxnOp::delete(key, Some(DeleteOptions::new().with_prev_key())));
+    }
+
+    /// プレフィックス配下のキーをすべて削除する
+    pub fn delete_prefix(&mut self, prefix: &str) {
+        self.ops.push(TxnOp::delete(prefix, Some(DeleteOptions::new().with_prefix())));
+    }
+
+    /// 索引のエントリを `owner` のものにする (他のリソースが所有していれば 409)
+    pub async fn claim(&mut self, state: &AppState, key: &str, owner: &str) -> Result<(), ApiError> {
+        match get_from_etcd(state, key).await?.kvs().first() {
+            Some(kv) if kv.value() != owner.as_bytes() => Err(ApiError::Conflict(format!(
+                "'{}' is already claimed by '{}'.",
+                entry_name(key),
+                String::from_utf8_lossy(kv.value())
+            ))),
+            Some(kv) => {
+                self.require_revision(key, kv.mod_revision());
+                Ok(())
+            }
+            None => {
+                self.require_absent(key);
+                self.put(key, owner.as_bytes().to_vec());
+                Ok(())
+            }
+        }
+    }
+
+    /// 索引のエントリを `owner` が所有している場合のみ削除する
+    pub async fn release(&mut self, state: &AppState, key: &str, owner: &str) -> Result<(), ApiError> {
+        if let Some(kv) = get_from_etcd(state, key).await?.kvs().first() {
+            if kv.value() == owner.as_bytes() {
+                self.require_revision(key, kv.mod_revision());
+                self.ops.push(TxnOp::delete(key, None));
+            }
+        }
+        Ok(())
+    }
+
+    /// Realm 内のリソースが所有する索引のエントリをすべて削除する
+    pub async fn release_realm(&mut self, state: &AppState, realm: &RealmName) -> Result<(), ApiError> {
+        let mut client = state.etcd_client.clone();
+        let resp = client.get(INDEX_PREFIX, Some(GetOptions::new().with_prefix())).await?;
+        for kv in resp.kvs() {
+            let owned = std::str::from_utf8(kv.value())
+                .ok()
+                .and_then(|owner| Urn::parse("owner", owner).ok())
+                .is_some_and(|owner| owner.realm() == realm);
+            if owned {
+                let key = String::from_utf8_lossy(kv.key());
+                self.require_revision(&key, kv.mod_revision());
+                self.ops.push(TxnOp::delete(kv.key(), None));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn commit(self, state: &AppState) -> Result<TxnResponse, ApiError> {
+        let mut client = state.etcd_client.clone();
+        let resp = client.txn(Txn::new().when(self.compares).and_then(self.ops)).await?;
+        if !resp.succeeded() {
+            return Err(ApiError::Conflict(
+                "The resource or the host index was modified concurrently; retry the request.".to_string(),
+            ));
+        }
+        Ok(resp)
+    }
+}
+
+/// トランザクションで最初に削除したキーの値を返す
+pub fn deleted_value(resp: &TxnResponse) -> Option<Vec<u8>> {
+    resp.op_responses().into_iter().find_map(|op| match op {
+        TxnOpResponse::Delete(delete) => delete.prev_kvs().first().map(|kv| kv.value().to_vec()),
+        _ => None,
+    })
+}
+
+/// 索引のキーからエラーメッセージに使う名前を取り出す
+fn entry_name(key: &str) -> &str {
+    [HOST_PREFIX, BINDING_PREFIX, HUB_ENDPOINT_PREFIX]
+        .iter()
+        .find_map(|prefix| key.strip_prefix(prefix))
+        .unwrap_or(key)
+}
+
+/// ホスト名に一致しうる索引の FQDN を、具体的なものから順に返す (完全一致、`*.` で 1 ラベルずつ短くしたもの)
+fn candidates(hostname: &str) -> Vec<String> {
+    let labels: Vec<&str> = hostname.split('.').collect();
+    let mut names = vec![hostname.to_string()];
+    names.extend((1..labels.len()).map(|i| format!("*.{}", labels[i..].join("."))));
+    names
+}
+
+/// GET /lookup/host/{hostname}
+///
+/// ホスト名を配信するリソース (最も具体的な Subdomain とそれを配信する VirtualHost、ホスト名で待ち受ける Hub) を返す。
+pub async fn lookup_host(
+    State(state): State<AppState>,
+    Path(hostname): Path<String>,
+) -> Result<Json<HostLookup>, ApiError> {
+    let hostname = validation::domain_name("hostname", &hostname, false)?;
+    let mut client = state.etcd_client.clone();
+
+    // 候補を同じリビジョンでまとめて読む
+    let names = candidates(&hostname);
+    let gets: Vec<TxnOp> = names.iter().map(|name| TxnOp::get(host_key(name), None)).collect();
+    let resp = client.txn(Txn::new().and_then(gets)).await?;
+    let matched = resp.op_responses().into_iter().zip(&names).find_map(|(op, name)| match op {
+        TxnOpResponse::Get(get) => get.kvs().first().map(|kv| (name.clone(), String::from_utf8_lossy(kv.value()).into_owned())),
+        _ => None,
+    });
+
+    let mut lookup = HostLookup {
+        hostname: hostname.clone(),
+        subdomain: None,
+        fqdn: None,
+        wildcard: false,
+        virtual_host: None,
+        hubs: Vec::new(),
+    };
+    if let Some((fqdn, urn)) = matched {
+        if let Some(kv) = get_from_etcd(&state, &binding_key(&urn)).await?.kvs().first() {
+            lookup.virtual_host = Some(String::from_utf8_lossy(kv.value()).into_owned());
+        }
+        lookup.wildcard = fqdn.starts_with("*.");
+        lookup.fqdn = Some(fqdn);
+        lookup.subdomain = Some(urn);
+    }
+
+    let prefix = format!("{}{}:", HUB_ENDPOINT_PREFIX, hostname);
+    let hubs = client.get(prefix.as_str(), Some(GetOptions::new().with_prefix())).await?;
+    for kv in hubs.kvs() {
+        let Some(Ok(port)) = kv.key_str()?.strip_prefix(&prefix).map(str::parse) else {
+            continue;
+        };
+        lookup.hubs.push(HubEndpoint { hub: String::from_utf8_lossy(kv.value()).into_owned(), port });
+    }
+
+    if lookup.subdomain.is_none() && lookup.hubs.is_empty() {
+        return Err(ApiError::NotFound(format!("No subdomain or hub serves host '{}'", hostname)));
+    }
+    Ok(Json(lookup))
+}
+
+/// 保存されているリソースから索引を作り直す
+///
+/// 索引にないエントリを追加し、所有者がいなくなったエントリを削除する。
+/// 既存のエントリは所有者がまだ主張している限り変更しない。
+pub async fn rebuild(state: &AppState) -> Result<RebuildReport, ApiError> {
+    let mut client = state.etcd_client.clone();
+    let resp = client.get(REALM_PREFIX, Some(GetOptions::new().with_prefix())).await?;
+
+    // 索引のキー -> 主張しているリソースの URN (キーの順)
+    let mut wanted: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for kv in resp.kvs() {
+        let key = kv.key_str()?;
+        let segments: Vec<&str> = key.trim_start_matches(REALM_PREFIX).split('/').collect();
+        let (index_key, owner) = match segments.as_slice() {
+            [realm, "zones", zone, "subdomains", name] => {
+                let Ok(sub) = serde_json::from_slice::<Subdomain>(kv.value()) else {
+                    continue;
+                };
+                let fqdn = sub.fqdn.unwrap_or_else(|| subdomain::fqdn_of(name, zone));
+                let owner = RealmName::parse("realm", realm).and_then(|realm| {
+                    Ok(Urn::Subdomain(realm, ZoneName::parse("zone", zone)?, SubdomainName::parse("name", name)?))
+                });
+                (host_key(&fqdn), owner)
+            }
+            [realm, "virtual-hosts", _] => match state.keyring.decode::<VirtualHost>(kv.value()) {
+                Ok(host) if !host.disabled => {
+                    let owner = RealmName::parse("realm", realm)
+                        .and_then(|realm| Ok(Urn::VirtualHost(realm, VirtualHostName::parse("name", &host.name)?)));
+                    (binding_key(&host.subdomain), owner)
+                }
+                _ => continue,
+            },
+            [realm, "hubs", _] => {
+                let Ok(hub) = state.keyring.decode::<Hub>(kv.value()) else {
+                    continue;
+                };
+                let owner = RealmName::parse("realm", realm).and_then(|realm| Ok(Urn::Hub(realm, HubName::parse("name", &hub.name)?)));
+                (hub_endpoint_key(&hub.fqdn, hub.server_port), owner)
+            }
+            _ => continue,
+        };
+        // 名前が不正な古いドキュメントは索引に載せない
+        if let Ok(owner) = owner {
+            wanted.entry(index_key).or_default().push(owner.to_string());
+        }
+    }
+
+    let mut report = RebuildReport::default();
+    for (key, owners) in wanted.iter().filter(|(_, owners)| owners.len() > 1) {
+        report.conflicts.push(IndexConflict { key: key.clone(), owners: owners.clone() });
+    }
+    let existing = client.get(INDEX_PREFIX, Some(GetOptions::new().with_prefix())).await?;
+    for kv in existing.kvs() {
+        let key = kv.key_str()?;
+        let owner = String::from_utf8_lossy(kv.value());
+        match wanted.get(key) {
+            Some(owners) if owners.iter().any(|o| *o == owner) => {
+                wanted.remove(key);
+            }
+            _ => {
+                // 所有者がいなくなったエントリは削除し、他に主張するリソースがあれば後で追加する
+                let txn = Txn::new()
+                    .when([Compare::mod_revision(key, CompareOp::Equal, kv.mod_revision())])
+                    .and_then([TxnOp::delete(key, None)]);
+                if client.txn(txn).await?.succeeded() {
+                    report.removed += 1;
+                }
+            }
+        }
+    }
+
+    for (key, owners) in wanted {
+        let txn = Txn::new()
+            .when([Compare::version(key.as_str(), CompareOp::Equal, 0)])
+            .and_then([TxnOp::put(key.as_str(), owners[0].as_bytes(), None)]);
+        if client.txn(txn).await?.succeeded() {
+            report.added += 1;
+        }
+    }
+    Ok(report)
+}
+
+/// 起動時に索引を作り直す (失敗しても API の提供は続ける)
+pub async fn rebuild_task(state: AppState) {
+    match rebuild(&state).await {
+        Ok(report) => {
+            info!("Host index rebuilt: {} added, {} removed", report.added, report.removed);
+            for conflict in report.conflicts {
+                warn!("Host index conflict on '{}': claimed by {}", entry_name(&conflict.key), conflict.owners.join(", "));
+            }
+        }
+        Err(e) => warn!("Failed to rebuild the host index: {}", e),
+    }
+}
@@ -1,9 +1,16 @@
+use crate::ca::{self, IssueCertificateRequest};
 use crate::db::AppState;
+use crate::dns;
 use crate::error::ApiError;
-use crate::utils::get_from_etcd;
+use crate::host_index::{self, IndexedWrite};
+use crate::pki;
+use crate::secret::{Reveal, Secret, SecretFields};
+use crate::urn::Urn;
+use crate::utils::{decode_children, get_secret_document, get_from_etcd};
+use crate::validation::{self, HubName, RealmName};
 use axum::{
     extract::{Path, State},
-    routing::get,
+    routing::{get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
@@ -13,6 +20,7 @@ use serde::{Deserialize, Serialize};
 #[serde(deny_unknown_fields)]
 pub struct Hub {
     pub name: String,
+    // Realm の URN (読み取り専用)
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub realm: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -23,91 +31,184 @@ pub struct Hub {
     pub fqdn: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub server_port: Option<i32>,
+    // 省略した場合は Realm CA で発行できる (POST /realms/{realm}/hubs/{hub}/issue-certificate)
+    #[serde(default)]
     pub server_cert: String,
-    pub server_cert_key: String,
+    // 秘密情報: レスポンスでは既定で伏せられ、更新時に省略すると保存済みの値が維持される
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_cert_key: Option<Secret>,
+}
+
+impl SecretFields for Hub {
+    fn redact(&mut self) {
+        self.server_cert_key = None;
+    }
+
+    fn retain_secrets(&mut self, stored: &Self) {
+        if self.server_cert_key.is_none() {
+            self.server_cert_key = stored.server_cert_key.clone();
+        }
+    }
+
+    fn secrets_mut(&mut self) -> Vec<&mut Secret> {
+        self.server_cert_key.iter_mut().collect()
+    }
 }
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/", get(list_hubs).post(add_hub).put(update_hub))
         .route("/{hub_name}", get(get_hub).delete(delete_hub))
+        .route("/{hub_name}/issue-certificate", post(issue_hub_certificate))
 }
 
-fn hub_key(realm: &str, name: &str) -> String {
+pub fn hub_key(realm: &RealmName, name: &HubName) -> String {
     format!("/realms/{}/hubs/{}", realm, name)
 }
 
-fn hub_prefix(realm: &str) -> String {
+fn hub_prefix(realm: &RealmName) -> String {
     format!("/realms/{}/hubs/", realm)
 }
 
+/// サーバー証明書と秘密鍵が対応し、証明書が Hub の FQDN をカバーしていることを確認する
+fn validate_certificates(hub: &Hub) -> Result<(), ApiError> {
+    if hub.server_cert.is_empty() && hub.server_cert_key.is_none() {
+        return Ok(());
+    }
+    let chain = pki::parse_certificates("serverCert", &hub.server_cert)?;
+    pki::check_server_certificate("serverCert", &chain, "serverCertKey", hub.server_cert_key.as_ref(), &hub.fqdn)
+}
+
 async fn list_hubs(
     State(state): State<AppState>,
     Path(realm): Path<String>,
+    reveal: Reveal,
 ) -> Result<Json<Vec<Hub>>, ApiError> {
     let mut client = state.etcd_client.clone();
-    let prefix = hub_prefix(&realm);
-    let resp = client.get(prefix, Some(etcd_client::GetOptions::new().with_prefix())).await?;
-    let hubs = resp
-        .kvs()
-        .iter()
-        .filter_map(|kv| serde_json::from_slice(kv.value()).ok())
-        .collect();
-    Ok(Json(hubs))
+    let prefix = hub_prefix(&RealmName::parse("realm", &realm)?);
+    let resp = client.get(prefix.as_str(), Some(etcd_client::GetOptions::new().with_prefix())).await?;
+    let hubs = decode_children(&state, &prefix, resp.kvs());
+    Ok(Json(reveal.apply_all(hubs)))
 }
 
 async fn add_hub(
     State(state): State<AppState>,
     Path(realm): Path<String>,
+    reveal: Reveal,
     Json(mut hub): Json<Hub>,
 ) -> Result<Json<Hub>, ApiError> {
-    let mut client = state.etcd_client.clone();
-    let key = hub_key(&realm, &hub.name);
+    let realm = RealmName::parse("realm", &realm)?;
+    let name = HubName::parse("name", &hub.name)?;
+    let key = hub_key(&realm, &name);
+    hub.fqdn = validation::host("fqdn", &hub.fqdn)?;
 
+    #[allow(clippy::unnecessary_first_then_check)]
     if get_from_etcd(&state, &key).await?.kvs().first().is_some() {
         return Err(ApiError::Conflict(format!("Hub '{}' already exists in realm '{}'.", hub.name, realm)));
     }
+    validate_certificates(&hub)?;
     
-    hub.realm = Some(realm.clone());
-    hub.urn = Some(format!("urn:chip-in:hub:{}:{}", realm, hub.name));
-    let value = serde_json::to_vec(&hub)?;
-    client.put(key, value, None).await?;
-    Ok(Json(hub))
+    hub.realm = Some(Urn::Realm(realm.clone()).to_string());
+    let urn = Urn::Hub(realm.clone(), name).to_string();
+    hub.urn = Some(urn.clone());
+
+    // FQDN とポートの組は全 Realm で一意
+    let mut write = IndexedWrite::new();
+    write.require_absent(&key);
+    write.claim(&state, &host_index::hub_endpoint_key(&hub.fqdn, hub.server_port), &urn).await?;
+    write.put(&key, state.keyring.encode(&hub)?);
+    write.commit(&state).await?;
+    dns::schedule_sync_all(&state).await?;
+    Ok(Json(reveal.apply(hub)))
 }
 
 async fn update_hub(
     State(state): State<AppState>,
     Path(realm): Path<String>,
+    reveal: Reveal,
     Json(mut hub): Json<Hub>,
 ) -> Result<Json<Hub>, ApiError> {
-    let mut client = state.etcd_client.clone();
-    let key = hub_key(&realm, &hub.name);
-    hub.realm = Some(realm.clone());
-    hub.urn = Some(format!("urn:chip-in:hub:{}:{}", realm, hub.name));
-    let value = serde_json::to_vec(&hub)?;
-    client.put(key, value, None).await?;
-    Ok(Json(hub))
+    let realm = RealmName::parse("realm", &realm)?;
+    let name = HubName::parse("name", &hub.name)?;
+    let key = hub_key(&realm, &name);
+    hub.fqdn = validation::host("fqdn", &hub.fqdn)?;
+    let stored = get_secret_document::<Hub>(&state, &key).await?;
+    if let Some(stored) = &stored {
+        hub.retain_secrets(stored);
+    }
+    validate_certificates(&hub)?;
+    hub.realm = Some(Urn::Realm(realm.clone()).to_string());
+    let urn = Urn::Hub(realm.clone(), name).to_string();
+    hub.urn = Some(urn.clone());
+
+    let endpoint = host_index::hub_endpoint_key(&hub.fqdn, hub.server_port);
+    let mut write = IndexedWrite::new();
+    if let Some(stored) = &stored {
+        let previous = host_index::hub_endpoint_key(&stored.fqdn, stored.server_port);
+        if previous != endpoint {
+            write.release(&state, &previous, &urn).await?;
+        }
+    }
+    write.claim(&state, &endpoint, &urn).await?;
+    write.put(&key, state.keyring.encode(&hub)?);
+    write.commit(&state).await?;
+    dns::schedule_sync_all(&state).await?;
+    Ok(Json(reveal.apply(hub)))
 }
 
-async fn get_hub(State(state): State<AppState>, Path((realm, name)): Path<(String, String)>) -> Result<Json<Hub>, ApiError> {
-    let key = hub_key(&realm, &name);
+async fn get_hub(State(state): State<AppState>, Path((realm, name)): Path<(String, String)>, reveal: Reveal) -> Result<Json<Hub>, ApiError> {
+    let key = hub_key(&RealmName::parse("realm", &realm)?, &HubName::parse("hub", &name)?);
     if let Some(kv) = get_from_etcd(&state, &key).await?.kvs().first() {
-        let hub = serde_json::from_slice(kv.value())?;
-        Ok(Json(hub))
+        let hub = state.keyring.decode(kv.value())?;
+        Ok(Json(reveal.apply(hub)))
     } else {
         Err(ApiError::NotFound(format!("Hub '{}' not found in realm '{}'", name, realm)))
     }
 }
 
-async fn delete_hub(State(state): State<AppState>, Path((realm, name)): Path<(String, String)>) -> Result<Json<Hub>, ApiError> {
-    let mut client = state.etcd_client.clone();
-    let key = hub_key(&realm, &name);
-    let opts = etcd_client::DeleteOptions::new().with_prev_key();
-    let resp = client.delete(key, Some(opts)).await?;
-    if let Some(kv) = resp.prev_kvs().first() {
-        let hub = serde_json::from_slice(kv.value())?;
-        Ok(Json(hub))
-    } else {
-        Err(ApiError::NotFound(format!("Hub '{}' not found in realm '{}'", name, realm)))
+async fn delete_hub(State(state): State<AppState>, Path((realm, name)): Path<(String, String)>, reveal: Reveal) -> Result<Json<Hub>, ApiError> {
+    let realm_name = RealmName::parse("realm", &realm)?;
+    let hub_name = HubName::parse("hub", &name)?;
+    let key = hub_key(&realm_name, &hub_name);
+    let Some(kv) = get_from_etcd(&state, &key).await?.kvs().first().cloned() else {
+        return Err(ApiError::NotFound(format!("Hub '{}' not found in realm '{}'", name, realm)));
+    };
+    let hub: Hub = state.keyring.decode(kv.value())?;
+
+    let mut write = IndexedWrite::new();
+    write.require_revision(&key, kv.mod_revision());
+    let urn = Urn::Hub(realm_name, hub_name).to_string();
+    write.release(&state, &host_index::hub_endpoint_key(&hub.fqdn, hub.server_port), &urn).await?;
+    write.delete(&key);
+    write.commit(&state).await?;
+    dns::schedule_sync_all(&state).await?;
+    Ok(Json(reveal.apply(hub)))
+}
+
+/// POST /realms/{realm}/hubs/{hub_name}/issue-certificate
+///
+/// Realm CA で Hub の FQDN 用のサーバー証明書を発行 (再発行) し、Hub に書き戻す。
+async fn issue_hub_certificate(
+    State(state): State<AppState>,
+    Path((realm, name)): Path<(String, String)>,
+    reveal: Reveal,
+    request: Option<Json<IssueCertificateRequest>>,
+) -> Result<Json<Hub>, ApiError> {
+    let Json(request) = request.unwrap_or_default();
+    let key = hub_key(&RealmName::parse("realm", &realm)?, &HubName::parse("hub", &name)?);
+    let Some(kv) = get_from_etcd(&state, &key).await?.kvs().first().cloned() else {
+        return Err(ApiError::NotFound(format!("Hub '{}' not found in realm '{}'", name, realm)));
+    };
+    let mut hub: Hub = state.keyring.decode(kv.value())?;
+
+    if let Some((cert, cert_key)) = ca::issue_for_host(&state, &realm, &hub.fqdn, &hub.server_cert, &request).await? {
+        hub.server_cert = cert;
+        hub.server_cert_key = Some(cert_key);
+        // 発行中に他の変更があれば 409 にする
+        let mut write = IndexedWrite::new();
+        write.require_revision(&key, kv.mod_revision());
+        write.put(&key, state.keyring.encode(&hub)?);
+        write.commit(&state).await?;
     }
-}
\ No newline at end of file
+    Ok(Json(reveal.apply(hub)))
+}
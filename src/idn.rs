@@ -0,0 +1,52 @@
+//! 国際化ドメイン名 (IDNA / UTS #46) の正規化
+//!
+//! Zone 名と Subdomain 名は小文字の A-label (punycode) 形式に正規化して保存し、etcd のキーや一意性の確認、
+//! 証明書の SAN の照合にはこの形式を使う。表示用の U-label (Unicode) 形式は別のフィールドで返す。
+
+/// 名前を UTS #46 で処理して A-label 形式にする (Unicode と punycode のどちらも受け付ける)
+///
+/// 先頭の `*` ラベル (ワイルドカード) はそのまま残す。
+pub fn to_ascii(name: &str) -> Result<String, String> {
+    let name = name.trim_end_matches('.');
+    let (wildcard, rest) = match name.strip_prefix('*') {
+        Some("") => return Ok("*".to_string()),
+        Some(rest) if rest.starts_with('.') => (true, &rest[1..]),
+        _ => (false, name),
+    };
+    let ascii = idna::domain_to_ascii(rest).map_err(|_| format!("'{}' is not a valid internationalized domain name", name))?;
+    if ascii.is_empty() {
+        return Err(format!("'{}' is not a valid domain name", name));
+    }
+    Ok(if wildcard { format!("*.{}", ascii) } else { ascii })
+}
+
+/// A-label 形式のラベルとして使えるか (英数字、ハイフン、アンダースコアの 63 文字以内で、先頭と末尾はハイフン以外)
+pub fn is_valid_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.len() <= 63
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// A-label 形式の名前を U-label 形式にする
+pub fn to_unicode(name: &str) -> String {
+    match name.strip_prefix("*.") {
+        Some(rest) => format!("*.{}", idna::domain_to_unicode(rest).0),
+        None => idna::domain_to_unicode(name).0,
+    }
+}
+
+/// URL のパスなどで受け取った名前を A-label 形式にする (変換できない名前はそのまま返す)
+pub fn canonical(name: &str) -> String {
+    if name == "@" {
+        return name.to_string();
+    }
+    to_ascii(name).unwrap_or_else(|_| name.to_string())
+}
+
+/// 国際化ドメイン名を含む場合のみ、表示用の U-label 形式を返す
+pub fn display(name: &str) -> Option<String> {
+    let unicode = to_unicode(name);
+    (unicode != name).then_some(unicode)
+}
@@ -0,0 +1,167 @@
+use crate::error::ApiError;
+use crate::secret::{Secret, SecretFields};
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use tracing::warn;
+
+/// 暗号化済みの値に付与するプレフィックス
+const ENCRYPTED_PREFIX: &str = "enc:v";
+/// これで始まる値は暗号文としてのみ扱う (導入前の平文は付いていないものに限る)
+const ENCRYPTED_MARKER: &str = "enc:";
+/// マスターキーがないときに、プレフィックスと紛らわしい平文に付与するプレフィックス
+const PLAINTEXT_PREFIX: &str = "plain:";
+const NONCE_LEN: usize = 12;
+
+/// 秘密情報をエンベロープ暗号化するためのマスターキー群
+///
+/// 値ごとにデータキーを生成して AES-256-GCM で暗号化し、データキーはマスターキーでラップする。
+/// 保存形式は `enc:v{version}:{wrapped data key}:{ciphertext}` (いずれも base64, 先頭 12 バイトが nonce)。
+/// 複数バージョンのマスターキーを保持でき、暗号化には常に現在のバージョンを使う。
+/// マスターキーがなければ平文で保存し、`enc:` や `plain:` で始まる平文には `plain:` を付けて区別する。
+pub struct Keyring {
+    keys: BTreeMap<u32, Key<Aes256Gcm>>,
+    current: Option<u32>,
+}
+
+impl Keyring {
+    /// 環境変数からマスターキーを読み込む
+    ///
+    /// - `SECRET_MASTER_KEYS`: `1:<base64>,2:<base64>` 形式のキー一覧
+    /// - `SECRET_MASTER_KEY_FILE`: 1 行に 1 つ `version:<base64>` を記述したファイル (`#` 以降はコメント)
+    /// - `SECRET_MASTER_KEY_VERSION`: 暗号化に使うバージョン (省略時は最大のバージョン)
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut entries = Vec::new();
+        if let Ok(list) = env::var("SECRET_MASTER_KEYS") {
+            entries.extend(list.split(',').map(str::to_string));
+        }
+        if let Ok(path) = env::var("SECRET_MASTER_KEY_FILE") {
+            let content = std::fs::read_to_string(&path).with_context(|| format!("Failed to read master key file '{}'", path))?;
+            entries.extend(content.lines().map(|line| line.split('#').next().unwrap_or_default().to_string()));
+        }
+
+        let mut keys = BTreeMap::new();
+        for entry in entries.iter().map(|e| e.trim()).filter(|e| !e.is_empty()) {
+            let (version, encoded) = entry.split_once(':').ok_or_else(|| anyhow!("Master key entry must be 'version:base64key'"))?;
+            let version: u32 = version.trim().parse().with_context(|| format!("Invalid master key version '{}'", version))?;
+            let bytes = BASE64.decode(encoded.trim()).with_context(|| format!("Master key v{} is not valid base64", version))?;
+            if bytes.len() != 32 {
+                bail!("Master key v{} must be 32 bytes, got {}", version, bytes.len());
+            }
+            if keys.insert(version, *Key::<Aes256Gcm>::from_slice(&bytes)).is_some() {
+                bail!("Master key v{} is defined more than once", version);
+            }
+        }
+
+        let current = match env::var("SECRET_MASTER_KEY_VERSION") {
+            Ok(v) => {
+                let version: u32 = v.parse().with_context(|| format!("Invalid SECRET_MASTER_KEY_VERSION '{}'", v))?;
+                if !keys.contains_key(&version) {
+                    bail!("SECRET_MASTER_KEY_VERSION {} has no matching master key", version);
+                }
+                Some(version)
+            }
+            Err(_) => keys.keys().next_back().copied(),
+        };
+        if current.is_none() {
+            warn!("No master key configured; secret fields will be stored in plain text");
+        }
+
+        Ok(Keyring { keys, current })
+    }
+
+    /// 暗号化に使うマスターキーのバージョン
+    pub fn current_version(&self) -> Option<u32> {
+        self.current
+    }
+
+    /// 値を暗号化したときのキーバージョン (平文なら `None`)
+    pub fn version_of(value: &str) -> Option<u32> {
+        value.strip_prefix(ENCRYPTED_PREFIX)?.split(':').next()?.parse().ok()
+    }
+
+    fn encrypt(&self, plaintext: &str) -> anyhow::Result<String> {
+        let Some(version) = self.current else {
+            if plaintext.starts_with(ENCRYPTED_MARKER) || plaintext.starts_with(PLAINTEXT_PREFIX) {
+                return Ok(format!("{}{}", PLAINTEXT_PREFIX, plaintext));
+            }
+            return Ok(plaintext.to_string());
+        };
+        let master = Aes256Gcm::new(&self.keys[&version]);
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        let wrapped = seal(&master, data_key.as_slice())?;
+        let ciphertext = seal(&Aes256Gcm::new(&data_key), plaintext.as_bytes())?;
+        Ok(format!("{}{}:{}:{}", ENCRYPTED_PREFIX, version, BASE64.encode(wrapped), BASE64.encode(ciphertext)))
+    }
+
+    fn decrypt(&self, value: &str) -> anyhow::Result<String> {
+        if let Some(plaintext) = value.strip_prefix(PLAINTEXT_PREFIX) {
+            return Ok(plaintext.to_string());
+        }
+        if !value.starts_with(ENCRYPTED_MARKER) {
+            // 暗号化導入前に保存された平文の値
+            return Ok(value.to_string());
+        }
+        let Some(rest) = value.strip_prefix(ENCRYPTED_PREFIX) else {
+            bail!("Malformed encrypted secret");
+        };
+        let mut parts = rest.splitn(3, ':');
+        let (Some(version), Some(wrapped), Some(ciphertext)) = (parts.next(), parts.next(), parts.next()) else {
+            bail!("Malformed encrypted secret");
+        };
+        let version: u32 = version.parse().context("Malformed encrypted secret version")?;
+        let key = self.keys.get(&version).ok_or_else(|| anyhow!("Master key v{} is not configured", version))?;
+        open_data(key, wrapped, ciphertext)
+    }
+
+    /// 秘密情報を暗号化したうえで etcd に保存する値へシリアライズする
+    pub fn encode<T: SecretFields + Serialize + Clone>(&self, doc: &T) -> Result<Vec<u8>, ApiError> {
+        let mut doc = doc.clone();
+        for secret in doc.secrets_mut() {
+            *secret = Secret::new(self.encrypt(secret.expose()).map_err(ApiError::Internal)?);
+        }
+        Ok(serde_json::to_vec(&doc)?)
+    }
+
+    /// etcd から取得した値をデシリアライズし、秘密情報を復号する
+    pub fn decode<T: SecretFields + DeserializeOwned>(&self, value: &[u8]) -> Result<T, ApiError> {
+        let mut doc: T = serde_json::from_slice(value)?;
+        for secret in doc.secrets_mut() {
+            *secret = Secret::new(self.decrypt(secret.expose()).map_err(ApiError::Internal)?);
+        }
+        Ok(doc)
+    }
+}
+
+/// ラップされたデータキーを開き、暗号文を復号する
+fn open_data(key: &Key<Aes256Gcm>, wrapped: &str, ciphertext: &str) -> anyhow::Result<String> {
+    let data_key = open(&Aes256Gcm::new(key), &BASE64.decode(wrapped)?)?;
+    if data_key.len() != 32 {
+        bail!("Unwrapped data key has an invalid length");
+    }
+    let plaintext = open(&Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key)), &BASE64.decode(ciphertext)?)?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+fn seal(cipher: &Aes256Gcm, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let mut out = nonce.to_vec();
+    out.extend(cipher.encrypt(&nonce, plaintext).map_err(|_| anyhow!("Encryption failed"))?);
+    Ok(out)
+}
+
+fn open(cipher: &Aes256Gcm, sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        bail!("Encrypted value is too short");
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("Decryption failed (wrong master key or corrupted value)"))
+}
@@ -0,0 +1,182 @@
+//! RoutingChain のルールの静的検査
+//!
+//! | code | 内容 |
+//! |---|---|
+//! | `unreachable` | 無条件の `Proxy` / `Redirect` / `Respond` より後のルール |
+//! | `shadowed` | 条件が前の `Proxy` / `Redirect` / `Respond` の条件に含まれ、評価されることのないルール (間に変数やヘッダーを変えうるルールがない場合だけ) |
+//! | `unused-variable` | 後のルールの条件で読まれない `SetVariables` のキー |
+//! | `undefined-variable` | 前のルールで設定されていない変数の読み取り |
+//! | `duplicate-header` | 前のルールが同じ `target` に設定したヘッダーの再設定 |
+//!
+//! 検査はひとつの RoutingChain の中だけで行う (`Jump` の参照先や呼び出し元で読み書きする変数は考慮しない)。
+use crate::db::AppState;
+use crate::error::ApiError;
+use crate::match_expr::{self, Expr};
+use crate::routing_chain::{Action, RoutingChain};
+use crate::urn::Urn;
+use crate::utils::get_from_etcd;
+use crate::validation::{RealmName, RoutingChainName};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Finding {
+    pub rule: usize,
+    pub code: &'static str,
+    pub message: String,
+    // 原因となった前のルール
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub related: Option<usize>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LintReport {
+    pub findings: Vec<Finding>,
+}
+
+fn is_terminal(action: &Action) -> bool {
+    matches!(action, Action::Proxy(_) | Action::Redirect(_) | Action::Respond(_))
+}
+
+/// 後のルールの条件の評価に使う変数やヘッダーを変えうるか
+fn changes_state(action: &Action) -> bool {
+    matches!(action, Action::SetVariables(_) | Action::SetHeaders(_) | Action::Jump(_) | Action::Authenticate(_))
+}
+
+fn sorted_keys<V>(map: &std::collections::HashMap<String, V>) -> Vec<&String> {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+    keys
+}
+
+/// RoutingChain のルールを検査する (条件式を解析できないルールは対象外)
+pub fn lint(chain: &RoutingChain) -> Vec<Finding> {
+    let exprs: Vec<Option<Expr>> = chain.rules.iter().map(|rule| match_expr::parse(&rule.match_expr).ok()).collect();
+    let mut findings = Vec::new();
+
+    // 到達しないルールと、前のルールに含まれるルール
+    let mut unconditional = None;
+    // これより前のルールは、変数やヘッダーが変わる前の状態で評価されている
+    let mut barrier = 0;
+    for (j, rule) in chain.rules.iter().enumerate() {
+        if let Some(i) = unconditional {
+            findings.push(Finding {
+                rule: j,
+                code: "unreachable",
+                message: format!("rule {} is never evaluated because rule {} always ends the chain", j, i),
+                related: Some(i),
+            });
+            continue;
+        }
+        let Some(expr) = &exprs[j] else {
+            if changes_state(&rule.action) {
+                barrier = j + 1;
+            }
+            continue;
+        };
+        let shadowing = (barrier..j).find(|&i| {
+            is_terminal(&chain.rules[i].action) && exprs[i].as_ref().is_some_and(|earlier| expr.implies(earlier))
+        });
+        if let Some(i) = shadowing {
+            findings.push(Finding {
+                rule: j,
+                code: "shadowed",
+                message: format!("rule {} is never evaluated because every request it matches also matches rule {}", j, i),
+                related: Some(i),
+            });
+        }
+        if is_terminal(&rule.action) && expr.is_always_true() {
+            unconditional = Some(j);
+        }
+        if changes_state(&rule.action) {
+            barrier = j + 1;
+        }
+    }
+
+    // 変数の読み書き
+    let reads: Vec<Vec<&str>> = exprs
+        .iter()
+        .map(|expr| expr.as_ref().map(|expr| expr.variables().into_iter().collect()).unwrap_or_default())
+        .collect();
+    let mut set_by: BTreeMap<&str, usize> = BTreeMap::new();
+    for (j, rule) in chain.rules.iter().enumerate() {
+        for name in &reads[j] {
+            if !set_by.contains_key(name) {
+                findings.push(Finding {
+                    rule: j,
+                    code: "undefined-variable",
+                    message: format!("variable '{}' is read but not set by any earlier rule", name),
+                    related: None,
+                });
+            }
+        }
+        if let Action::SetVariables(action) = &rule.action {
+            for name in sorted_keys(&action.variables) {
+                if !reads[j + 1..].iter().any(|names| names.contains(&name.as_str())) {
+                    findings.push(Finding {
+                        rule: j,
+                        code: "unused-variable",
+                        message: format!("variable '{}' is not read by any later rule", name),
+                        related: None,
+                    });
+                }
+                set_by.entry(name.as_str()).or_insert(j);
+            }
+        }
+    }
+
+    // ヘッダーの再設定
+    let mut headers_set: BTreeMap<(String, String), usize> = BTreeMap::new();
+    for (j, rule) in chain.rules.iter().enumerate() {
+        let Action::SetHeaders(action) = &rule.action else { continue };
+        for name in sorted_keys(&action.headers) {
+            let key = (action.target.clone(), name.to_ascii_lowercase());
+            if let Some(&i) = headers_set.get(&key) {
+                findings.push(Finding {
+                    rule: j,
+                    code: "duplicate-header",
+                    message: format!("header '{}' on '{}' is already set by rule {}", name, action.target, i),
+                    related: Some(i),
+                });
+            } else {
+                headers_set.insert(key, j);
+            }
+        }
+    }
+
+    findings.sort_by_key(|finding| finding.rule);
+    findings
+}
+
+/// 検査で問題が見つかれば 422 を返す (書き込み時の `?lint=true`)
+pub fn require_clean(chain: &RoutingChain) -> Result<(), ApiError> {
+    let findings = lint(chain);
+    if findings.is_empty() {
+        return Ok(());
+    }
+    Err(ApiError::UnprocessableDetails {
+        message: format!("RoutingChain '{}' has {} lint finding(s)", chain.name, findings.len()),
+        details: json!({ "findings": findings }),
+    })
+}
+
+pub async fn lint_routing_chain(
+    State(state): State<AppState>,
+    Path((realm, name)): Path<(String, String)>,
+) -> Result<Json<LintReport>, ApiError> {
+    let realm = RealmName::parse("realm", &realm)?;
+    let name = RoutingChainName::parse("routingChain", &name)?;
+    let key = Urn::RoutingChain(realm.clone(), name.clone()).key();
+    let chain: RoutingChain = match get_from_etcd(&state, &key).await?.kvs().first() {
+        Some(kv) => serde_json::from_slice(kv.value())?,
+        None => return Err(ApiError::NotFound(format!("RoutingChain '{}' not found in realm '{}'", name, realm))),
+    };
+    Ok(Json(LintReport { findings: lint(&chain) }))
+}
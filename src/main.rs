@@ -1,15 +1,39 @@
+mod acme;
+mod acme_client;
+mod admin;
+mod blob;
+mod ca;
+mod certificate;
 mod db;
+mod dns;
+mod dns_provider;
 mod error;
+mod host_index;
 mod realm;
 mod zone;
 mod virtual_host;
 mod subdomain;
 mod routing_chain;
+mod routing_chain_version;
+mod routing_rule;
 mod hub;
+mod idn;
 mod service;
+mod simulator;
+mod keyring;
+mod lint;
+mod match_expr;
+mod pki;
+mod secret;
+mod urn;
 mod utils;
+mod validation;
+mod zonefile;
 
+use crate::acme::AcmeSettings;
 use crate::db::AppState;
+use crate::dns::DnsSettings;
+use crate::keyring::Keyring;
 use axum::{
     response::{Html, IntoResponse},
     routing::get,
@@ -18,6 +42,7 @@ use axum::{
 use etcd_client::Client;
 use std::env;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use tracing::info;
 
 /// Web UI (index.html, webui.html, webui2.html) を提供するハンドラ
@@ -43,8 +68,33 @@ async fn main() -> anyhow::Result<()> {
     let etcd_client = Client::connect([etcd_endpoints], None).await?;
     info!("Connected to etcd");
 
+    // 秘密情報の開示用トークン
+    let reveal_secrets_token = env::var("REVEAL_SECRETS_TOKEN").ok().filter(|t| !t.is_empty());
+
+    // 管理用エンドポイントの呼び出し用トークン
+    let admin_token = env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
+
+    // 秘密情報を暗号化するマスターキーの読み込み
+    let keyring = Arc::new(Keyring::from_env()?);
+
+    // ACME クライアントの設定
+    let acme = Arc::new(AcmeSettings::from_env()?);
+
+    // DNS 同期の設定
+    let dns = Arc::new(DnsSettings::from_env()?);
+
     // アプリケーションの状態を生成
-    let app_state = AppState { etcd_client };
+    let app_state = AppState { etcd_client, reveal_secrets_token, admin_token, keyring, acme, dns };
+
+    // 旧形式の参照を URN に移行してから、ホスト名の索引を既存のデータから作り直す
+    let startup_state = app_state.clone();
+    tokio::spawn(async move {
+        urn::migrate_task(&startup_state).await;
+        host_index::rebuild_task(startup_state).await;
+    });
+
+    // ACME 証明書の自動更新
+    tokio::spawn(acme::renewal_task(app_state.clone()));
 
     // ルーターの構築
     let app = Router::new()
@@ -55,11 +105,19 @@ async fn main() -> anyhow::Result<()> {
         .route("/webui2.html", get(webui2))
         // API Server
         .nest("/realms", realm::routes()
+            .nest("/{realm}/certificates", certificate::realm_routes())
             .nest("/{realm}/zones", zone::routes())
             .nest("/{realm}/virtual-hosts", virtual_host::routes())
             .nest("/{realm}/routing-chains", routing_chain::routes())
+            .nest("/{realm}/blobs", blob::routes())
             .nest("/{realm}/hubs", hub::routes()
                 .nest("/{hub_name}/services", service::routes())))
+        .nest("/certificates", certificate::routes())
+        .nest("/admin", admin::routes())
+        .route("/lookup/host/{hostname}", get(host_index::lookup_host))
+        .merge(urn::routes())
+        // ACME HTTP-01 チャレンジ
+        .route("/.well-known/acme-challenge/{token}", get(acme::http01_response))
         .with_state(app_state);
 
     // サーバーの起動
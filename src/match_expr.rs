@@ -0,0 +1,744 @@
+//! RoutingChain のルールの条件式 (`match`) の字句解析・構文解析
+//!
+//! ```text
+//! expr      := or
+//! or        := and ("||" and)*
+//! and       := unary ("&&" unary)*
+//! unary     := "!" unary | primary
+//! primary   := "(" expr ")" | "true" | "false" | predicate
+//! predicate := operand ("==" | "!=") operand
+//!            | operand "." function "(" string ")"
+//!            | operand "in" "[" string ("," string)* "]"
+//!            | string "in" collection
+//! operand   := field | string
+//! field     := "request" "." ("host" | "path" | "method" | "scheme" | "clientIp")
+//!            | "request" "." ("headers" | "query" | "cookies") "[" string "]"
+//!            | "variables" "." ident | "variables" "[" string "]"
+//! collection:= "request" "." ("headers" | "query" | "cookies") | "variables"
+//! function  := "startsWith" | "endsWith" | "contains" | "matches" | "inCidr"
+//! ```
+//!
+//! 例: `request.host == "www.example.com" && request.path.startsWith("/api/")`
+//!
+//! 文字列は `"` で囲み、`\"` `\\` `\n` `\t` のエスケープを使える。ヘッダー名は大文字と小文字を区別しない。
+//!
+//! `!` と括弧の入れ子は [`MAX_NESTING`] 段まで、`&&` と `||` は合わせて [`MAX_OPERATORS`] 個までに制限する
+//! (構文木の深さを抑え、解析や評価でスタックを溢れさせないため)。
+use ipnet::IpNet;
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::net::IpAddr;
+
+/// `!` と括弧の入れ子の上限
+pub const MAX_NESTING: usize = 64;
+/// `&&` と `||` の数の上限
+pub const MAX_OPERATORS: usize = 256;
+
+/// [`Expr::implies`] で調べる組み合わせの数の上限
+const IMPLIES_BUDGET: usize = 10_000;
+
+/// 条件式の構文木
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(bool),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    /// `left == right` (`negated` なら `!=`)
+    Compare { left: Operand, negated: bool, right: Operand },
+    /// `target.startsWith("...")` など
+    Call { target: Operand, function: Function },
+    /// `operand in ["a", "b"]`
+    InList { operand: Operand, values: Vec<String> },
+    /// `"name" in request.headers`
+    Has { collection: Collection, key: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Field(Field),
+    Literal(String),
+}
+
+/// リクエストと変数の参照
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Field {
+    Host,
+    Path,
+    Method,
+    Scheme,
+    ClientIp,
+    /// ヘッダー名 (小文字)
+    Header(String),
+    Query(String),
+    Cookie(String),
+    Variable(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collection {
+    Headers,
+    Query,
+    Cookies,
+    Variables,
+}
+
+#[derive(Debug, Clone)]
+pub enum Function {
+    StartsWith(String),
+    EndsWith(String),
+    Contains(String),
+    Matches(Regex),
+    InCidr(IpNet),
+}
+
+/// 条件式を評価する対象のリクエスト (ヘッダー名は小文字)
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    pub host: String,
+    pub path: String,
+    pub method: String,
+    pub scheme: String,
+    pub client_ip: Option<IpAddr>,
+    pub headers: BTreeMap<String, String>,
+    pub query: BTreeMap<String, String>,
+    pub cookies: BTreeMap<String, String>,
+}
+
+impl Expr {
+    /// リクエストと変数に対して評価する。存在しないヘッダーや変数との比較は `!=` だけが真になる
+    pub fn eval(&self, request: &Request, variables: &BTreeMap<String, String>) -> bool {
+        match self {
+            Expr::Literal(value) => *value,
+            Expr::Not(expr) => !expr.eval(request, variables),
+            Expr::And(left, right) => left.eval(request, variables) && right.eval(request, variables),
+            Expr::Or(left, right) => left.eval(request, variables) || right.eval(request, variables),
+            Expr::Compare { left, negated, right } => {
+                let equal = matches!(
+                    (left.value(request, variables), right.value(request, variables)),
+                    (Some(l), Some(r)) if l == r
+                );
+                equal != *negated
+            }
+            Expr::Call { target, function } => match target.value(request, variables) {
+                Some(value) => function.call(&value),
+                None => false,
+            },
+            Expr::InList { operand, values } => {
+                operand.value(request, variables).is_some_and(|value| values.contains(&value))
+            }
+            Expr::Has { collection, key } => match collection {
+                Collection::Headers => request.headers.contains_key(key),
+                Collection::Query => request.query.contains_key(key),
+                Collection::Cookies => request.cookies.contains_key(key),
+                Collection::Variables => variables.contains_key(key),
+            },
+        }
+    }
+}
+
+impl Expr {
+    /// リクエストによらず常に真か (定数だけで判定できる場合に限る)
+    pub fn is_always_true(&self) -> bool {
+        self.constant() == Some(true)
+    }
+
+    fn constant(&self) -> Option<bool> {
+        match self {
+            Expr::Literal(value) => Some(*value),
+            Expr::Not(expr) => expr.constant().map(|value| !value),
+            Expr::And(left, right) => match (left.constant(), right.constant()) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Expr::Or(left, right) => match (left.constant(), right.constant()) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// 読み取る変数の名前
+    pub fn variables(&self) -> BTreeSet<&str> {
+        let mut names = BTreeSet::new();
+        self.collect_variables(&mut names);
+        names
+    }
+
+    fn collect_variables<'a>(&'a self, names: &mut BTreeSet<&'a str>) {
+        let mut operand = |operand: &'a Operand| {
+            if let Operand::Field(Field::Variable(name)) = operand {
+                names.insert(name.as_str());
+            }
+        };
+        match self {
+            Expr::Literal(_) => {}
+            Expr::Not(expr) => expr.collect_variables(names),
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                left.collect_variables(names);
+                right.collect_variables(names);
+            }
+            Expr::Compare { left, right, .. } => {
+                operand(left);
+                operand(right);
+            }
+            Expr::Call { target, .. } => operand(target),
+            Expr::InList { operand: target, .. } => operand(target),
+            Expr::Has { collection: Collection::Variables, key } => {
+                names.insert(key.as_str());
+            }
+            Expr::Has { .. } => {}
+        }
+    }
+
+    /// `self` が真なら `other` も必ず真になるか
+    ///
+    /// 構文上の包含 (`&&` / `||` の分解、同じ比較、前方一致の包含など) だけを調べるため、`false` は「含まれない」ことを意味しない。
+    /// `&&` / `||` の組み合わせで調べる数は指数的に増えるため、[`IMPLIES_BUDGET`] 回を超えたら `false` とする。
+    pub fn implies(&self, other: &Expr) -> bool {
+        let mut budget = IMPLIES_BUDGET;
+        self.implies_within(other, &mut budget)
+    }
+
+    fn implies_within(&self, other: &Expr, budget: &mut usize) -> bool {
+        // 結果を否定せずに組み合わせるので、打ち切って `false` にしても誤って `true` になることはない
+        if *budget == 0 {
+            return false;
+        }
+        *budget -= 1;
+        if other.is_always_true() || self == other {
+            return true;
+        }
+        match (self, other) {
+            (_, Expr::And(left, right)) => self.implies_within(left, budget) && self.implies_within(right, budget),
+            (Expr::Or(left, right), _) => left.implies_within(other, budget) && right.implies_within(other, budget),
+            (Expr::And(left, right), _) if left.implies_within(other, budget) || right.implies_within(other, budget) => true,
+            (_, Expr::Or(left, right)) => self.implies_within(left, budget) || self.implies_within(right, budget),
+            _ => self.implies_predicate(other),
+        }
+    }
+
+    fn implies_predicate(&self, other: &Expr) -> bool {
+        let (Some((field, value)), Some(target)) = (self.condition(), other.subject()) else {
+            return false;
+        };
+        if field != target {
+            return false;
+        }
+        match (value, other) {
+            // `field == "x"` ならその値についての条件をそのまま評価できる
+            (Condition::Equals(value), _) => {
+                let request = Request::default();
+                let variables = BTreeMap::new();
+                other.substitute(value).is_some_and(|expr| expr.eval(&request, &variables))
+            }
+            (Condition::OneOf(values), Expr::InList { values: allowed, .. }) => values.iter().all(|v| allowed.contains(v)),
+            (Condition::Prefix(prefix), Expr::Call { function: Function::StartsWith(p), .. }) => prefix.starts_with(p.as_str()),
+            (Condition::Prefix(prefix), Expr::Call { function: Function::Contains(p), .. }) => prefix.contains(p.as_str()),
+            (Condition::Suffix(suffix), Expr::Call { function: Function::EndsWith(s), .. }) => suffix.ends_with(s.as_str()),
+            (Condition::Suffix(suffix), Expr::Call { function: Function::Contains(s), .. }) => suffix.contains(s.as_str()),
+            (Condition::Substring(part), Expr::Call { function: Function::Contains(p), .. }) => part.contains(p.as_str()),
+            _ => false,
+        }
+    }
+
+    /// 単一のフィールドに対する条件として表す
+    fn condition(&self) -> Option<(&Field, Condition<'_>)> {
+        match self {
+            Expr::Compare { left: Operand::Field(field), negated: false, right: Operand::Literal(value) }
+            | Expr::Compare { left: Operand::Literal(value), negated: false, right: Operand::Field(field) } => {
+                Some((field, Condition::Equals(value)))
+            }
+            Expr::InList { operand: Operand::Field(field), values } => Some((field, Condition::OneOf(values))),
+            Expr::Call { target: Operand::Field(field), function } => match function {
+                Function::StartsWith(prefix) => Some((field, Condition::Prefix(prefix))),
+                Function::EndsWith(suffix) => Some((field, Condition::Suffix(suffix))),
+                Function::Contains(part) => Some((field, Condition::Substring(part))),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// 単一のフィールドだけを参照する述語ならそのフィールド
+    fn subject(&self) -> Option<&Field> {
+        match self {
+            Expr::Compare { left: Operand::Field(field), right: Operand::Literal(_), .. }
+            | Expr::Compare { left: Operand::Literal(_), right: Operand::Field(field), .. }
+            | Expr::Call { target: Operand::Field(field), .. }
+            | Expr::InList { operand: Operand::Field(field), .. } => Some(field),
+            _ => None,
+        }
+    }
+
+    /// 単一のフィールドを参照する述語で、フィールドを値に置き換える
+    fn substitute(&self, value: &str) -> Option<Expr> {
+        let literal = |operand: &Operand| match operand {
+            Operand::Field(_) => Operand::Literal(value.to_string()),
+            Operand::Literal(_) => operand.clone(),
+        };
+        match self {
+            Expr::Compare { left, negated, right } => {
+                Some(Expr::Compare { left: literal(left), negated: *negated, right: literal(right) })
+            }
+            Expr::Call { target, function } => Some(Expr::Call { target: literal(target), function: function.clone() }),
+            Expr::InList { operand, values } => Some(Expr::InList { operand: literal(operand), values: values.clone() }),
+            _ => None,
+        }
+    }
+}
+
+/// 単一のフィールドに対する条件
+enum Condition<'a> {
+    Equals(&'a str),
+    OneOf(&'a [String]),
+    Prefix(&'a str),
+    Suffix(&'a str),
+    Substring(&'a str),
+}
+
+impl Operand {
+    fn value(&self, request: &Request, variables: &BTreeMap<String, String>) -> Option<String> {
+        match self {
+            Operand::Literal(value) => Some(value.clone()),
+            Operand::Field(field) => match field {
+                Field::Host => Some(request.host.clone()),
+                Field::Path => Some(request.path.clone()),
+                Field::Method => Some(request.method.clone()),
+                Field::Scheme => Some(request.scheme.clone()),
+                Field::ClientIp => request.client_ip.map(|ip| ip.to_string()),
+                Field::Header(name) => request.headers.get(name).cloned(),
+                Field::Query(name) => request.query.get(name).cloned(),
+                Field::Cookie(name) => request.cookies.get(name).cloned(),
+                Field::Variable(name) => variables.get(name).cloned(),
+            },
+        }
+    }
+}
+
+impl Function {
+    fn call(&self, value: &str) -> bool {
+        match self {
+            Function::StartsWith(prefix) => value.starts_with(prefix.as_str()),
+            Function::EndsWith(suffix) => value.ends_with(suffix.as_str()),
+            Function::Contains(part) => value.contains(part.as_str()),
+            Function::Matches(regex) => regex.is_match(value),
+            Function::InCidr(net) => value.parse::<IpAddr>().is_ok_and(|ip| net.contains(&ip)),
+        }
+    }
+}
+
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Function::StartsWith(a), Function::StartsWith(b))
+            | (Function::EndsWith(a), Function::EndsWith(b))
+            | (Function::Contains(a), Function::Contains(b)) => a == b,
+            (Function::Matches(a), Function::Matches(b)) => a.as_str() == b.as_str(),
+            (Function::InCidr(a), Function::InCidr(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// 構文エラー (列は 1 始まりの文字位置)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub column: usize,
+    pub expected: String,
+    pub found: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: expected {}, found {}", self.column, self.expected, self.found)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Eq,
+    Ne,
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Dot,
+    Comma,
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::Str(value) => write!(f, "string {:?}", value),
+            Token::Eq => f.write_str("'=='"),
+            Token::Ne => f.write_str("'!='"),
+            Token::And => f.write_str("'&&'"),
+            Token::Or => f.write_str("'||'"),
+            Token::Not => f.write_str("'!'"),
+            Token::LParen => f.write_str("'('"),
+            Token::RParen => f.write_str("')'"),
+            Token::LBracket => f.write_str("'['"),
+            Token::RBracket => f.write_str("']'"),
+            Token::Dot => f.write_str("'.'"),
+            Token::Comma => f.write_str("','"),
+            Token::End => f.write_str("end of expression"),
+        }
+    }
+}
+
+/// 字句解析 (各トークンと開始列の組を返す)
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let column = i + 1;
+        let c = chars[i];
+        let two = |next: char| chars.get(i + 1) == Some(&next);
+        let token = match c {
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '=' if two('=') => Token::Eq,
+            '!' if two('=') => Token::Ne,
+            '&' if two('&') => Token::And,
+            '|' if two('|') => Token::Or,
+            '!' => Token::Not,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '.' => Token::Dot,
+            ',' => Token::Comma,
+            '"' => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(error(chars.len() + 1, "closing '\"'", "end of expression")),
+                        Some('"') => break,
+                        Some('\\') => {
+                            let escaped = match chars.get(i + 1) {
+                                Some('"') => '"',
+                                Some('\\') => '\\',
+                                Some('n') => '\n',
+                                Some('t') => '\t',
+                                other => {
+                                    let found = other.map_or("end of expression".to_string(), |c| format!("'\\{}'", c));
+                                    return Err(error(i + 1, "escape sequence (\\\", \\\\, \\n or \\t)", &found));
+                                }
+                            };
+                            value.push(escaped);
+                            i += 2;
+                        }
+                        Some(c) => {
+                            value.push(*c);
+                            i += 1;
+                        }
+                    }
+                }
+                tokens.push((Token::Str(value), column));
+                i += 1;
+                continue;
+            }
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                let start = i;
+                while chars.get(i).is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    i += 1;
+                }
+                tokens.push((Token::Ident(chars[start..i].iter().collect()), column));
+                continue;
+            }
+            _ => return Err(error(column, "an operator, identifier or string", &format!("'{}'", c))),
+        };
+        i += if matches!(token, Token::Eq | Token::Ne | Token::And | Token::Or) { 2 } else { 1 };
+        tokens.push((token, column));
+    }
+    tokens.push((Token::End, chars.len() + 1));
+    Ok(tokens)
+}
+
+fn error(column: usize, expected: &str, found: &str) -> ParseError {
+    ParseError { column, expected: expected.to_string(), found: found.to_string() }
+}
+
+/// 条件式を解析する
+pub fn parse(source: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser { tokens: tokenize(source)?, pos: 0, depth: 0, operators: 0 };
+    let expr = parser.or()?;
+    parser.expect(Token::End, "'&&', '||' or end of expression")?;
+    Ok(expr)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    // `!` と括弧の現在の入れ子の深さ
+    depth: usize,
+    // これまでに読んだ `&&` と `||` の数
+    operators: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn next(&mut self) -> (Token, usize) {
+        let token = self.tokens[self.pos].clone();
+        if token.0 != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    /// 現在のトークンで構文エラーを作る
+    fn unexpected(&self, expected: &str) -> ParseError {
+        let (token, column) = &self.tokens[self.pos];
+        error(*column, expected, &token.to_string())
+    }
+
+    fn expect(&mut self, token: Token, expected: &str) -> Result<(), ParseError> {
+        if *self.peek() != token {
+            return Err(self.unexpected(expected));
+        }
+        self.next();
+        Ok(())
+    }
+
+    fn string(&mut self, expected: &str) -> Result<(String, usize), ParseError> {
+        match self.tokens[self.pos].clone() {
+            (Token::Str(value), column) => {
+                self.next();
+                Ok((value, column))
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn ident(&mut self, expected: &str) -> Result<String, ParseError> {
+        match self.peek().clone() {
+            Token::Ident(name) => {
+                self.next();
+                Ok(name)
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    /// `&&` / `||` を読む (上限を超えたらエラー)
+    fn operator(&mut self) -> Result<(), ParseError> {
+        if self.operators == MAX_OPERATORS {
+            return Err(self.unexpected(&format!("at most {} '&&' and '||' operators", MAX_OPERATORS)));
+        }
+        self.operators += 1;
+        self.next();
+        Ok(())
+    }
+
+    /// `!` や括弧の内側を `parse` で読む (入れ子が上限を超えたらエラー)
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Expr, ParseError>) -> Result<Expr, ParseError> {
+        if self.depth == MAX_NESTING {
+            return Err(self.unexpected(&format!("at most {} nested '!' and '('", MAX_NESTING)));
+        }
+        self.depth += 1;
+        self.next();
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.and()?;
+        while *self.peek() == Token::Or {
+            self.operator()?;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.unary()?;
+        while *self.peek() == Token::And {
+            self.operator()?;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if *self.peek() == Token::Not {
+            return Ok(Expr::Not(Box::new(self.nested(Self::unary)?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        match self.peek().clone() {
+            Token::LParen => self.nested(|parser| {
+                let expr = parser.or()?;
+                parser.expect(Token::RParen, "')'")?;
+                Ok(expr)
+            }),
+            Token::Ident(name) if name == "true" || name == "false" => {
+                self.next();
+                Ok(Expr::Literal(name == "true"))
+            }
+            Token::Ident(_) | Token::Str(_) => self.predicate(),
+            _ => Err(self.unexpected("'(', '!', 'true', 'false', a field or a string")),
+        }
+    }
+
+    fn predicate(&mut self) -> Result<Expr, ParseError> {
+        let left = self.operand()?;
+        match self.peek().clone() {
+            Token::Eq | Token::Ne => {
+                let negated = self.next().0 == Token::Ne;
+                Ok(Expr::Compare { left, negated, right: self.operand()? })
+            }
+            Token::Dot => {
+                self.next();
+                let function = self.function()?;
+                Ok(Expr::Call { target: left, function })
+            }
+            Token::Ident(name) if name == "in" => {
+                self.next();
+                if *self.peek() == Token::LBracket {
+                    return Ok(Expr::InList { operand: left, values: self.list()? });
+                }
+                match left {
+                    Operand::Literal(key) => {
+                        let collection = self.collection()?;
+                        let key = if collection == Collection::Headers { key.to_ascii_lowercase() } else { key };
+                        Ok(Expr::Has { collection, key })
+                    }
+                    Operand::Field(_) => Err(self.unexpected("'['")),
+                }
+            }
+            _ => Err(self.unexpected("'==', '!=', 'in' or '.'")),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, ParseError> {
+        match self.peek().clone() {
+            Token::Str(value) => {
+                self.next();
+                Ok(Operand::Literal(value))
+            }
+            Token::Ident(name) if name == "request" => {
+                self.next();
+                self.expect(Token::Dot, "'.'")?;
+                let field = match self.ident("a request field")?.as_str() {
+                    "host" => Field::Host,
+                    "path" => Field::Path,
+                    "method" => Field::Method,
+                    "scheme" => Field::Scheme,
+                    "clientIp" => Field::ClientIp,
+                    "headers" => Field::Header(self.subscript()?.to_ascii_lowercase()),
+                    "query" => Field::Query(self.subscript()?),
+                    "cookies" => Field::Cookie(self.subscript()?),
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.unexpected(
+                            "'host', 'path', 'method', 'scheme', 'clientIp', 'headers', 'query' or 'cookies'",
+                        ));
+                    }
+                };
+                Ok(Operand::Field(field))
+            }
+            Token::Ident(name) if name == "variables" => {
+                self.next();
+                let name = match self.peek() {
+                    Token::Dot => {
+                        self.next();
+                        self.ident("a variable name")?
+                    }
+                    _ => self.subscript_or("'.' or '['")?,
+                };
+                Ok(Operand::Field(Field::Variable(name)))
+            }
+            _ => Err(self.unexpected("'request', 'variables' or a string")),
+        }
+    }
+
+    /// `["name"]`
+    fn subscript(&mut self) -> Result<String, ParseError> {
+        self.subscript_or("'['")
+    }
+
+    fn subscript_or(&mut self, expected: &str) -> Result<String, ParseError> {
+        self.expect(Token::LBracket, expected)?;
+        let (key, _) = self.string("a string")?;
+        self.expect(Token::RBracket, "']'")?;
+        Ok(key)
+    }
+
+    fn collection(&mut self) -> Result<Collection, ParseError> {
+        match self.ident("'request' or 'variables'")?.as_str() {
+            "variables" => Ok(Collection::Variables),
+            "request" => {
+                self.expect(Token::Dot, "'.'")?;
+                match self.ident("'headers', 'query' or 'cookies'")?.as_str() {
+                    "headers" => Ok(Collection::Headers),
+                    "query" => Ok(Collection::Query),
+                    "cookies" => Ok(Collection::Cookies),
+                    _ => {
+                        self.pos -= 1;
+                        Err(self.unexpected("'headers', 'query' or 'cookies'"))
+                    }
+                }
+            }
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected("'request' or 'variables'"))
+            }
+        }
+    }
+
+    fn list(&mut self) -> Result<Vec<String>, ParseError> {
+        self.expect(Token::LBracket, "'['")?;
+        let mut values = vec![self.string("a string")?.0];
+        while *self.peek() == Token::Comma {
+            self.next();
+            values.push(self.string("a string")?.0);
+        }
+        self.expect(Token::RBracket, "',' or ']'")?;
+        Ok(values)
+    }
+
+    fn function(&mut self) -> Result<Function, ParseError> {
+        const EXPECTED: &str = "'startsWith', 'endsWith', 'contains', 'matches' or 'inCidr'";
+        let name = self.ident(EXPECTED)?;
+        self.expect(Token::LParen, "'('")?;
+        let (argument, column) = self.string("a string")?;
+        let function = match name.as_str() {
+            "startsWith" => Function::StartsWith(argument),
+            "endsWith" => Function::EndsWith(argument),
+            "contains" => Function::Contains(argument),
+            "matches" => Function::Matches(
+                Regex::new(&argument).map_err(|_| error(column, "a valid regular expression", &format!("{:?}", argument)))?,
+            ),
+            "inCidr" => Function::InCidr(
+                argument.parse().map_err(|_| error(column, "a CIDR block such as \"10.0.0.0/8\"", &format!("{:?}", argument)))?,
+            ),
+            _ => {
+                return Err(error(self.tokens[self.pos - 3].1, EXPECTED, &format!("'{}'", name)));
+            }
+        };
+        self.expect(Token::RParen, "')'")?;
+        Ok(function)
+    }
+}
@@ -0,0 +1,195 @@
+use crate::error::ApiError;
+use crate::idn;
+use crate::secret::Secret;
+use ring::{
+    rand::SystemRandom,
+    signature::{self, EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair},
+};
+use x509_parser::{extensions::GeneralName, prelude::*};
+
+/// PEM から取り出した DER 形式の証明書
+pub struct Certificate {
+    der: Vec<u8>,
+}
+
+impl Certificate {
+    pub fn parsed(&self) -> X509Certificate<'_> {
+        // `parse_certificates` で検証済みのため失敗しない
+        X509Certificate::from_der(&self.der).expect("certificate was validated on parse").1
+    }
+}
+
+/// 検証失敗を 422 として返す。メッセージには対象フィールドと失敗したチェックを含める。
+fn invalid(field: &str, check: &str, detail: impl std::fmt::Display) -> ApiError {
+    ApiError::Unprocessable(format!("{}: {} check failed: {}", field, check, detail))
+}
+
+/// PEM 文字列に含まれる証明書をすべて読み込む (1 つ以上必要)
+pub fn parse_certificates(field: &str, pem_text: &str) -> Result<Vec<Certificate>, ApiError> {
+    let blocks = ::pem::parse_many(pem_text).map_err(|e| invalid(field, "PEM parse", e))?;
+    if blocks.is_empty() {
+        return Err(invalid(field, "PEM parse", "no PEM block found"));
+    }
+    blocks
+        .into_iter()
+        .enumerate()
+        .map(|(i, block)| {
+            if block.tag() != "CERTIFICATE" {
+                return Err(invalid(field, "PEM parse", format!("block #{} is '{}', expected 'CERTIFICATE'", i, block.tag())));
+            }
+            X509Certificate::from_der(block.contents())
+                .map_err(|e| invalid(field, "certificate parse", format!("block #{}: {}", i, e)))?;
+            Ok(Certificate { der: block.into_contents() })
+        })
+        .collect()
+}
+
+/// 秘密鍵 (PKCS#8, PKCS#1 RSA, SEC1 EC) を読み込み、PKCS#8 (DER) と対応する公開鍵のバイト列を返す
+pub fn parse_private_key(field: &str, key: &Secret) -> Result<(Vec<u8>, Vec<u8>), ApiError> {
+    let block = ::pem::parse(key.expose()).map_err(|e| invalid(field, "PEM parse", e))?;
+    let der = block.contents();
+    let candidates = match block.tag() {
+        "PRIVATE KEY" => vec![der.to_vec()],
+        "RSA PRIVATE KEY" => vec![pkcs1_to_pkcs8(der)],
+        "EC PRIVATE KEY" => vec![sec1_to_pkcs8(der, OID_P256), sec1_to_pkcs8(der, OID_P384)],
+        other => return Err(invalid(field, "private key parse", format!("unsupported PEM block '{}'", other))),
+    };
+    candidates
+        .into_iter()
+        .find_map(|pkcs8| pkcs8_public_key(&pkcs8).map(|public_key| (pkcs8, public_key)))
+        .ok_or_else(|| invalid(field, "private key parse", "unsupported or malformed private key"))
+}
+
+fn pkcs8_public_key(der: &[u8]) -> Option<Vec<u8>> {
+    let rng = SystemRandom::new();
+    for alg in [&signature::ECDSA_P256_SHA256_ASN1_SIGNING, &signature::ECDSA_P384_SHA384_ASN1_SIGNING] {
+        if let Ok(k) = EcdsaKeyPair::from_pkcs8(alg, der, &rng) {
+            return Some(k.public_key().as_ref().to_vec());
+        }
+    }
+    if let Ok(k) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der) {
+        return Some(k.public_key().as_ref().to_vec());
+    }
+    RsaKeyPair::from_pkcs8(der).ok().map(|k| k.public_key().as_ref().to_vec())
+}
+
+// rsaEncryption, id-ecPublicKey と名前付き曲線の OID (DER エンコード済み)
+const OID_RSA_ENCRYPTION: &[u8] = &[0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_P256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_P384: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22];
+
+/// PKCS#1 (RFC 8017) の RSAPrivateKey を PKCS#8 の PrivateKeyInfo で包む
+fn pkcs1_to_pkcs8(pkcs1: &[u8]) -> Vec<u8> {
+    wrap_pkcs8(&[OID_RSA_ENCRYPTION, &[0x05, 0x00]].concat(), pkcs1)
+}
+
+/// SEC1 (RFC 5915) の ECPrivateKey を PKCS#8 の PrivateKeyInfo で包む
+fn sec1_to_pkcs8(sec1: &[u8], curve_oid: &[u8]) -> Vec<u8> {
+    wrap_pkcs8(&[OID_EC_PUBLIC_KEY, curve_oid].concat(), sec1)
+}
+
+fn wrap_pkcs8(algorithm: &[u8], private_key: &[u8]) -> Vec<u8> {
+    let algorithm = der_tlv(0x30, algorithm);
+    let private_key = der_tlv(0x04, private_key);
+    der_tlv(0x30, &[&[0x02, 0x01, 0x00][..], &algorithm, &private_key].concat())
+}
+
+fn der_tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len.to_be_bytes().into_iter().skip_while(|b| *b == 0).collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// 秘密鍵が証明書の公開鍵と対応していることを確認する
+pub fn check_key_matches(key_field: &str, key: &Secret, cert: &Certificate) -> Result<(), ApiError> {
+    let (_, public_key) = parse_private_key(key_field, key)?;
+    if public_key.as_slice() != cert.parsed().public_key().subject_public_key.data.as_ref() {
+        return Err(invalid(key_field, "key match", "private key does not match the certificate's public key"));
+    }
+    Ok(())
+}
+
+/// 証明書チェーンがリーフから順に並び、各証明書が次の証明書によって署名されていることを確認する
+pub fn check_chain(field: &str, chain: &[Certificate]) -> Result<(), ApiError> {
+    for (i, pair) in chain.windows(2).enumerate() {
+        let (cert, issuer) = (pair[0].parsed(), pair[1].parsed());
+        if cert.issuer() != issuer.subject() {
+            return Err(invalid(
+                field,
+                "chain order",
+                format!("certificate #{} was issued by '{}', but certificate #{} is '{}'", i, cert.issuer(), i + 1, issuer.subject()),
+            ));
+        }
+        cert.verify_signature(Some(issuer.public_key()))
+            .map_err(|e| invalid(field, "chain signature", format!("certificate #{} is not signed by certificate #{}: {}", i, i + 1, e)))?;
+    }
+    Ok(())
+}
+
+/// CA 証明書であることを確認する
+pub fn check_ca(field: &str, cert: &Certificate) -> Result<(), ApiError> {
+    if !cert.parsed().is_ca() {
+        return Err(invalid(field, "CA", "certificate does not have basicConstraints CA:TRUE"));
+    }
+    Ok(())
+}
+
+/// 証明書の SAN (DNS 名) の一覧
+pub fn dns_names(cert: &X509Certificate<'_>) -> Vec<String> {
+    cert.subject_alternative_name()
+        .ok()
+        .flatten()
+        .map(|san| {
+            san.value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(dns) => Some(dns.to_string()),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// SAN のパターンがホスト名に一致するか (`*.example.com` は 1 ラベル分のみ一致し、国際化ドメイン名は A-label で比較する)
+pub fn san_matches(pattern: &str, hostname: &str) -> bool {
+    let pattern = idn::canonical(pattern).to_ascii_lowercase();
+    let hostname = idn::canonical(hostname).to_ascii_lowercase();
+    if pattern == hostname {
+        return true;
+    }
+    match (pattern.strip_prefix("*."), hostname.split_once('.')) {
+        (Some(suffix), Some((label, rest))) => !label.is_empty() && label != "*" && suffix == rest,
+        _ => false,
+    }
+}
+
+/// リーフ証明書の SAN がホスト名をカバーしていることを確認する
+pub fn check_covers(field: &str, cert: &Certificate, hostname: &str) -> Result<(), ApiError> {
+    let names = dns_names(&cert.parsed());
+    if !names.iter().any(|name| san_matches(name, hostname)) {
+        return Err(invalid(field, "SAN", format!("'{}' is not covered by the certificate's SANs {:?}", hostname, names)));
+    }
+    Ok(())
+}
+
+/// サーバー証明書 (チェーン) と秘密鍵の組をまとめて検証する
+pub fn check_server_certificate(cert_field: &str, chain: &[Certificate], key_field: &str, key: Option<&Secret>, hostname: &str) -> Result<(), ApiError> {
+    check_chain(cert_field, chain)?;
+    let leaf = &chain[0];
+    match key {
+        Some(key) => check_key_matches(key_field, key, leaf)?,
+        None => return Err(invalid(key_field, "key match", "a private key is required with the certificate")),
+    }
+    check_covers(cert_field, leaf, hostname)
+}
@@ -1,6 +1,11 @@
+use crate::ca;
 use crate::db::{AppState, REALM_PREFIX};
 use crate::error::ApiError;
-use crate::utils::get_from_etcd;
+use crate::host_index::IndexedWrite;
+use crate::pki;
+use crate::secret::{Reveal, Secret, SecretFields};
+use crate::utils::{decode_children, get_secret_document, get_from_etcd};
+use crate::validation::{self, RealmName};
 use axum::{
     extract::{Path, State},
     routing::get,
@@ -16,8 +21,14 @@ pub struct Realm {
     pub title: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    // 作成時に省略すると Realm CA が生成される。更新時に省略すると保存済みの値が維持される
+    #[serde(default)]
     pub cacert: String,
-    pub signing_key: String,
+    // 秘密情報: レスポンスでは既定で伏せられ、更新時に省略すると保存済みの値が維持される
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_key: Option<Secret>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<Secret>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub session_timeout: Option<i64>,
     #[serde(default)]
@@ -27,6 +38,33 @@ pub struct Realm {
     pub disabled: bool,
 }
 
+impl SecretFields for Realm {
+    fn redact(&mut self) {
+        self.ca_key = None;
+        self.signing_key = None;
+    }
+
+    fn retain_secrets(&mut self, stored: &Self) {
+        if self.ca_key.is_none() {
+            self.ca_key = stored.ca_key.clone();
+        }
+        if self.signing_key.is_none() {
+            self.signing_key = stored.signing_key.clone();
+        }
+    }
+
+    fn secrets_mut(&mut self) -> Vec<&mut Secret> {
+        self.ca_key.iter_mut().chain(self.signing_key.iter_mut()).collect()
+    }
+}
+
+/// Realm を取得し、秘密情報を復号して返す (存在しなければ 404)
+pub async fn load_realm(state: &AppState, name: &str) -> Result<Realm, ApiError> {
+    get_secret_document(state, &realm_key(&RealmName::parse("realm", name)?))
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Realm '{}' not found.", name)))
+}
+
 /// Realm関連のエンドポイントをまとめたルーターを返す
 pub fn routes() -> Router<AppState> {
     Router::new()
@@ -34,65 +72,100 @@ pub fn routes() -> Router<AppState> {
         .route("/{realm}", get(get_realm).delete(delete_realm))
 }
 
-fn realm_key(name: &str) -> String {
+pub fn realm_key(name: &RealmName) -> String {
     format!("{}{}", REALM_PREFIX, name)
 }
 
+/// CA 証明書が PEM として読み込め、CA として使えることを確認する (CA 鍵があれば対応も確認する)
+fn validate_certificates(realm: &Realm) -> Result<(), ApiError> {
+    let certs = pki::parse_certificates("cacert", &realm.cacert)?;
+    pki::check_ca("cacert", &certs[0])?;
+    match &realm.ca_key {
+        Some(ca_key) => pki::check_key_matches("caKey", ca_key, &certs[0]),
+        None => Ok(()),
+    }
+}
+
 /// GET /realms
-async fn list_realms(State(state): State<AppState>) -> Result<Json<Vec<Realm>>, ApiError> {
+async fn list_realms(State(state): State<AppState>, reveal: Reveal) -> Result<Json<Vec<Realm>>, ApiError> {
     let mut client = state.etcd_client.clone();
     let resp = client.get(REALM_PREFIX, Some(etcd_client::GetOptions::new().with_prefix())).await?;
-    let realms = resp
-        .kvs()
-        .iter()
-        .filter_map(|kv| serde_json::from_slice(kv.value()).ok())
-        .collect();
-    Ok(Json(realms))
+    let realms = decode_children(&state, REALM_PREFIX, resp.kvs());
+    Ok(Json(reveal.apply_all(realms)))
 }
 
 /// POST /realms
 async fn add_realm(
     State(state): State<AppState>,
-    Json(realm): Json<Realm>,
+    reveal: Reveal,
+    Json(mut realm): Json<Realm>,
 ) -> Result<Json<Realm>, ApiError> {
     let mut client = state.etcd_client.clone();
-    let key = realm_key(&realm.name).clone();
+    let key = realm_key(&RealmName::parse("name", &realm.name)?);
 
+    #[allow(clippy::unnecessary_first_then_check)]
     if get_from_etcd(&state, &key).await?.kvs().first().is_some() {
         return Err(ApiError::Conflict(format!("Realm '{}' already exists.", realm.name)))
 
     }
+    if realm.signing_key.is_none() {
+        return Err(validation::invalid("signingKey", format!("realm '{}' requires a signingKey", realm.name)));
+    }
+    if realm.cacert.is_empty() && realm.ca_key.is_none() {
+        let (cacert, ca_key) = ca::generate_ca(&realm.name)?;
+        realm.cacert = cacert;
+        realm.ca_key = Some(ca_key);
+    }
+    validate_certificates(&realm)?;
 
-    let value = serde_json::to_vec(&realm)?;
+    let value = state.keyring.encode(&realm)?;
     client.put(key, value, None).await?;
-    Ok(Json(realm))
+    Ok(Json(reveal.apply(realm)))
 }
 
 /// PUT /realms
 async fn update_realm(
     State(state): State<AppState>,
-    Json(realm): Json<Realm>,
+    reveal: Reveal,
+    Json(mut realm): Json<Realm>,
 ) -> Result<Json<Realm>, ApiError> {
-    let key = realm_key(&realm.name);
-    let value = serde_json::to_vec(&realm)?;    let mut client = state.etcd_client.clone();
+    let key = realm_key(&RealmName::parse("name", &realm.name)?);
+    if let Some(stored) = get_secret_document::<Realm>(&state, &key).await? {
+        // CA 証明書を差し替えて CA 鍵を指定しなかった場合は、古い CA 鍵を引き継がない
+        let ca_replaced = !realm.cacert.is_empty() && realm.cacert != stored.cacert && realm.ca_key.is_none();
+        if realm.cacert.is_empty() {
+            realm.cacert = stored.cacert.clone();
+        }
+        realm.retain_secrets(&stored);
+        if ca_replaced {
+            realm.ca_key = None;
+        }
+    }
+    if realm.signing_key.is_none() {
+        return Err(validation::invalid("signingKey", format!("realm '{}' requires a signingKey", realm.name)));
+    }
+    validate_certificates(&realm)?;
+    let value = state.keyring.encode(&realm)?;
+    let mut client = state.etcd_client.clone();
 
 
     client.put(key, value, None).await?;
-    Ok(Json(realm))
+    Ok(Json(reveal.apply(realm)))
 }
 
 /// GET /realms/{realm}
 async fn get_realm(
     State(state): State<AppState>,
     Path(name): Path<String>,
+    reveal: Reveal,
 ) -> Result<Json<Realm>, ApiError> {
-    let key = realm_key(&name);
+    let key = realm_key(&RealmName::parse("realm", &name)?);
     let resp = get_from_etcd(&state, &key).await?;
 
     if let Some(kv) = resp.kvs().first() {
-        let realm = serde_json::from_slice(kv.value())?;
+        let realm = state.keyring.decode(kv.value())?;
         
-        Ok(Json(realm))
+        Ok(Json(reveal.apply(realm)))
     } else {
         Err(ApiError::NotFound(format!("Realm '{}' not found.", name)))
     }
@@ -102,19 +175,20 @@ async fn get_realm(
 async fn delete_realm(
     State(state): State<AppState>,
     Path(name): Path<String>,
+    reveal: Reveal,
 ) -> Result<Json<Realm>, ApiError> {
-    let mut client = state.etcd_client.clone();
-    let key = realm_key(&name);
-
-    // 削除する前に値を取得するために GetOptions を使うこともできますが、
-    // トランザクション不使用の要件とシンプルさから、delete操作にprev_kvを要求します。
-    let opts = etcd_client::DeleteOptions::new().with_prev_key();
-    let resp = client.delete(key, Some(opts)).await?;
+    let realm_name = RealmName::parse("realm", &name)?;
+    let key = realm_key(&realm_name);
+    let Some(kv) = get_from_etcd(&state, &key).await?.kvs().first().cloned() else {
+        return Err(ApiError::NotFound(format!("Realm '{}' not found.", name)));
+    };
+    let realm: Realm = state.keyring.decode(kv.value())?;
 
-    if let Some(kv) = resp.prev_kvs().first() {
-        let realm = serde_json::from_slice(kv.value())?;
-        Ok(Json(realm))
-    } else {
-        Err(ApiError::NotFound(format!("Realm '{}' not found.", name)))
-    }
+    // Realm とともに、Realm 内のリソースが所有する索引のエントリも解放する
+    let mut write = IndexedWrite::new();
+    write.require_revision(&key, kv.mod_revision());
+    write.delete(&key);
+    write.release_realm(&state, &realm_name).await?;
+    write.commit(&state).await?;
+    Ok(Json(reveal.apply(realm)))
 }
\ No newline at end of file
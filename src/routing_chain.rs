@@ -1,19 +1,33 @@
-use crate::db::AppState;
+use crate::blob;
+use crate::db::{AppState, REALM_PREFIX};
 use crate::error::ApiError;
+use crate::host_index::{self, IndexedWrite};
+use crate::lint;
+use crate::match_expr;
+use crate::realm;
+use crate::routing_chain_version;
+use crate::routing_rule;
+use crate::simulator::{self, SimulatedRequest};
+use crate::urn::Urn;
 use crate::utils::get_from_etcd;
+use crate::validation::{self, BlobName, RealmName, RoutingChainName, VirtualHostName};
+use crate::virtual_host;
 use axum::{
-    extract::{Path, State},
-    routing::get,
+    extract::{Path, Query, State},
+    routing::{get, post},
     Json, Router,
 };
+use etcd_client::GetOptions;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct RoutingChain {
     pub name: String,
+    // Realm の URN (読み取り専用)
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub realm: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -23,16 +37,68 @@ pub struct RoutingChain {
     pub description: Option<String>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub rules: Vec<Rule>,
+    // 更新のたびに実行するテストケース (更新で省略すると保存されているものを引き継ぐ)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tests: Option<Vec<TestCase>>,
+    // 最新の版の番号 (読み取り専用)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    // 配信に使う版 (読み取り専用、promote / rollback で変更する)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_version: Option<u64>,
+    // 直前に有効だった版 (読み取り専用、rollback の戻り先)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_active_version: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct Rule {
+    // 位置の代わりにルールを指定するための ID (数字だけの ID は使えない)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     #[serde(rename = "match")]
     pub match_expr: String,
     pub action: Action,
 }
 
+/// 入力のリクエストと期待する結果の組
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TestCase {
+    pub name: String,
+    pub request: SimulatedRequest,
+    pub expect: Expectation,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Expectation {
+    // 最終的なアクションの種類
+    pub action: ExpectedAction,
+    // 指定した場合は `Proxy` / `Redirect` の `target` (重み付きの `Proxy` なら選ばれた転送先) と比較する
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    // 指定した場合は Hub が返すステータス (`Redirect` / `Respond` と、`Authenticate` で拒否した場合) と比較する
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    // `SetHeaders` の `target` ごとに、設定されているべきヘッダー (指定したものだけを比較する)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ExpectedAction {
+    Proxy,
+    Redirect,
+    Respond,
+    // `Authenticate` で拒否される
+    Authenticate,
+    // 終端のアクションに達しない
+    None,
+}
+
 /// Helper function for `serde` to skip serializing boolean fields that are false.
 fn is_false(b: &bool) -> bool {
     !*b
@@ -48,23 +114,211 @@ pub enum Action {
     SetVariables(SetVariablesAction),
     SetHeaders(SetHeadersAction),
     AccessLog(AccessLogAction),
+    Authenticate(AuthenticateAction),
+    Respond(RespondAction),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct ProxyAction {
-    pub target: String,
+    // 転送先 (`targets` とどちらか一方を指定する)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    // 重み付きの複数の転送先 (カナリアリリースや Blue/Green 切り替え用)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<WeightedTarget>,
+    // 同じキーのリクエストを同じ転送先に送る (省略するとリクエストごとに選ぶ)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sticky: Option<StickyKey>,
     #[serde(default, skip_serializing_if = "is_false")]
     pub no_body: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeouts: Option<ProxyTimeouts>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+    // 転送先との TLS 接続
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<UpstreamTls>,
+    // WebSocket へのアップグレードを中継する (HTTP/1.1 で接続する)
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub websocket: bool,
+    // 転送先と HTTP/2 で接続する
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub http2: bool,
+    // リクエストボディの上限 (バイト)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_body_size: Option<u64>,
+}
+
+/// 転送先へのタイムアウト (ミリ秒、省略時は Hub の既定値)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct ProxyTimeouts {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_ms: Option<u32>,
+    // レスポンスの読み取りが途切れてよい時間
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_ms: Option<u32>,
+}
+
+/// 失敗したリクエストの再試行
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicy {
+    // 最初の試行を含まない再試行の回数
+    pub count: u32,
+    // 再試行するレスポンスのステータス (接続の失敗は常に再試行する)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub statuses: Vec<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backoff: Option<Backoff>,
+}
+
+/// 再試行の間隔 (`initialMs` から倍にしていき、`maxMs` で頭打ちにする)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct Backoff {
+    pub initial_ms: u32,
+    pub max_ms: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamTls {
+    // 省略すると転送先のホスト名を使う
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sni: Option<String>,
+    // 転送先の証明書を検証する CA (Realm の URN で、その `cacert` を使う)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<String>,
+    #[serde(default = "default_verify", skip_serializing_if = "is_true")]
+    pub verify: bool,
+}
+
+fn default_verify() -> bool {
+    true
+}
+
+fn is_true(b: &bool) -> bool {
+    *b
+}
+
+const MAX_CONNECT_TIMEOUT_MS: u32 = 60_000;
+const MAX_READ_TIMEOUT_MS: u32 = 3_600_000;
+const MAX_RETRY_COUNT: u32 = 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WeightedTarget {
+    // Service の URN
+    pub target: String,
+    pub weight: u32,
+}
+
+/// 転送先を固定するキーの取り出し元
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum StickyKey {
+    Cookie(String),
+    Header(String),
+}
+
+impl ProxyAction {
+    /// ハッシュ値から転送先を選ぶ
+    ///
+    /// `hash % 重みの合計` を、`targets` の順に重みを積み上げた範囲に当てはめる。
+    /// スティッキーキーのハッシュ値は [`sticky_hash`] で求める。
+    pub fn select(&self, hash: u64) -> Option<(usize, &WeightedTarget)> {
+        let total: u64 = self.targets.iter().map(|t| u64::from(t.weight)).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut bucket = hash % total;
+        for (i, target) in self.targets.iter().enumerate() {
+            let weight = u64::from(target.weight);
+            if bucket < weight {
+                return Some((i, target));
+            }
+            bucket -= weight;
+        }
+        None
+    }
+}
+
+/// スティッキーキーのハッシュ値 (UTF-8 のバイト列の 64 ビット FNV-1a)
+pub fn sticky_hash(value: &str) -> u64 {
+    value.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3))
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct RedirectAction {
     pub target: String,
+    // 301 / 302 / 307 / 308 (省略すると 302)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    // リクエストのクエリ文字列を `target` に引き継ぐ
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub preserve_query: bool,
+}
+
+const REDIRECT_STATUSES: [u16; 4] = [301, 302, 307, 308];
+
+impl RedirectAction {
+    pub fn status(&self) -> u16 {
+        self.status.unwrap_or(302)
+    }
+
+    /// `Location` ヘッダーの値 (`query` はリクエストのクエリ文字列、`target` にフラグメントがあればその前に加える)
+    pub fn location(&self, query: Option<&str>) -> String {
+        match query.filter(|query| self.preserve_query && !query.is_empty()) {
+            Some(query) => {
+                let (base, fragment) = match self.target.split_once('#') {
+                    Some((base, fragment)) => (base, Some(fragment)),
+                    None => (self.target.as_str(), None),
+                };
+                let separator = if base.contains('?') { '&' } else { '?' };
+                let mut location = format!("{}{}{}", base, separator, query);
+                if let Some(fragment) = fragment {
+                    location.push('#');
+                    location.push_str(fragment);
+                }
+                location
+            }
+            None => self.target.clone(),
+        }
+    }
+}
+
+/// Hub が転送せずに返す応答 (メンテナンス画面など)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct RespondAction {
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<ResponseBody>,
 }
 
+/// 応答の本文 (`{"inline": "..."}` または同じ Realm の Blob 名の `{"blob": "..."}`)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ResponseBody {
+    Inline(String),
+    Blob(String),
+}
+
+/// `inline` の本文の上限 (バイト、大きな本文は Blob に保存する)
+const MAX_INLINE_BODY_SIZE: usize = 64 * 1024;
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct JumpAction {
@@ -97,17 +351,339 @@ pub struct AccessLogAction {
 
 fn default_max_value_length() -> i32 { 512 }
 
+/// Realm のセッション (`signingKey` で署名し、`sessionTimeout` で失効する) によるログインの要求
+///
+/// 認証済みで `roles` と `claims` を満たせば次のルールに進み、そうでなければ評価を終える
+/// (未認証は `unauthenticated` に従い、権限が足りなければ 403)。
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct AuthenticateAction {
+    // すべて持っている必要があるロール
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    // クレームごとに許す値 (いずれかに一致する必要がある)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub claims: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub unauthenticated: Unauthenticated,
+    // ログイン画面の URL (`unauthenticated` が `redirect` の場合に必須)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub login_url: Option<String>,
+    // 転送先に渡すヘッダー名とクレーム名 (受け取ったリクエストの同じ名前のヘッダーは置き換える)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub identity_headers: BTreeMap<String, String>,
+}
+
+/// 未認証のリクエストの扱い
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Unauthenticated {
+    // 401 を返す
+    #[default]
+    Reject,
+    // `loginUrl` にリダイレクトする
+    Redirect,
+}
+
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/", get(list_routing_chains).post(add_routing_chain).put(update_routing_chain))
         .route("/{routing_chain_name}", get(get_routing_chain).delete(delete_routing_chain))
+        .route("/{routing_chain_name}/simulate", post(simulator::simulate_routing_chain))
+        .route("/{routing_chain_name}/test", post(simulator::test_routing_chain))
+        .route("/{routing_chain_name}/lint", get(lint::lint_routing_chain))
+        .nest("/{routing_chain_name}/rules", routing_rule::routes())
+        .nest("/{routing_chain_name}/versions", routing_chain_version::routes())
+        .route("/{routing_chain_name}/promote", post(routing_chain_version::promote))
+        .route("/{routing_chain_name}/rollback", post(routing_chain_version::rollback))
 }
 
-fn routing_chain_key(realm: &str, name: &str) -> String {
+pub fn routing_chain_key(realm: &RealmName, name: &RoutingChainName) -> String {
     format!("/realms/{}/routing-chains/{}", realm, name)
 }
 
-fn routing_chain_prefix(realm: &str) -> String {
+/// 各ルールの ID と条件式を確認する。条件式の構文エラーはルールの番号と列、期待したトークンを付けた 422 にする
+pub fn validate_rules(chain: &RoutingChain) -> Result<(), ApiError> {
+    let mut ids = HashSet::new();
+    for (i, rule) in chain.rules.iter().enumerate() {
+        if let Some(id) = &rule.id {
+            let field = format!("rules[{}].id", i);
+            validation::identifier(&field, id)?;
+            if id.chars().all(|c| c.is_ascii_digit()) {
+                return Err(validation::invalid(&field, format!("'{}' must not be all digits", id)));
+            }
+            if !ids.insert(id.as_str()) {
+                return Err(validation::invalid(&field, format!("'{}' is used by another rule", id)));
+            }
+        }
+        if let Err(e) = match_expr::parse(&rule.match_expr) {
+            let field = format!("rules[{}].match", i);
+            return Err(ApiError::UnprocessableDetails {
+                message: format!("{}: {}", field, e),
+                details: json!({ "field": field, "rule": i, "column": e.column, "expected": e.expected, "found": e.found }),
+            });
+        }
+        match &rule.action {
+            Action::Proxy(proxy) => validate_proxy(&format!("rules[{}].action", i), proxy)?,
+            Action::Authenticate(action) => validate_authenticate(&format!("rules[{}].action", i), action)?,
+            Action::Redirect(action) => validate_redirect(&format!("rules[{}].action", i), action)?,
+            Action::Respond(action) => validate_respond(&format!("rules[{}].action", i), action)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// 転送先の指定を確認する (`targets` は Service の URN に限る)
+fn validate_proxy(field: &str, proxy: &ProxyAction) -> Result<(), ApiError> {
+    match (&proxy.target, proxy.targets.is_empty()) {
+        (Some(_), false) => return Err(validation::invalid(field, "specify either 'target' or 'targets', not both")),
+        (None, true) => return Err(validation::invalid(field, "either 'target' or 'targets' is required")),
+        _ => {}
+    }
+    for (j, target) in proxy.targets.iter().enumerate() {
+        let field = format!("{}.targets[{}]", field, j);
+        if target.weight == 0 {
+            return Err(validation::invalid(&format!("{}.weight", field), "must be positive"));
+        }
+        let urn = Urn::parse(&format!("{}.target", field), &target.target)?;
+        if urn.kind() != "service" {
+            return Err(validation::invalid(&format!("{}.target", field), format!("'{}' must refer to a service", target.target)));
+        }
+    }
+    match &proxy.sticky {
+        Some(_) if proxy.targets.is_empty() => return Err(validation::invalid(&format!("{}.sticky", field), "requires 'targets'")),
+        Some(StickyKey::Cookie(name) | StickyKey::Header(name)) if name.is_empty() => {
+            return Err(validation::invalid(&format!("{}.sticky", field), "the cookie or header name must not be empty"));
+        }
+        _ => {}
+    }
+    validate_connection(field, proxy)
+}
+
+/// タイムアウト、再試行、TLS などの接続の設定を確認する
+fn validate_connection(field: &str, proxy: &ProxyAction) -> Result<(), ApiError> {
+    if let Some(timeouts) = &proxy.timeouts {
+        let limits = [("connectMs", timeouts.connect_ms, MAX_CONNECT_TIMEOUT_MS), ("readMs", timeouts.read_ms, MAX_READ_TIMEOUT_MS)];
+        for (name, value, max) in limits {
+            if value.is_some_and(|ms| ms == 0 || ms > max) {
+                return Err(validation::invalid(&format!("{}.timeouts.{}", field, name), format!("must be between 1 and {}", max)));
+            }
+        }
+    }
+    if let Some(retry) = &proxy.retry {
+        let field = format!("{}.retry", field);
+        if retry.count == 0 || retry.count > MAX_RETRY_COUNT {
+            return Err(validation::invalid(&format!("{}.count", field), format!("must be between 1 and {}", MAX_RETRY_COUNT)));
+        }
+        let mut statuses = HashSet::new();
+        for (i, status) in retry.statuses.iter().enumerate() {
+            if !(400..=599).contains(status) || !statuses.insert(status) {
+                let message = format!("{} must be a unique 4xx or 5xx status", status);
+                return Err(validation::invalid(&format!("{}.statuses[{}]", field, i), message));
+            }
+        }
+        if let Some(backoff) = &retry.backoff {
+            if backoff.initial_ms == 0 {
+                return Err(validation::invalid(&format!("{}.backoff.initialMs", field), "must be positive"));
+            }
+            if backoff.max_ms < backoff.initial_ms {
+                return Err(validation::invalid(&format!("{}.backoff.maxMs", field), "must not be less than 'initialMs'"));
+            }
+        }
+    }
+    if let Some(tls) = &proxy.tls {
+        let field = format!("{}.tls", field);
+        if let Some(sni) = &tls.sni {
+            validation::domain_name(&format!("{}.sni", field), sni, false)?;
+        }
+        if let Some(ca_bundle) = &tls.ca_bundle {
+            let ca_field = format!("{}.caBundle", field);
+            if !tls.verify {
+                return Err(validation::invalid(&ca_field, "is not used when 'verify' is false"));
+            }
+            if Urn::parse(&ca_field, ca_bundle)?.kind() != "realm" {
+                return Err(validation::invalid(&ca_field, format!("'{}' must refer to a realm", ca_bundle)));
+            }
+        }
+    }
+    if proxy.websocket && proxy.http2 {
+        let message = "cannot be combined with 'websocket', which upgrades an HTTP/1.1 connection";
+        return Err(validation::invalid(&format!("{}.http2", field), message));
+    }
+    match proxy.max_body_size {
+        Some(0) => Err(validation::invalid(&format!("{}.maxBodySize", field), "must be positive")),
+        Some(_) if proxy.no_body => Err(validation::invalid(&format!("{}.maxBodySize", field), "cannot be combined with 'noBody'")),
+        _ => Ok(()),
+    }
+}
+
+/// ロールやクレーム、ログイン画面、転送するヘッダーを確認する
+fn validate_authenticate(field: &str, action: &AuthenticateAction) -> Result<(), ApiError> {
+    let mut roles = HashSet::new();
+    for (i, role) in action.roles.iter().enumerate() {
+        if role.is_empty() || !roles.insert(role) {
+            return Err(validation::invalid(&format!("{}.roles[{}]", field, i), format!("'{}' must be a non-empty, unique role", role)));
+        }
+    }
+    for (name, values) in &action.claims {
+        if name.is_empty() || values.is_empty() || values.iter().any(String::is_empty) {
+            let message = format!("claim '{}' must have a name and at least one non-empty value", name);
+            return Err(validation::invalid(&format!("{}.claims", field), message));
+        }
+    }
+    let login_field = format!("{}.loginUrl", field);
+    match (action.unauthenticated, &action.login_url) {
+        (Unauthenticated::Redirect, None) => {
+            return Err(validation::invalid(&login_field, "is required when 'unauthenticated' is 'redirect'"));
+        }
+        (Unauthenticated::Reject, Some(_)) => {
+            return Err(validation::invalid(&login_field, "is only used when 'unauthenticated' is 'redirect'"));
+        }
+        (_, Some(url)) if !url.starts_with('/') && !url::Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https")) => {
+            return Err(validation::invalid(&login_field, format!("'{}' must be an http(s) URL or an absolute path", url)));
+        }
+        _ => {}
+    }
+    for (name, claim) in &action.identity_headers {
+        if !is_header_name(name) {
+            return Err(validation::invalid(&format!("{}.identityHeaders", field), format!("'{}' is not a valid header name", name)));
+        }
+        if claim.is_empty() {
+            return Err(validation::invalid(&format!("{}.identityHeaders", field), format!("header '{}' must name a claim", name)));
+        }
+    }
+    Ok(())
+}
+
+/// HTTP のヘッダー名 (token) として使えるか
+fn is_header_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn validate_redirect(field: &str, action: &RedirectAction) -> Result<(), ApiError> {
+    match action.status {
+        Some(status) if !REDIRECT_STATUSES.contains(&status) => {
+            Err(validation::invalid(&format!("{}.status", field), format!("{} must be one of {:?}", status, REDIRECT_STATUSES)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// ステータス、ヘッダー、本文を確認する (Blob の存在は [`check_response_body`] で確認する)
+pub fn validate_respond(field: &str, action: &RespondAction) -> Result<(), ApiError> {
+    if !(200..=599).contains(&action.status) {
+        return Err(validation::invalid(&format!("{}.status", field), format!("{} must be between 200 and 599", action.status)));
+    }
+    for (name, value) in &action.headers {
+        if !is_header_name(name) || value.contains(['\r', '\n']) {
+            return Err(validation::invalid(&format!("{}.headers", field), format!("'{}' is not a valid header", name)));
+        }
+    }
+    let body_field = format!("{}.body", field);
+    match &action.body {
+        Some(_) if matches!(action.status, 204 | 304) => {
+            Err(validation::invalid(&body_field, format!("a {} response cannot have a body", action.status)))
+        }
+        Some(ResponseBody::Inline(body)) if body.len() > MAX_INLINE_BODY_SIZE => Err(validation::invalid(
+            &body_field,
+            format!("inline bodies are limited to {} bytes; store larger bodies as a blob", MAX_INLINE_BODY_SIZE),
+        )),
+        Some(ResponseBody::Blob(name)) => BlobName::parse(&format!("{}.blob", body_field), name).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// `Respond` の本文に Blob `name` を使っているかを返す
+pub fn uses_blob(chain: &RoutingChain, name: &str) -> bool {
+    chain.rules.iter().any(|rule| matches!(&rule.action, Action::Respond(action) if is_blob(action, name)))
+}
+
+/// 応答の本文が Blob `name` かを返す
+pub fn is_blob(action: &RespondAction, name: &str) -> bool {
+    matches!(&action.body, Some(ResponseBody::Blob(blob)) if blob == name)
+}
+
+/// 本文の Blob が `realm` に存在することを確認する
+pub async fn check_response_body(state: &AppState, realm: &RealmName, field: &str, action: &RespondAction) -> Result<(), ApiError> {
+    let Some(ResponseBody::Blob(name)) = &action.body else {
+        return Ok(());
+    };
+    let field = format!("{}.body.blob", field);
+    if blob::exists(state, realm, &BlobName::parse(&field, name)?).await? {
+        return Ok(());
+    }
+    Err(ApiError::UnprocessableDetails {
+        message: format!("{}: blob '{}' does not exist in realm '{}'", field, name, realm),
+        details: json!({ "field": field }),
+    })
+}
+
+/// 参照先が存在することを確認する
+///
+/// 重み付きの転送先の Service、TLS の CA の Realm、`Respond` の本文の Blob に加え、`Authenticate` を使う場合は Realm の `signingKey` を確認する。
+async fn validate_references(state: &AppState, urn: &Urn, chain: &RoutingChain) -> Result<(), ApiError> {
+    if let Some(i) = chain.rules.iter().position(|rule| matches!(rule.action, Action::Authenticate(_))) {
+        if realm::load_realm(state, urn.realm()).await?.signing_key.is_none() {
+            let field = format!("rules[{}].action", i);
+            return Err(ApiError::UnprocessableDetails {
+                message: format!("{}: realm '{}' has no signingKey for sessions", field, urn.realm()),
+                details: json!({ "field": field }),
+            });
+        }
+    }
+    for (i, rule) in chain.rules.iter().enumerate() {
+        if let Action::Respond(action) = &rule.action {
+            check_response_body(state, urn.realm(), &format!("rules[{}].action", i), action).await?;
+        }
+        let Action::Proxy(proxy) = &rule.action else { continue };
+        let mut references: Vec<(String, &str)> = proxy
+            .targets
+            .iter()
+            .enumerate()
+            .map(|(j, target)| (format!("rules[{}].action.targets[{}].target", i, j), target.target.as_str()))
+            .collect();
+        if let Some(ca_bundle) = proxy.tls.as_ref().and_then(|tls| tls.ca_bundle.as_deref()) {
+            references.push((format!("rules[{}].action.tls.caBundle", i), ca_bundle));
+        }
+        for (field, value) in references {
+            let urn = Urn::parse(&field, value)?;
+            if get_from_etcd(state, &urn.key()).await?.kvs().is_empty() {
+                return Err(ApiError::UnprocessableDetails {
+                    message: format!("{}: '{}' does not refer to an existing {}", field, value, urn.kind()),
+                    details: json!({ "field": field }),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 作成・更新時のクエリパラメーター
+#[derive(Deserialize)]
+pub struct WriteParams {
+    // `true` なら検査で問題が見つかった変更を拒否する
+    #[serde(default)]
+    pub lint: bool,
+    // 指定した場合、保存されている RoutingChain の mod_revision が一致するときだけ更新する
+    pub revision: Option<i64>,
+    // `true` なら作った版をすぐに有効にする
+    #[serde(default)]
+    pub promote: bool,
+}
+
+/// 保存する前の RoutingChain を確認する (条件式、`?lint=true` なら検査、テストケース)
+pub async fn check_changes(state: &AppState, urn: &Urn, chain: &RoutingChain, params: &WriteParams) -> Result<(), ApiError> {
+    validate_rules(chain)?;
+    validate_references(state, urn, chain).await?;
+    if params.lint {
+        lint::require_clean(chain)?;
+    }
+    simulator::require_passing_tests(state, urn, chain).await
+}
+
+pub fn routing_chain_prefix(realm: &RealmName) -> String {
     format!("/realms/{}/routing-chains/", realm)
 }
 
@@ -116,7 +692,7 @@ async fn list_routing_chains(
     Path(realm): Path<String>,
 ) -> Result<Json<Vec<RoutingChain>>, ApiError> {
     let mut client = state.etcd_client.clone();
-    let prefix = routing_chain_prefix(&realm);
+    let prefix = routing_chain_prefix(&RealmName::parse("realm", &realm)?);
     let resp = client.get(prefix, Some(etcd_client::GetOptions::new().with_prefix())).await?;
     let chains = resp
         .kvs()
@@ -129,38 +705,62 @@ async fn list_routing_chains(
 async fn add_routing_chain(
     State(state): State<AppState>,
     Path(realm): Path<String>,
+    Query(params): Query<WriteParams>,
     Json(mut chain): Json<RoutingChain>,
 ) -> Result<Json<RoutingChain>, ApiError> {
-    let mut client = state.etcd_client.clone();
-    let key = routing_chain_key(&realm, &chain.name);
+    let realm = RealmName::parse("realm", &realm)?;
+    let name = RoutingChainName::parse("name", &chain.name)?;
+    let key = routing_chain_key(&realm, &name);
+    validate_rules(&chain)?;
 
+    #[allow(clippy::unnecessary_first_then_check)]
     if get_from_etcd(&state, &key).await?.kvs().first().is_some() {
         return Err(ApiError::Conflict(format!("RoutingChain '{}' already exists in realm '{}'.", chain.name, realm)));
     }
-    
-    chain.realm = Some(realm.clone());
-    chain.urn = Some(format!("urn:chip-in:routing-chain:{}:{}", realm, chain.name));
-    let value = serde_json::to_vec(&chain)?;
-    client.put(key, value, None).await?;
+    let urn = Urn::RoutingChain(realm.clone(), name.clone());
+    check_changes(&state, &urn, &chain, &params).await?;
+
+    chain.realm = Some(Urn::Realm(realm.clone()).to_string());
+    chain.urn = Some(urn.to_string());
+    routing_chain_version::save_version(&state, &realm, &name, &mut chain, None, true).await?;
     Ok(Json(chain))
 }
 
+/// 新しい版として保存する (存在しなければ作成する)。`?promote=true` でなければ有効な版は変わらない
+///
+/// `tests` を省略した場合は最新の版のテストケースを引き継ぎ、それを実行する。
 async fn update_routing_chain(
     State(state): State<AppState>,
     Path(realm): Path<String>,
+    Query(params): Query<WriteParams>,
     Json(mut chain): Json<RoutingChain>,
 ) -> Result<Json<RoutingChain>, ApiError> {
-    let mut client = state.etcd_client.clone();
-    let key = routing_chain_key(&realm, &chain.name);
-    chain.realm = Some(realm.clone());
-    chain.urn = Some(format!("urn:chip-in:routing-chain:{}:{}", realm, chain.name));
-    let value = serde_json::to_vec(&chain)?;
-    client.put(key, value, None).await?;
+    let realm = RealmName::parse("realm", &realm)?;
+    let name = RoutingChainName::parse("name", &chain.name)?;
+    let urn = Urn::RoutingChain(realm.clone(), name.clone());
+
+    let current = routing_chain_version::load_latest(&state, &realm, &name).await?;
+    let revision = current.as_ref().map(|(_, revision)| *revision);
+    if params.revision.is_some() && params.revision != revision {
+        return Err(ApiError::Conflict(format!(
+            "RoutingChain '{}' was modified since revision {}; reload it and retry",
+            chain.name,
+            params.revision.unwrap_or_default()
+        )));
+    }
+    if chain.tests.is_none() {
+        chain.tests = current.and_then(|(stored, _)| stored.tests);
+    }
+    check_changes(&state, &urn, &chain, &params).await?;
+    chain.realm = Some(Urn::Realm(realm.clone()).to_string());
+    chain.urn = Some(urn.to_string());
+    let promote = params.promote || revision.is_none();
+    routing_chain_version::save_version(&state, &realm, &name, &mut chain, revision, promote).await?;
     Ok(Json(chain))
 }
 
 async fn get_routing_chain(State(state): State<AppState>, Path((realm, name)): Path<(String, String)>) -> Result<Json<RoutingChain>, ApiError> {
-    let key = routing_chain_key(&realm, &name);
+    let key = routing_chain_key(&RealmName::parse("realm", &realm)?, &RoutingChainName::parse("routingChain", &name)?);
     if let Some(kv) = get_from_etcd(&state, &key).await?.kvs().first() {
         let chain = serde_json::from_slice(kv.value())?;
         Ok(Json(chain))
@@ -169,15 +769,50 @@ async fn get_routing_chain(State(state): State<AppState>, Path((realm, name)): P
     }
 }
 
-async fn delete_routing_chain(State(state): State<AppState>, Path((realm, name)): Path<(String, String)>) -> Result<Json<RoutingChain>, ApiError> {
+/// RoutingChain を参照している VirtualHost の URN を返す (参照は他の Realm からもありうるため全 Realm を調べる)
+///
+/// 調べた VirtualHost が削除までに変わっていないことを `write` の条件に加える。
+async fn referencing_hosts(state: &AppState, urn: &Urn, write: &mut IndexedWrite) -> Result<Vec<String>, ApiError> {
     let mut client = state.etcd_client.clone();
-    let key = routing_chain_key(&realm, &name);
-    let opts = etcd_client::DeleteOptions::new().with_prev_key();
-    let resp = client.delete(key, Some(opts)).await?;
-    if let Some(kv) = resp.prev_kvs().first() {
-        let chain = serde_json::from_slice(kv.value())?;
+    let realms = client.get(REALM_PREFIX, Some(GetOptions::new().with_prefix().with_keys_only())).await?;
+    let target = urn.to_string();
+    let mut hosts = Vec::new();
+    for kv in realms.kvs() {
+        let Ok(realm) = RealmName::parse("realm", kv.key_str()?.trim_start_matches(REALM_PREFIX)) else {
+            continue;
+        };
+        let prefix = virtual_host::virtual_host_prefix(&realm);
+        let resp = client.get(prefix.as_str(), Some(GetOptions::new().with_prefix())).await?;
+        if let Some(header) = resp.header() {
+            write.require_prefix_unchanged(&prefix, header.revision());
+        }
+        for kv in resp.kvs() {
+            let host: Value = serde_json::from_slice(kv.value())?;
+            if host.get("routingChain").and_then(Value::as_str) == Some(target.as_str()) {
+                let name = host.get("name").and_then(Value::as_str).unwrap_or_default();
+                hosts.push(Urn::VirtualHost(realm.clone(), VirtualHostName::parse("name", name)?).to_string());
+            }
+        }
+    }
+    Ok(hosts)
+}
+
+/// RoutingChain とそのすべての版を削除する (VirtualHost が参照していれば 409)
+async fn delete_routing_chain(State(state): State<AppState>, Path((realm, name)): Path<(String, String)>) -> Result<Json<RoutingChain>, ApiError> {
+    let (realm_name, chain_name) = (RealmName::parse("realm", &realm)?, RoutingChainName::parse("routingChain", &name)?);
+    let key = routing_chain_key(&realm_name, &chain_name);
+    let mut write = IndexedWrite::new();
+    let hosts = referencing_hosts(&state, &Urn::RoutingChain(realm_name.clone(), chain_name.clone()), &mut write).await?;
+    if !hosts.is_empty() {
+        return Err(ApiError::Conflict(format!("RoutingChain '{}' is used by {}", name, hosts.join(", "))));
+    }
+    write.delete(&key);
+    write.delete_prefix(&routing_chain_version::versions_prefix(&realm_name, &chain_name));
+    let resp = write.commit(&state).await?;
+    if let Some(value) = host_index::deleted_value(&resp) {
+        let chain = serde_json::from_slice(&value)?;
         Ok(Json(chain))
     } else {
         Err(ApiError::NotFound(format!("RoutingChain '{}' not found in realm '{}'", name, realm)))
     }
-}
\ No newline at end of file
+}
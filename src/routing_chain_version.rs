@@ -0,0 +1,476 @@
+//! RoutingChain の版 (`/realms/{realm}/routing-chains/{name}/versions`)
+//!
+//! RoutingChain の作成・更新 (ルール単位の変更を含む) のたびに、番号付きの変更できない版を
+//! `/realms/{realm}/routing-chain-versions/{name}/{version}` に保存する。配信に使う版は `activeVersion` で、
+//! 作成時の版 1 以外は `POST .../promote` (または書き込み時の `?promote=true`) で有効にするまで変わらない。
+//! `POST .../rollback` は直前に有効だった版 (`previousActiveVersion`) に戻す。
+//!
+//! `/realms/{realm}/routing-chains/{name}` には常に有効な版の内容 (と版の番号) を保存する。有効にしていない版は
+//! `routing-chain-versions` にだけ置くため、取得、シミュレーター、検査、`Jump` の参照はどれも有効な版を使う。
+//! ルール単位の変更は最新の版 ([`load_latest`]) に対して行う。
+//!
+//! 版を導入する前に保存された RoutingChain は、最初に版を扱うときにその内容を版 1 として有効にする。
+use crate::db::AppState;
+use crate::error::ApiError;
+use crate::routing_chain::{self, RoutingChain, Rule};
+use crate::urn::Urn;
+use crate::utils::get_from_etcd;
+use crate::validation::{self, RealmName, RoutingChainName};
+use axum::{
+    extract::{Path, State},
+    routing::get,
+    Json, Router,
+};
+use etcd_client::{Compare, CompareOp, GetOptions, Txn, TxnOp};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_versions))
+        .route("/{version}", get(get_version))
+        .route("/{version}/diff/{other}", get(diff_versions))
+}
+
+/// 保存された版
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainVersion {
+    pub version: u64,
+    pub created_at: String,
+    pub chain: RoutingChain,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionSummary {
+    version: u64,
+    created_at: String,
+    active: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PromoteRequest {
+    // 省略すると promote は最新の版、rollback は直前に有効だった版
+    version: Option<u64>,
+}
+
+/// 2 つの版の差分
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionDiff {
+    from: u64,
+    to: u64,
+    fields: Vec<FieldChange>,
+    rules: Vec<RuleChange>,
+}
+
+#[derive(Serialize)]
+struct FieldChange {
+    field: &'static str,
+    from: Value,
+    to: Value,
+}
+
+/// ルールの変更 (`from` / `to` はそれぞれの版でのルールの位置)
+#[derive(Serialize)]
+struct RuleChange {
+    // `added` / `removed` / `changed` / `moved`
+    op: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    before: Option<Rule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after: Option<Rule>,
+}
+
+pub fn version_key(realm: &RealmName, name: &RoutingChainName, version: u64) -> String {
+    format!("{}{:010}", versions_prefix(realm, name), version)
+}
+
+pub fn versions_prefix(realm: &RealmName, name: &RoutingChainName) -> String {
+    format!("{}{}/", realm_versions_prefix(realm), name)
+}
+
+/// Realm のすべての RoutingChain の版
+pub fn realm_versions_prefix(realm: &RealmName) -> String {
+    format!("/realms/{}/routing-chain-versions/", realm)
+}
+
+fn snapshot(version: u64, chain: &RoutingChain) -> Result<Vec<u8>, ApiError> {
+    let mut chain = chain.clone();
+    chain.active_version = None;
+    chain.previous_active_version = None;
+    let created_at = OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default();
+    Ok(serde_json::to_vec(&ChainVersion { version, created_at, chain })?)
+}
+
+fn modified(name: &str) -> ApiError {
+    ApiError::Conflict(format!("RoutingChain '{}' was modified concurrently; reload it and retry", name))
+}
+
+/// 保存されている RoutingChain と mod_revision を読み込む (版を持たなければ現在の内容を版 1 にする)
+pub async fn load_versioned(
+    state: &AppState,
+    realm: &RealmName,
+    name: &RoutingChainName,
+) -> Result<Option<(RoutingChain, i64)>, ApiError> {
+    let key = routing_chain::routing_chain_key(realm, name);
+    let Some(kv) = get_from_etcd(state, &key).await?.kvs().first().cloned() else {
+        return Ok(None);
+    };
+    let mut chain: RoutingChain = serde_json::from_slice(kv.value())?;
+    if chain.version.is_some() {
+        return Ok(Some((chain, kv.mod_revision())));
+    }
+
+    chain.version = Some(1);
+    chain.active_version = Some(1);
+    let version_key = version_key(realm, name, 1);
+    let txn = Txn::new()
+        .when([
+            Compare::mod_revision(key.as_str(), CompareOp::Equal, kv.mod_revision()),
+            Compare::version(version_key.as_str(), CompareOp::Equal, 0),
+        ])
+        .and_then([TxnOp::put(key.as_str(), serde_json::to_vec(&chain)?, None), TxnOp::put(version_key, snapshot(1, &chain)?, None)]);
+    let mut client = state.etcd_client.clone();
+    let resp = client.txn(txn).await?;
+    if !resp.succeeded() {
+        return Err(modified(name));
+    }
+    Ok(Some((chain, resp.header().map(|h| h.revision()).unwrap_or_default())))
+}
+
+/// 最新の版の内容と、保存されている RoutingChain の mod_revision を読み込む (ルール単位の変更の元にする)
+pub async fn load_latest(
+    state: &AppState,
+    realm: &RealmName,
+    name: &RoutingChainName,
+) -> Result<Option<(RoutingChain, i64)>, ApiError> {
+    let Some((chain, revision)) = load_versioned(state, realm, name).await? else {
+        return Ok(None);
+    };
+    let latest = chain.version.unwrap_or_default();
+    if chain.active_version == Some(latest) {
+        return Ok(Some((chain, revision)));
+    }
+    let Some((version, _)) = get_chain_version(state, realm, name, latest).await? else {
+        return Err(ApiError::Internal(anyhow::anyhow!("version {} of RoutingChain '{}' is missing", latest, name)));
+    };
+    Ok(Some((with_versions(version.chain, &chain), revision)))
+}
+
+/// 有効な版を読み込む (版を持たない RoutingChain は移行せず、現在の内容を版 1 として返す)
+pub async fn load_active(state: &AppState, realm: &RealmName, name: &RoutingChainName) -> Result<Option<ChainVersion>, ApiError> {
+    let Some(kv) = get_from_etcd(state, &routing_chain::routing_chain_key(realm, name)).await?.kvs().first().cloned() else {
+        return Ok(None);
+    };
+    let chain: RoutingChain = serde_json::from_slice(kv.value())?;
+    match chain.active_version {
+        Some(version) => Ok(get_chain_version(state, realm, name, version).await?.map(|(version, _)| version)),
+        None => Ok(Some(ChainVersion { version: 1, created_at: String::new(), chain })),
+    }
+}
+
+/// 版の内容に、保存されている RoutingChain の版の番号を付ける
+fn with_versions(mut content: RoutingChain, stored: &RoutingChain) -> RoutingChain {
+    content.version = stored.version;
+    content.active_version = stored.active_version;
+    content.previous_active_version = stored.previous_active_version;
+    content
+}
+
+/// `chain` を新しい版として保存し、保存後の revision を返す
+///
+/// `current` は保存されている RoutingChain の mod_revision (新規作成なら `None`)。
+/// 新規作成なら版 1 を有効にし、更新なら `promote` のときだけ新しい版を有効にする。
+/// 有効にしない場合、保存されている RoutingChain は版の番号だけを更新する。
+pub async fn save_version(
+    state: &AppState,
+    realm: &RealmName,
+    name: &RoutingChainName,
+    chain: &mut RoutingChain,
+    current: Option<i64>,
+    promote: bool,
+) -> Result<i64, ApiError> {
+    let key = routing_chain::routing_chain_key(realm, name);
+    let (version, guard, main) = match current {
+        Some(revision) => {
+            let stored: RoutingChain = match get_from_etcd(state, &key).await?.kvs().first() {
+                Some(kv) if kv.mod_revision() == revision => serde_json::from_slice(kv.value())?,
+                _ => return Err(modified(name)),
+            };
+            let version = stored.version.unwrap_or_default() + 1;
+            if promote {
+                chain.active_version = Some(version);
+                chain.previous_active_version = stored.active_version;
+            } else {
+                chain.active_version = stored.active_version;
+                chain.previous_active_version = stored.previous_active_version;
+            }
+            chain.version = Some(version);
+            let main = if promote { chain.clone() } else { RoutingChain { version: Some(version), ..stored } };
+            (version, Compare::mod_revision(key.as_str(), CompareOp::Equal, revision), main)
+        }
+        None => {
+            chain.version = Some(1);
+            chain.active_version = Some(1);
+            chain.previous_active_version = None;
+            (1, Compare::version(key.as_str(), CompareOp::Equal, 0), chain.clone())
+        }
+    };
+
+    let version_key = version_key(realm, name, version);
+    let txn = Txn::new()
+        .when([guard, Compare::version(version_key.as_str(), CompareOp::Equal, 0)])
+        .and_then([TxnOp::put(key.as_str(), serde_json::to_vec(&main)?, None), TxnOp::put(version_key, snapshot(version, chain)?, None)]);
+    let mut client = state.etcd_client.clone();
+    let resp = client.txn(txn).await?;
+    if !resp.succeeded() {
+        return Err(match current {
+            Some(_) => modified(name),
+            None => ApiError::Conflict(format!("RoutingChain '{}' already exists in realm '{}'.", name, realm)),
+        });
+    }
+    Ok(resp.header().map(|h| h.revision()).unwrap_or_default())
+}
+
+/// 版を読み込む (存在しなければ `None`)
+pub async fn get_chain_version(
+    state: &AppState,
+    realm: &RealmName,
+    name: &RoutingChainName,
+    version: u64,
+) -> Result<Option<(ChainVersion, i64)>, ApiError> {
+    match get_from_etcd(state, &version_key(realm, name, version)).await?.kvs().first() {
+        Some(kv) => Ok(Some((serde_json::from_slice(kv.value())?, kv.mod_revision()))),
+        None => Ok(None),
+    }
+}
+
+fn parse_names(realm: &str, name: &str) -> Result<(RealmName, RoutingChainName), ApiError> {
+    Ok((RealmName::parse("realm", realm)?, RoutingChainName::parse("routingChain", name)?))
+}
+
+async fn require_versioned(state: &AppState, realm: &RealmName, name: &RoutingChainName) -> Result<(RoutingChain, i64), ApiError> {
+    load_versioned(state, realm, name)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("RoutingChain '{}' not found in realm '{}'", name, realm)))
+}
+
+/// `{version}` を版の番号にする (数字、`active`、`latest`)
+fn resolve_number(chain: &RoutingChain, value: &str) -> Result<u64, ApiError> {
+    let number = match value {
+        "active" => chain.active_version,
+        "latest" => chain.version,
+        _ => Some(value.parse().map_err(|_| {
+            validation::invalid("version", format!("'{}' must be a version number, 'active' or 'latest'", value))
+        })?),
+    };
+    number.ok_or_else(|| ApiError::NotFound(format!("RoutingChain '{}' has no {} version", chain.name, value)))
+}
+
+async fn require_version(state: &AppState, realm: &RealmName, name: &RoutingChainName, version: u64) -> Result<ChainVersion, ApiError> {
+    get_chain_version(state, realm, name, version)
+        .await?
+        .map(|(version, _)| version)
+        .ok_or_else(|| ApiError::NotFound(format!("Version {} of RoutingChain '{}' not found in realm '{}'", version, name, realm)))
+}
+
+async fn list_versions(
+    State(state): State<AppState>,
+    Path((realm, name)): Path<(String, String)>,
+) -> Result<Json<Vec<VersionSummary>>, ApiError> {
+    let (realm, name) = parse_names(&realm, &name)?;
+    let (chain, _) = require_versioned(&state, &realm, &name).await?;
+    let mut client = state.etcd_client.clone();
+    let resp = client.get(versions_prefix(&realm, &name), Some(GetOptions::new().with_prefix())).await?;
+    let versions = resp
+        .kvs()
+        .iter()
+        .filter_map(|kv| serde_json::from_slice::<ChainVersion>(kv.value()).ok())
+        .map(|v| VersionSummary { active: Some(v.version) == chain.active_version, version: v.version, created_at: v.created_at })
+        .collect();
+    Ok(Json(versions))
+}
+
+async fn get_version(
+    State(state): State<AppState>,
+    Path((realm, name, version)): Path<(String, String, String)>,
+) -> Result<Json<ChainVersion>, ApiError> {
+    let (realm, name) = parse_names(&realm, &name)?;
+    let (chain, _) = require_versioned(&state, &realm, &name).await?;
+    let version = resolve_number(&chain, &version)?;
+    Ok(Json(require_version(&state, &realm, &name, version).await?))
+}
+
+async fn diff_versions(
+    State(state): State<AppState>,
+    Path((realm, name, from, to)): Path<(String, String, String, String)>,
+) -> Result<Json<VersionDiff>, ApiError> {
+    let (realm, name) = parse_names(&realm, &name)?;
+    let (chain, _) = require_versioned(&state, &realm, &name).await?;
+    let old = require_version(&state, &realm, &name, resolve_number(&chain, &from)?).await?;
+    let new = require_version(&state, &realm, &name, resolve_number(&chain, &to)?).await?;
+
+    let mut fields = Vec::new();
+    let pairs = [
+        ("title", serde_json::to_value(&old.chain.title)?, serde_json::to_value(&new.chain.title)?),
+        ("description", serde_json::to_value(&old.chain.description)?, serde_json::to_value(&new.chain.description)?),
+        (
+            "tests",
+            serde_json::to_value(old.chain.tests.as_deref().unwrap_or_default())?,
+            serde_json::to_value(new.chain.tests.as_deref().unwrap_or_default())?,
+        ),
+    ];
+    for (field, from, to) in pairs {
+        if from != to {
+            fields.push(FieldChange { field, from, to });
+        }
+    }
+    Ok(Json(VersionDiff { from: old.version, to: new.version, fields, rules: diff_rules(&old.chain.rules, &new.chain.rules) }))
+}
+
+/// ルールの差分を求める
+///
+/// 最長共通部分列で変わらないルールを揃え、残りを `id` が同じもの、次に同じ位置の間で `id` を持たないもの同士を
+/// `changed` / `moved` として対応付ける。対応しないものは `removed` / `added` とする。
+fn diff_rules(old: &[Rule], new: &[Rule]) -> Vec<RuleChange> {
+    let (n, m) = (old.len(), new.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+
+    // 揃えたルールの間ごとに、削除と追加の位置を集める
+    let mut gaps: Vec<(Vec<usize>, Vec<usize>)> = vec![(Vec::new(), Vec::new())];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            gaps.push((Vec::new(), Vec::new()));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            gaps.last_mut().unwrap().0.push(i);
+            i += 1;
+        } else {
+            gaps.last_mut().unwrap().1.push(j);
+            j += 1;
+        }
+    }
+    let mut removed: Vec<usize> = gaps.iter().flat_map(|(r, _)| r.clone()).collect();
+    let mut added: Vec<usize> = gaps.iter().flat_map(|(_, a)| a.clone()).collect();
+
+    let mut changes = Vec::new();
+    let pair = |from: usize, to: usize| RuleChange {
+        op: if old[from] == new[to] { "moved" } else { "changed" },
+        from: Some(from),
+        to: Some(to),
+        before: (old[from] != new[to]).then(|| old[from].clone()),
+        after: Some(new[to].clone()),
+    };
+    // 同じ id のルール
+    removed.retain(|&from| {
+        let Some(id) = &old[from].id else { return true };
+        match added.iter().position(|&to| new[to].id.as_ref() == Some(id)) {
+            Some(k) => {
+                changes.push(pair(from, added.remove(k)));
+                false
+            }
+            None => true,
+        }
+    });
+    // 同じ位置で置き換えられた id のないルール
+    for (gap_removed, gap_added) in &gaps {
+        let from: Vec<usize> = gap_removed.iter().copied().filter(|i| removed.contains(i) && old[*i].id.is_none()).collect();
+        let to: Vec<usize> = gap_added.iter().copied().filter(|j| added.contains(j) && new[*j].id.is_none()).collect();
+        for (&from, &to) in from.iter().zip(&to) {
+            changes.push(pair(from, to));
+            removed.retain(|&i| i != from);
+            added.retain(|&j| j != to);
+        }
+    }
+    for from in removed {
+        changes.push(RuleChange { op: "removed", from: Some(from), to: None, before: Some(old[from].clone()), after: None });
+    }
+    for to in added {
+        changes.push(RuleChange { op: "added", from: None, to: Some(to), before: None, after: Some(new[to].clone()) });
+    }
+    changes.sort_by_key(|change| (change.to.or(change.from), change.from));
+    changes
+}
+
+/// 有効な版を変更し、その内容を保存した RoutingChain を返す
+async fn activate(
+    state: &AppState,
+    realm: &RealmName,
+    name: &RoutingChainName,
+    target: impl FnOnce(&RoutingChain) -> Result<u64, ApiError>,
+) -> Result<RoutingChain, ApiError> {
+    let (stored, revision) = require_versioned(state, realm, name).await?;
+    let version = target(&stored)?;
+    let Some((content, version_revision)) = get_chain_version(state, realm, name, version).await? else {
+        return Err(ApiError::NotFound(format!("Version {} of RoutingChain '{}' not found in realm '{}'", version, name, realm)));
+    };
+    if stored.active_version == Some(version) {
+        return Ok(stored);
+    }
+    // 保存後に参照先が削除されていることがあるため、有効にする前に確認し直す
+    let params = routing_chain::WriteParams { lint: false, revision: None, promote: true };
+    routing_chain::check_changes(state, &Urn::RoutingChain(realm.clone(), name.clone()), &content.chain, &params).await?;
+    let mut chain = with_versions(content.chain, &stored);
+    chain.previous_active_version = stored.active_version;
+    chain.active_version = Some(version);
+
+    let key = routing_chain::routing_chain_key(realm, name);
+    let txn = Txn::new()
+        .when([
+            Compare::mod_revision(key.as_str(), CompareOp::Equal, revision),
+            Compare::mod_revision(version_key(realm, name, version), CompareOp::Equal, version_revision),
+        ])
+        .and_then([TxnOp::put(key.as_str(), serde_json::to_vec(&chain)?, None)]);
+    let mut client = state.etcd_client.clone();
+    if !client.txn(txn).await?.succeeded() {
+        return Err(modified(name));
+    }
+    Ok(chain)
+}
+
+/// POST /realms/{realm}/routing-chains/{name}/promote
+pub async fn promote(
+    State(state): State<AppState>,
+    Path((realm, name)): Path<(String, String)>,
+    request: Option<Json<PromoteRequest>>,
+) -> Result<Json<RoutingChain>, ApiError> {
+    let (realm, name) = parse_names(&realm, &name)?;
+    let Json(request) = request.unwrap_or_default();
+    let chain = activate(&state, &realm, &name, |chain| {
+        request.version.or(chain.version).ok_or_else(|| ApiError::NotFound(format!("RoutingChain '{}' has no versions", chain.name)))
+    })
+    .await?;
+    Ok(Json(chain))
+}
+
+/// POST /realms/{realm}/routing-chains/{name}/rollback
+pub async fn rollback(
+    State(state): State<AppState>,
+    Path((realm, name)): Path<(String, String)>,
+    request: Option<Json<PromoteRequest>>,
+) -> Result<Json<RoutingChain>, ApiError> {
+    let (realm, name) = parse_names(&realm, &name)?;
+    let Json(request) = request.unwrap_or_default();
+    let chain = activate(&state, &realm, &name, |chain| {
+        request.version.or(chain.previous_active_version).ok_or_else(|| {
+            ApiError::Unprocessable(format!("RoutingChain '{}' has no previously active version to roll back to", chain.name))
+        })
+    })
+    .await?;
+    Ok(Json(chain))
+}
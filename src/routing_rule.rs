@@ -0,0 +1,183 @@
+//! RoutingChain のルール単位の操作 (`/realms/{realm}/routing-chains/{name}/rules`)
+//!
+//! `{rule}` はルールの位置 (0 始まりの数字) か `id` で指定する。変更は読み込んだ RoutingChain の mod_revision を条件に
+//! トランザクションで保存するため、同時に別の変更があれば 409 になる。`?revision=` を指定すると、その revision から
+//! 変更されていない場合だけ適用する。変更後の RoutingChain には作成・更新と同じ確認 (条件式、`?lint=true`、テストケース) を行い、
+//! 新しい版として保存する (`?promote=true` でその版を有効にする)。ルールは有効な版ではなく最新の版のものを扱う。
+use crate::db::AppState;
+use crate::error::ApiError;
+use crate::routing_chain::{self, Rule, RoutingChain, WriteParams};
+use crate::routing_chain_version;
+use crate::urn::Urn;
+use crate::validation::{self, RealmName, RoutingChainName};
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_rules).post(append_rule))
+        .route("/{rule}", get(get_rule).post(insert_rule).put(replace_rule).delete(delete_rule))
+        .route("/{rule}/move", post(move_rule))
+}
+
+/// ルールの一覧と、それを読んだ時点の revision
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RuleList {
+    revision: i64,
+    rules: Vec<Rule>,
+}
+
+/// ひとつのルールとその位置
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RuleEntry {
+    revision: i64,
+    index: usize,
+    rule: Rule,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MoveRequest {
+    // 移動後の位置
+    to: usize,
+}
+
+/// 最新の版の RoutingChain と、保存されている RoutingChain の mod_revision
+struct Stored {
+    realm: RealmName,
+    name: RoutingChainName,
+    chain: RoutingChain,
+    revision: i64,
+}
+
+async fn load(state: &AppState, realm: &str, name: &str) -> Result<Stored, ApiError> {
+    let realm = RealmName::parse("realm", realm)?;
+    let name = RoutingChainName::parse("routingChain", name)?;
+    match routing_chain_version::load_latest(state, &realm, &name).await? {
+        Some((chain, revision)) => Ok(Stored { realm, name, chain, revision }),
+        None => Err(ApiError::NotFound(format!("RoutingChain '{}' not found in realm '{}'", name, realm))),
+    }
+}
+
+/// `{rule}` をルールの位置に変換する (`end` なら末尾の次の位置も許す)
+fn locate(chain: &RoutingChain, rule: &str, end: bool) -> Result<usize, ApiError> {
+    let limit = if end { chain.rules.len() + 1 } else { chain.rules.len() };
+    let index = if rule.chars().all(|c| c.is_ascii_digit()) {
+        rule.parse::<usize>().ok().filter(|&index| index < limit)
+    } else {
+        chain.rules.iter().position(|r| r.id.as_deref() == Some(rule))
+    };
+    index.ok_or_else(|| ApiError::NotFound(format!("Rule '{}' not found in RoutingChain '{}'", rule, chain.name)))
+}
+
+/// 変更したルールを新しい版として保存し、保存後の revision を返す
+async fn save(state: &AppState, stored: &Stored, mut chain: RoutingChain, params: &WriteParams) -> Result<i64, ApiError> {
+    let revision = params.revision.unwrap_or(stored.revision);
+    if revision != stored.revision {
+        return Err(ApiError::Conflict(format!(
+            "RoutingChain '{}' was modified since revision {}; reload it and retry",
+            chain.name, revision
+        )));
+    }
+    let urn = Urn::RoutingChain(stored.realm.clone(), stored.name.clone());
+    routing_chain::check_changes(state, &urn, &chain, params).await?;
+    routing_chain_version::save_version(state, &stored.realm, &stored.name, &mut chain, Some(revision), params.promote).await
+}
+
+async fn list_rules(
+    State(state): State<AppState>,
+    Path((realm, name)): Path<(String, String)>,
+) -> Result<Json<RuleList>, ApiError> {
+    let stored = load(&state, &realm, &name).await?;
+    Ok(Json(RuleList { revision: stored.revision, rules: stored.chain.rules }))
+}
+
+async fn get_rule(
+    State(state): State<AppState>,
+    Path((realm, name, rule)): Path<(String, String, String)>,
+) -> Result<Json<RuleEntry>, ApiError> {
+    let stored = load(&state, &realm, &name).await?;
+    let index = locate(&stored.chain, &rule, false)?;
+    Ok(Json(RuleEntry { revision: stored.revision, index, rule: stored.chain.rules[index].clone() }))
+}
+
+async fn append_rule(
+    State(state): State<AppState>,
+    Path((realm, name)): Path<(String, String)>,
+    Query(params): Query<WriteParams>,
+    Json(rule): Json<Rule>,
+) -> Result<Json<RuleEntry>, ApiError> {
+    let stored = load(&state, &realm, &name).await?;
+    let index = stored.chain.rules.len();
+    insert_at(&state, stored, index, rule, &params).await
+}
+
+/// `{rule}` の位置に挿入する (既存のルールはひとつ後ろにずれる)
+async fn insert_rule(
+    State(state): State<AppState>,
+    Path((realm, name, rule)): Path<(String, String, String)>,
+    Query(params): Query<WriteParams>,
+    Json(new_rule): Json<Rule>,
+) -> Result<Json<RuleEntry>, ApiError> {
+    let stored = load(&state, &realm, &name).await?;
+    let index = locate(&stored.chain, &rule, true)?;
+    insert_at(&state, stored, index, new_rule, &params).await
+}
+
+async fn insert_at(state: &AppState, stored: Stored, index: usize, rule: Rule, params: &WriteParams) -> Result<Json<RuleEntry>, ApiError> {
+    let mut chain = stored.chain.clone();
+    chain.rules.insert(index, rule.clone());
+    let revision = save(state, &stored, chain, params).await?;
+    Ok(Json(RuleEntry { revision, index, rule }))
+}
+
+async fn replace_rule(
+    State(state): State<AppState>,
+    Path((realm, name, rule)): Path<(String, String, String)>,
+    Query(params): Query<WriteParams>,
+    Json(new_rule): Json<Rule>,
+) -> Result<Json<RuleEntry>, ApiError> {
+    let stored = load(&state, &realm, &name).await?;
+    let index = locate(&stored.chain, &rule, false)?;
+    let mut chain = stored.chain.clone();
+    chain.rules[index] = new_rule.clone();
+    let revision = save(&state, &stored, chain, &params).await?;
+    Ok(Json(RuleEntry { revision, index, rule: new_rule }))
+}
+
+async fn move_rule(
+    State(state): State<AppState>,
+    Path((realm, name, rule)): Path<(String, String, String)>,
+    Query(params): Query<WriteParams>,
+    Json(request): Json<MoveRequest>,
+) -> Result<Json<RuleEntry>, ApiError> {
+    let stored = load(&state, &realm, &name).await?;
+    let from = locate(&stored.chain, &rule, false)?;
+    if request.to >= stored.chain.rules.len() {
+        return Err(validation::invalid("to", format!("must be less than the number of rules ({})", stored.chain.rules.len())));
+    }
+    let mut chain = stored.chain.clone();
+    let moved = chain.rules.remove(from);
+    chain.rules.insert(request.to, moved.clone());
+    let revision = save(&state, &stored, chain, &params).await?;
+    Ok(Json(RuleEntry { revision, index: request.to, rule: moved }))
+}
+
+async fn delete_rule(
+    State(state): State<AppState>,
+    Path((realm, name, rule)): Path<(String, String, String)>,
+    Query(params): Query<WriteParams>,
+) -> Result<Json<RuleEntry>, ApiError> {
+    let stored = load(&state, &realm, &name).await?;
+    let index = locate(&stored.chain, &rule, false)?;
+    let mut chain = stored.chain.clone();
+    let removed = chain.rules.remove(index);
+    let revision = save(&state, &stored, chain, &params).await?;
+    Ok(Json(RuleEntry { revision, index, rule: removed }))
+}
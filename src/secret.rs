@@ -0,0 +1,105 @@
+use crate::db::AppState;
+use crate::error::ApiError;
+use axum::{
+    extract::{FromRequestParts, Query},
+    http::{header::AUTHORIZATION, request::Parts},
+};
+use ring::{hmac, rand::SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// 秘密情報を保持するフィールドの型 (署名キー、秘密鍵など)
+///
+/// Debug 出力では値を伏せる。レスポンスからの除去は `SecretFields::redact` で行う。
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Secret(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+/// `Secret` フィールドを持つモデルが実装するトレイト
+pub trait SecretFields {
+    /// レスポンスとして返す前に秘密情報を取り除く
+    fn redact(&mut self);
+
+    /// 更新リクエストで省略された秘密情報を保存済みの値で補う (write-only セマンティクス)
+    fn retain_secrets(&mut self, stored: &Self);
+
+    /// 保存時に暗号化する秘密情報への参照
+    fn secrets_mut(&mut self) -> Vec<&mut Secret>;
+}
+
+/// 提示されたトークンが設定値と一致するかを調べる
+///
+/// 比較にかかる時間から一致した長さが分からないよう、使い捨ての鍵による HMAC で照合する。
+pub fn token_matches(expected: &str, presented: &str) -> bool {
+    let Ok(key) = hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new()) else {
+        return false;
+    };
+    let tag = hmac::sign(&key, expected.as_bytes());
+    hmac::verify(&key, presented.as_bytes(), tag.as_ref()).is_ok()
+}
+
+#[derive(Deserialize)]
+struct RevealParams {
+    reveal: Option<String>,
+}
+
+/// `?reveal=secrets` による秘密情報の開示要求
+///
+/// 開示には `REVEAL_SECRETS_TOKEN` に設定したトークンを
+/// `Authorization: Bearer` ヘッダーで提示する必要がある。
+pub struct Reveal(bool);
+
+impl Reveal {
+    /// 開示が許可されていなければ秘密情報を取り除いて返す
+    pub fn apply<T: SecretFields>(&self, mut doc: T) -> T {
+        if !self.0 {
+            doc.redact();
+        }
+        doc
+    }
+
+    pub fn apply_all<T: SecretFields>(&self, docs: Vec<T>) -> Vec<T> {
+        docs.into_iter().map(|doc| self.apply(doc)).collect()
+    }
+}
+
+impl FromRequestParts<AppState> for Reveal {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<RevealParams>::try_from_uri(&parts.uri)
+            .map_err(|e| ApiError::BadRequest(e.body_text()))?;
+
+        match params.reveal.as_deref() {
+            None => Ok(Reveal(false)),
+            Some("secrets") => {
+                let presented = parts
+                    .headers
+                    .get(AUTHORIZATION)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.strip_prefix("Bearer "));
+                match (&state.reveal_secrets_token, presented) {
+                    (Some(expected), Some(token)) if token_matches(expected, token) => Ok(Reveal(true)),
+                    _ => Err(ApiError::Forbidden("Revealing secrets requires a valid reveal token.".to_string())),
+                }
+            }
+            Some(other) => Err(ApiError::BadRequest(format!("Unsupported reveal value '{}'", other))),
+        }
+    }
+}
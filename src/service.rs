@@ -1,6 +1,8 @@
 use crate::db::AppState;
 use crate::error::ApiError;
+use crate::urn::Urn;
 use crate::utils::get_from_etcd;
+use crate::validation::{HubName, RealmName, ServiceName};
 use axum::{
     extract::{Path, State},
     routing::get,
@@ -20,6 +22,8 @@ pub struct Service {
     pub title: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    // Realm の URN (サーバーが設定する。入力では省略でき、名前だけの旧形式も受け付ける)
+    #[serde(default)]
     pub realm: String,
     pub hub_name: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -50,11 +54,11 @@ pub fn routes() -> Router<AppState> {
         .route("/{service_name}", get(get_service).delete(delete_service))
 }
 
-fn service_key(realm: &str, hub: &str, name: &str) -> String {
+pub fn service_key(realm: &RealmName, hub: &HubName, name: &ServiceName) -> String {
     format!("/realms/{}/hubs/{}/services/{}", realm, hub, name)
 }
 
-fn service_prefix(realm: &str, hub: &str) -> String {
+fn service_prefix(realm: &RealmName, hub: &HubName) -> String {
     format!("/realms/{}/hubs/{}/services/", realm, hub)
 }
 
@@ -63,7 +67,7 @@ async fn list_services(
     Path((realm, hub_name)): Path<(String, String)>,
 ) -> Result<Json<Vec<Service>>, ApiError> {
     let mut client = state.etcd_client.clone();
-    let prefix = service_prefix(&realm, &hub_name);
+    let prefix = service_prefix(&RealmName::parse("realm", &realm)?, &HubName::parse("hub", &hub_name)?);
     let resp = client.get(prefix, Some(etcd_client::GetOptions::new().with_prefix())).await?;
     let services = resp
         .kvs()
@@ -79,16 +83,19 @@ async fn add_service(
     Json(mut service): Json<Service>,
 ) -> Result<Json<Service>, ApiError> {
     let mut client = state.etcd_client.clone();
-    let key = service_key(&realm, &hub_name, &service.name);
+    let (realm, hub_name) = (RealmName::parse("realm", &realm)?, HubName::parse("hub", &hub_name)?);
+    let name = ServiceName::parse("name", &service.name)?;
+    let key = service_key(&realm, &hub_name, &name);
 
+    #[allow(clippy::unnecessary_first_then_check)]
     if get_from_etcd(&state, &key).await?.kvs().first().is_some() {
         return Err(ApiError::Conflict(format!("Service '{}' already exists in hub '{}'.", service.name, hub_name)));
     }
     
-    service.realm = realm.clone();
-    service.hub_name = hub_name.clone();
-    service.hub = Some(format!("urn:chip-in:hub:{}:{}", realm, hub_name));
-    service.urn = Some(format!("urn:chip-in:service:{}:{}:{}", realm, hub_name, service.name));
+    service.realm = Urn::Realm(realm.clone()).to_string();
+    service.hub_name = hub_name.to_string();
+    service.hub = Some(Urn::Hub(realm.clone(), hub_name.clone()).to_string());
+    service.urn = Some(Urn::Service(realm, hub_name, name).to_string());
 
     let value = serde_json::to_vec(&service)?;
     client.put(key, value, None).await?;
@@ -101,12 +108,14 @@ async fn update_service(
     Json(mut service): Json<Service>,
 ) -> Result<Json<Service>, ApiError> {
     let mut client = state.etcd_client.clone();
-    let key = service_key(&realm, &hub_name, &service.name);
+    let (realm, hub_name) = (RealmName::parse("realm", &realm)?, HubName::parse("hub", &hub_name)?);
+    let name = ServiceName::parse("name", &service.name)?;
+    let key = service_key(&realm, &hub_name, &name);
 
-    service.realm = realm.clone();
-    service.hub_name = hub_name.clone();
-    service.hub = Some(format!("urn:chip-in:hub:{}:{}", realm, hub_name));
-    service.urn = Some(format!("urn:chip-in:service:{}:{}:{}", realm, hub_name, service.name));
+    service.realm = Urn::Realm(realm.clone()).to_string();
+    service.hub_name = hub_name.to_string();
+    service.hub = Some(Urn::Hub(realm.clone(), hub_name.clone()).to_string());
+    service.urn = Some(Urn::Service(realm, hub_name, name).to_string());
 
     let value = serde_json::to_vec(&service)?;
     client.put(key, value, None).await?;
@@ -114,7 +123,7 @@ async fn update_service(
 }
 
 async fn get_service(State(state): State<AppState>, Path((realm, hub_name, name)): Path<(String, String, String)>) -> Result<Json<Service>, ApiError> {
-    let key = service_key(&realm, &hub_name, &name);
+    let key = service_key(&RealmName::parse("realm", &realm)?, &HubName::parse("hub", &hub_name)?, &ServiceName::parse("service", &name)?);
     if let Some(kv) = get_from_etcd(&state, &key).await?.kvs().first() {
         let service = serde_json::from_slice(kv.value())?;
         Ok(Json(service))
@@ -125,7 +134,7 @@ async fn get_service(State(state): State<AppState>, Path((realm, hub_name, name)
 
 async fn delete_service(State(state): State<AppState>, Path((realm, hub_name, name)): Path<(String, String, String)>) -> Result<Json<Service>, ApiError> {
     let mut client = state.etcd_client.clone();
-    let key = service_key(&realm, &hub_name, &name);
+    let key = service_key(&RealmName::parse("realm", &realm)?, &HubName::parse("hub", &hub_name)?, &ServiceName::parse("service", &name)?);
     let opts = etcd_client::DeleteOptions::new().with_prev_key();
     let resp = client.delete(key, Some(opts)).await?;
     if let Some(kv) = resp.prev_kvs().first() {
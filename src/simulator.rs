@@ -0,0 +1,527 @@
+//! RoutingChain のシミュレーター
+//!
+//! 合成したリクエストに対してルールを先頭から順に評価し、各ステップの結果を記録する。
+//!
+//! - `SetVariables` は変数を、`SetHeaders` は `target` ごとのヘッダーを更新する (`target` が `request` ならリクエストのヘッダーも書き換え、以降のルールの条件に反映する)
+//! - `Jump` は参照先の RoutingChain を評価し、終端のアクションに達しなければ呼び出し元の次のルールから続ける
+//! - `Proxy` / `Redirect` / `Respond` に達した時点で評価を終え、それを結果 (`decision`) とする
+//! - `Authenticate` は `identity` を満たせば転送するヘッダーを設定して続け、満たさなければ応答のステータス (`decision.status`) で評価を終える
+//! - 重み付きの `Proxy` は、リクエストの `hash`、なければスティッキーキーのハッシュ値、どちらもなければ 0 で転送先を選ぶ (`decision.selection`)
+//!
+//! 参照先の RoutingChain が見つからない、`Jump` が循環するなどの場合は `error` を設定して評価を打ち切る。
+//!
+//! RoutingChain の `tests` は作成・更新のたびにここで実行し、失敗するテストがあれば変更を拒否する。
+use crate::db::AppState;
+use crate::error::ApiError;
+use crate::match_expr::{self, Request};
+use crate::routing_chain::{
+    self, Action, AuthenticateAction, ExpectedAction, ProxyAction, RoutingChain, StickyKey, TestCase, Unauthenticated,
+};
+use crate::routing_chain_version;
+use crate::urn::{self, Urn};
+use crate::utils::get_from_etcd;
+use crate::validation::{self, RealmName, RoutingChainName};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+use std::net::IpAddr;
+
+/// `Jump` の入れ子の上限
+const MAX_JUMP_DEPTH: usize = 16;
+/// 評価するルールの数の上限
+const MAX_STEPS: usize = 1000;
+
+/// シミュレーターに与えるリクエスト
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct SimulatedRequest {
+    #[serde(default = "default_method")]
+    pub method: String,
+    // `https://www.example.com/path?key=value` の形式
+    pub url: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub cookies: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<String>,
+    // 重み付きの `Proxy` で転送先を選ぶハッシュ値 (スティッキーキーより優先する)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<u64>,
+    // ログイン済みのユーザー (省略すると未認証)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<Identity>,
+}
+
+/// セッションから得られるユーザーの情報
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Identity {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    // `sub` などのクレーム
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub claims: BTreeMap<String, String>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+impl SimulatedRequest {
+    /// 条件式の評価に使う形に変換する (`field` はエラーで示すフィールド名の接頭辞)
+    pub fn to_request(&self, field: &str) -> Result<Request, ApiError> {
+        let method = self.method.to_ascii_uppercase();
+        if method.is_empty() || !method.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(validation::invalid(&format!("{}method", field), format!("'{}' is not an HTTP method", self.method)));
+        }
+        let url_field = format!("{}url", field);
+        let url = url::Url::parse(&self.url).map_err(|e| validation::invalid(&url_field, format!("'{}' is not a valid URL: {}", self.url, e)))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(validation::invalid(&url_field, "the scheme must be 'http' or 'https'"));
+        }
+        let host = url.host_str().ok_or_else(|| validation::invalid(&url_field, "the URL must have a host"))?;
+        let client_ip = match &self.client_ip {
+            Some(ip) => Some(ip.parse::<IpAddr>().map_err(|_| {
+                validation::invalid(&format!("{}clientIp", field), format!("'{}' is not an IP address", ip))
+            })?),
+            None => None,
+        };
+
+        let headers: BTreeMap<String, String> =
+            self.headers.iter().map(|(name, value)| (name.to_ascii_lowercase(), value.clone())).collect();
+        let mut query = BTreeMap::new();
+        for (key, value) in url.query_pairs() {
+            query.entry(key.into_owned()).or_insert_with(|| value.into_owned());
+        }
+        // `Cookie` ヘッダーと `cookies` の両方を使い、同じ名前は `cookies` を優先する
+        let mut cookies: BTreeMap<String, String> = headers
+            .get("cookie")
+            .into_iter()
+            .flat_map(|cookie| cookie.split(';'))
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+        cookies.extend(self.cookies.clone());
+
+        Ok(Request {
+            host: host.trim_start_matches('[').trim_end_matches(']').to_string(),
+            path: url.path().to_string(),
+            method,
+            scheme: url.scheme().to_string(),
+            client_ip,
+            headers,
+            query,
+            cookies,
+        })
+    }
+}
+
+/// 評価したルールごとの記録 (変数とヘッダーはルールを適用した後の状態)
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Step {
+    // ルールを含む RoutingChain の URN
+    pub chain: String,
+    pub rule: usize,
+    // `Jump` の入れ子の深さ (最初の RoutingChain は 0)
+    pub depth: usize,
+    #[serde(rename = "match")]
+    pub match_expr: String,
+    pub matched: bool,
+    // 条件に一致した場合に適用したアクション
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<Action>,
+    pub variables: BTreeMap<String, String>,
+    pub headers: BTreeMap<String, BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 最終的に適用する `Proxy` / `Redirect` / `Respond`、またはリクエストを拒否した `Authenticate`
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Decision {
+    pub chain: String,
+    pub rule: usize,
+    pub action: Action,
+    // 重み付きの `Proxy` で選んだ転送先
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selection: Option<Selection>,
+    // Hub が返す応答のステータス、リダイレクト先、その理由
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// `Authenticate` の要件を満たさない理由と応答のステータス
+fn authorize(action: &AuthenticateAction, identity: Option<&Identity>) -> Option<(u16, String)> {
+    let Some(identity) = identity else {
+        let status = match action.unauthenticated {
+            Unauthenticated::Reject => 401,
+            Unauthenticated::Redirect => 302,
+        };
+        return Some((status, "not authenticated".to_string()));
+    };
+    if let Some(role) = action.roles.iter().find(|role| !identity.roles.contains(role)) {
+        return Some((403, format!("missing role '{}'", role)));
+    }
+    for (name, values) in &action.claims {
+        if !identity.claims.get(name).is_some_and(|value| values.contains(value)) {
+            return Some((403, format!("claim '{}' is not one of {}", name, json!(values))));
+        }
+    }
+    None
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Selection {
+    pub hash: u64,
+    // ハッシュ値の出どころ (`request` / `cookie` / `header` / `default`)
+    pub source: &'static str,
+    pub index: usize,
+    pub target: String,
+}
+
+/// 重み付きの `Proxy` の転送先を選ぶ
+fn select(proxy: &ProxyAction, request: &Request, hash: Option<u64>) -> Option<Selection> {
+    let sticky = match &proxy.sticky {
+        Some(StickyKey::Cookie(name)) => request.cookies.get(name).map(|value| ("cookie", value)),
+        Some(StickyKey::Header(name)) => request.headers.get(&name.to_ascii_lowercase()).map(|value| ("header", value)),
+        None => None,
+    };
+    let (hash, source) = match (hash, sticky) {
+        (Some(hash), _) => (hash, "request"),
+        (None, Some((source, value))) => (routing_chain::sticky_hash(value), source),
+        (None, None) => (0, "default"),
+    };
+    let (index, target) = proxy.select(hash)?;
+    Some(Selection { hash, source, index, target: target.target.clone() })
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Trace {
+    pub steps: Vec<Step>,
+    // 終端のアクションに達しなかった場合は `null`
+    pub decision: Option<Decision>,
+    pub variables: BTreeMap<String, String>,
+    // `SetHeaders` の `target` ごとのヘッダー (`request` は受け取ったヘッダーを含む)
+    pub headers: BTreeMap<String, BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 評価中の RoutingChain と次に評価するルールの位置
+struct Frame {
+    urn: Urn,
+    chain: RoutingChain,
+    next: usize,
+}
+
+/// `chain` (`urn` が指す RoutingChain、または保存前の内容) をリクエストに対して評価する
+///
+/// `input` からは `Proxy` の転送先を選ぶ `hash` と、`Authenticate` で確認する `identity` を使う。
+pub async fn run(state: &AppState, urn: &Urn, chain: &RoutingChain, request: Request, input: &SimulatedRequest) -> Result<Trace, ApiError> {
+    let mut request = request;
+    // `preserveQuery` で引き継ぐクエリ文字列
+    let query = url::Url::parse(&input.url).ok().and_then(|url| url.query().map(str::to_string));
+    let mut variables = BTreeMap::new();
+    let mut headers = BTreeMap::from([("request".to_string(), request.headers.clone())]);
+    let mut steps = Vec::new();
+    let mut decision = None;
+    let mut error = None;
+    let mut stack = vec![Frame { urn: urn.clone(), chain: chain.clone(), next: 0 }];
+
+    while let Some(frame) = stack.last_mut() {
+        let Some(rule) = frame.chain.rules.get(frame.next).cloned() else {
+            stack.pop();
+            continue;
+        };
+        if steps.len() >= MAX_STEPS {
+            error = Some(format!("evaluation stopped after {} rules", MAX_STEPS));
+            break;
+        }
+        let index = frame.next;
+        frame.next += 1;
+        let current = frame.urn.clone();
+        let mut step = Step {
+            chain: current.to_string(),
+            rule: index,
+            depth: stack.len() - 1,
+            match_expr: rule.match_expr.clone(),
+            matched: false,
+            action: None,
+            variables: BTreeMap::new(),
+            headers: BTreeMap::new(),
+            error: None,
+        };
+        match match_expr::parse(&rule.match_expr) {
+            Ok(expr) => step.matched = expr.eval(&request, &variables),
+            Err(e) => step.error = Some(format!("invalid match expression: {}", e)),
+        }
+
+        let mut jump = None;
+        if step.matched {
+            match &rule.action {
+                Action::SetVariables(action) => variables.extend(action.variables.clone()),
+                Action::SetHeaders(action) => {
+                    let set: Vec<(String, String)> =
+                        action.headers.iter().map(|(name, value)| (name.to_ascii_lowercase(), value.clone())).collect();
+                    if action.target == "request" {
+                        request.headers.extend(set.clone());
+                    }
+                    headers.entry(action.target.clone()).or_default().extend(set);
+                }
+                Action::AccessLog(_) => {}
+                Action::Proxy(_) | Action::Redirect(_) | Action::Respond(_) => {
+                    let mut outcome = Decision {
+                        chain: current.to_string(),
+                        rule: index,
+                        action: rule.action.clone(),
+                        selection: None,
+                        status: None,
+                        location: None,
+                        reason: None,
+                    };
+                    match &rule.action {
+                        Action::Proxy(proxy) => outcome.selection = select(proxy, &request, input.hash),
+                        Action::Redirect(redirect) => {
+                            outcome.status = Some(redirect.status());
+                            outcome.location = Some(redirect.location(query.as_deref()));
+                        }
+                        Action::Respond(respond) => outcome.status = Some(respond.status),
+                        _ => {}
+                    }
+                    decision = Some(outcome);
+                }
+                Action::Authenticate(action) => match authorize(action, input.identity.as_ref()) {
+                    Some((status, reason)) => {
+                        decision = Some(Decision {
+                            chain: current.to_string(),
+                            rule: index,
+                            action: rule.action.clone(),
+                            selection: None,
+                            status: Some(status),
+                            location: action.login_url.clone().filter(|_| status == 302),
+                            reason: Some(reason),
+                        });
+                    }
+                    None => {
+                        // クレームのないヘッダーは送らない
+                        let identity = input.identity.as_ref().map(|identity| &identity.claims);
+                        let set = headers.entry("request".to_string()).or_default();
+                        for (name, claim) in &action.identity_headers {
+                            let name = name.to_ascii_lowercase();
+                            match identity.and_then(|claims| claims.get(claim)) {
+                                Some(value) => {
+                                    request.headers.insert(name.clone(), value.clone());
+                                    set.insert(name, value.clone());
+                                }
+                                None => {
+                                    request.headers.remove(&name);
+                                    set.remove(&name);
+                                }
+                            }
+                        }
+                    }
+                },
+                Action::Jump(action) => match jump_target(state, &current, &action.target, urn, chain, &stack).await? {
+                    Ok(frame) => jump = Some(frame),
+                    Err(message) => {
+                        step.error = Some(message.clone());
+                        error = Some(message);
+                    }
+                },
+            }
+            step.action = Some(rule.action.clone());
+        }
+        step.variables = variables.clone();
+        step.headers = headers.clone();
+        steps.push(step);
+
+        if decision.is_some() || error.is_some() {
+            break;
+        }
+        if let Some(frame) = jump {
+            stack.push(frame);
+        }
+    }
+
+    Ok(Trace { steps, decision, variables, headers, error })
+}
+
+/// `Jump` の参照先を読み込む。評価を続けられない場合は理由を `Err` で返す
+async fn jump_target(
+    state: &AppState,
+    current: &Urn,
+    target: &str,
+    root_urn: &Urn,
+    root: &RoutingChain,
+    stack: &[Frame],
+) -> Result<Result<Frame, String>, ApiError> {
+    let urn = match urn::reference("action.target", target, "routing-chain", current.realm()) {
+        Ok(urn) => urn,
+        Err(_) => return Ok(Err(format!("'{}' does not refer to a routing chain", target))),
+    };
+    if stack.iter().any(|frame| frame.urn == urn) {
+        return Ok(Err(format!("jump to '{}' would loop", urn)));
+    }
+    if stack.len() > MAX_JUMP_DEPTH {
+        return Ok(Err(format!("jumps are nested deeper than {}", MAX_JUMP_DEPTH)));
+    }
+    // 保存前の内容を評価している場合、自身への参照はその内容を使う
+    let chain = if urn == *root_urn {
+        root.clone()
+    } else {
+        match get_from_etcd(state, &urn.key()).await?.kvs().first() {
+            Some(kv) => serde_json::from_slice(kv.value())?,
+            None => return Ok(Err(format!("routing chain '{}' does not exist", urn))),
+        }
+    };
+    Ok(Ok(Frame { urn, chain, next: 0 }))
+}
+
+pub async fn simulate_routing_chain(
+    State(state): State<AppState>,
+    Path((realm, name)): Path<(String, String)>,
+    Json(input): Json<SimulatedRequest>,
+) -> Result<Json<Trace>, ApiError> {
+    let realm = RealmName::parse("realm", &realm)?;
+    let name = RoutingChainName::parse("routingChain", &name)?;
+    let request = input.to_request("")?;
+    let urn = Urn::RoutingChain(realm.clone(), name.clone());
+    let chain: RoutingChain = match get_from_etcd(&state, &urn.key()).await?.kvs().first() {
+        Some(kv) => serde_json::from_slice(kv.value())?,
+        None => return Err(ApiError::NotFound(format!("RoutingChain '{}' not found in realm '{}'", name, realm))),
+    };
+    Ok(Json(run(&state, &urn, &chain, request, &input).await?))
+}
+
+/// テストケースの実行結果
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TestResult {
+    pub name: String,
+    pub passed: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<String>,
+    pub decision: Option<Decision>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TestReport {
+    pub passed: bool,
+    pub results: Vec<TestResult>,
+}
+
+/// `chain` のテストケースをすべて実行する
+pub async fn run_tests(state: &AppState, urn: &Urn, chain: &RoutingChain) -> Result<TestReport, ApiError> {
+    let mut names = HashSet::new();
+    let mut requests = Vec::new();
+    for (i, test) in chain.tests.iter().flatten().enumerate() {
+        if test.name.is_empty() || !names.insert(test.name.as_str()) {
+            return Err(validation::invalid(&format!("tests[{}].name", i), format!("'{}' must be a non-empty, unique name", test.name)));
+        }
+        requests.push(test.request.to_request(&format!("tests[{}].request.", i))?);
+    }
+
+    let mut results = Vec::new();
+    for (test, request) in chain.tests.iter().flatten().zip(requests) {
+        let trace = run(state, urn, chain, request, &test.request).await?;
+        let failures = check(test, &trace);
+        results.push(TestResult { name: test.name.clone(), passed: failures.is_empty(), failures, decision: trace.decision });
+    }
+    Ok(TestReport { passed: results.iter().all(|result| result.passed), results })
+}
+
+/// 期待と異なる点を列挙する
+fn check(test: &TestCase, trace: &Trace) -> Vec<String> {
+    let mut failures = Vec::new();
+    if let Some(error) = &trace.error {
+        failures.push(format!("evaluation failed: {}", error));
+    }
+    let (action, target) = match trace.decision.as_ref() {
+        Some(Decision { action: Action::Proxy(proxy), selection, .. }) => {
+            (ExpectedAction::Proxy, selection.as_ref().map(|selection| selection.target.as_str()).or(proxy.target.as_deref()))
+        }
+        Some(Decision { action: Action::Redirect(redirect), .. }) => (ExpectedAction::Redirect, Some(redirect.target.as_str())),
+        Some(Decision { action: Action::Respond(_), .. }) => (ExpectedAction::Respond, None),
+        Some(Decision { action: Action::Authenticate(_), .. }) => (ExpectedAction::Authenticate, None),
+        _ => (ExpectedAction::None, None),
+    };
+    if action != test.expect.action {
+        failures.push(format!("expected action {}, got {}", json!(test.expect.action), json!(action)));
+    }
+    if let Some(expected) = &test.expect.target {
+        if target != Some(expected.as_str()) {
+            failures.push(format!("expected target '{}', got {}", expected, target.map_or("none".to_string(), |t| format!("'{}'", t))));
+        }
+    }
+    if let Some(expected) = test.expect.status {
+        let status = trace.decision.as_ref().and_then(|decision| decision.status);
+        if status != Some(expected) {
+            failures.push(format!("expected status {}, got {}", expected, status.map_or("none".to_string(), |s| s.to_string())));
+        }
+    }
+    for (set, expected) in &test.expect.headers {
+        for (name, value) in expected {
+            let actual = trace.headers.get(set).and_then(|headers| headers.get(&name.to_ascii_lowercase()));
+            if actual != Some(value) {
+                failures.push(format!(
+                    "expected {} header '{}' to be '{}', got {}",
+                    set,
+                    name,
+                    value,
+                    actual.map_or("none".to_string(), |v| format!("'{}'", v))
+                ));
+            }
+        }
+    }
+    failures
+}
+
+/// テストケースを実行し、失敗したものがあれば 422 を返す
+pub async fn require_passing_tests(state: &AppState, urn: &Urn, chain: &RoutingChain) -> Result<(), ApiError> {
+    let report = run_tests(state, urn, chain).await?;
+    if report.passed {
+        return Ok(());
+    }
+    let failed: Vec<TestResult> = report.results.into_iter().filter(|result| !result.passed).collect();
+    let names: Vec<&str> = failed.iter().map(|result| result.name.as_str()).collect();
+    Err(ApiError::UnprocessableDetails {
+        message: format!("RoutingChain '{}' fails its tests: {}", chain.name, names.join(", ")),
+        details: json!({ "tests": failed }),
+    })
+}
+
+/// 保存せずに、送られた RoutingChain の内容でテストケースを実行する
+///
+/// `tests` を省略した場合は、保存されている最新の版のテストケースを実行する。
+pub async fn test_routing_chain(
+    State(state): State<AppState>,
+    Path((realm, name)): Path<(String, String)>,
+    Json(mut chain): Json<RoutingChain>,
+) -> Result<Json<TestReport>, ApiError> {
+    let realm = RealmName::parse("realm", &realm)?;
+    let name = RoutingChainName::parse("routingChain", &name)?;
+    if chain.name != *name {
+        return Err(validation::invalid("name", format!("'{}' does not match the routing chain '{}' in the path", chain.name, name)));
+    }
+    routing_chain::validate_rules(&chain)?;
+    if chain.tests.is_none() {
+        chain.tests = routing_chain_version::load_latest(&state, &realm, &name).await?.and_then(|(stored, _)| stored.tests);
+    }
+    let urn = Urn::RoutingChain(realm, name);
+    Ok(Json(run_tests(&state, &urn, &chain).await?))
+}
@@ -1,6 +1,11 @@
 use crate::db::AppState;
+use crate::dns::{self, RecordType};
 use crate::error::ApiError;
+use crate::host_index::{self, IndexedWrite};
+use crate::idn;
+use crate::urn::{self, Urn};
 use crate::utils::get_from_etcd;
+use crate::validation::{self, RealmName, SubdomainName, ZoneName};
 use axum::{
     extract::{Path, State},
     routing::get,
@@ -12,40 +17,132 @@ use serde::{Deserialize, Serialize};
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct Subdomain {
+    // A-label 形式の名前 (Unicode で指定しても正規化して保存する)
     pub name: String,
     pub title: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub fqdn: Option<String>,
+    // 国際化ドメイン名の場合の U-label 形式の名前と FQDN (読み取り専用)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_fqdn: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub zone: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    // 配信先の Realm の URN (Realm 名だけの旧形式も受け付ける)
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub destination_realm: Option<String>,
     #[serde(default)]
     pub share_cookie: bool,
+    // 明示的に設定する DNS レコード
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub records: Vec<SubdomainRecord>,
 }
 
+/// Subdomain の FQDN に設定する DNS レコード
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct SubdomainRecord {
+    #[serde(rename = "type")]
+    pub record_type: RecordType,
+    // 省略時は DNS_DEFAULT_TTL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+    pub data: String,
+}
+
+/// TTL の上限 (RFC 2181 8)
+const MAX_TTL: u32 = 2_147_483_647;
+
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/", get(list_subdomains).post(add_subdomain).put(update_subdomain))
         .route("/{subdomain_name}", get(get_subdomain).delete(delete_subdomain))
 }
 
-fn subdomain_key(realm: &str, zone: &str, name: &str) -> String {
+pub fn subdomain_key(realm: &RealmName, zone: &ZoneName, name: &SubdomainName) -> String {
     format!("/realms/{}/zones/{}/subdomains/{}", realm, zone, name)
 }
 
-fn subdomain_prefix(realm: &str, zone: &str) -> String {
+pub fn subdomain_prefix(realm: &RealmName, zone: &ZoneName) -> String {
     format!("/realms/{}/zones/{}/subdomains/", realm, zone)
 }
 
+/// 名前を検証して A-label 形式に正規化し、FQDN と表示用の U-label 形式を設定する
+///
+/// 名前は `@` (Zone の apex) か DNS ラベルの並びで、`*` は先頭のラベルとしてのみ使える (`*`、`*.apps` など)。
+fn normalize(subdomain: &mut Subdomain, realm: &RealmName, zone: &ZoneName) -> Result<SubdomainName, ApiError> {
+    let name = SubdomainName::parse("name", &subdomain.name)?;
+    let fqdn = fqdn_of(&name, zone);
+    if fqdn.len() > validation::MAX_FQDN_LEN {
+        return Err(validation::invalid("name", format!("'{}' is longer than {} characters", fqdn, validation::MAX_FQDN_LEN)));
+    }
+    subdomain.name = name.to_string();
+    subdomain.zone = Some(Urn::Zone(realm.clone(), zone.clone()).to_string());
+    subdomain.display_name = idn::display(&subdomain.name);
+    subdomain.display_fqdn = idn::display(&fqdn);
+    subdomain.fqdn = Some(fqdn);
+    if let Some(destination) = &subdomain.destination_realm {
+        subdomain.destination_realm = Some(urn::reference("destinationRealm", destination, "realm", realm)?.to_string());
+    }
+    Ok(name)
+}
+
+/// Subdomain 名と Zone 名から FQDN を求める (`@` は Zone 自身、`*.apps` は `*.apps.{zone}`)
+pub fn fqdn_of(name: &str, zone: &str) -> String {
+    if name == "@" { zone.to_string() } else { format!("{}.{}", name, zone) }
+}
+
+/// レコードの種類ごとの書式と、CNAME が他のレコードと共存しないことを確認する
+fn validate_records(subdomain: &Subdomain) -> Result<(), ApiError> {
+    for (i, record) in subdomain.records.iter().enumerate() {
+        if record.ttl.is_some_and(|ttl| ttl > MAX_TTL) {
+            return Err(validation::invalid(&format!("records[{}].ttl", i), format!("must be at most {}", MAX_TTL)));
+        }
+        dns::normalize_data(record.record_type, &record.data).map_err(|e| validation::invalid(&format!("records[{}].data", i), e))?;
+    }
+    if subdomain.records.iter().any(|r| r.record_type == RecordType::CNAME) {
+        if subdomain.name == "@" {
+            return Err(validation::invalid("records", "the zone apex cannot have a CNAME record"));
+        }
+        if subdomain.records.len() > 1 {
+            return Err(validation::invalid(
+                "records",
+                format!("a CNAME record cannot coexist with other records for '{}'", subdomain.name),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// `urn:chip-in:subdomain:{realm}:{zone}:{name}` 形式の参照を (Realm, Zone, 名前) に分解する
+pub fn parse_reference(reference: &str) -> Option<(RealmName, ZoneName, SubdomainName)> {
+    match Urn::parse("subdomain", reference) {
+        Ok(Urn::Subdomain(realm, zone, name)) => Some((realm, zone, name)),
+        _ => None,
+    }
+}
+
+/// `urn:chip-in:subdomain:{realm}:{zone}:{name}` 形式の参照から Subdomain の FQDN を求める
+pub async fn resolve_fqdn(state: &AppState, reference: &str) -> Result<Option<String>, ApiError> {
+    let Some((realm, zone, name)) = parse_reference(reference) else {
+        return Ok(None);
+    };
+    match get_from_etcd(state, &subdomain_key(&realm, &zone, &name)).await?.kvs().first() {
+        Some(kv) => Ok(serde_json::from_slice::<Subdomain>(kv.value())?.fqdn),
+        None => Ok(None),
+    }
+}
+
 async fn list_subdomains(
     State(state): State<AppState>,
     Path((realm, zone_name)): Path<(String, String)>,
 ) -> Result<Json<Vec<Subdomain>>, ApiError> {
     let mut client = state.etcd_client.clone();
-    let prefix = subdomain_prefix(&realm, &zone_name);
+    let prefix = subdomain_prefix(&RealmName::parse("realm", &realm)?, &ZoneName::parse("zone", &zone_name)?);
     let resp = client.get(prefix, Some(etcd_client::GetOptions::new().with_prefix())).await?;
     let subdomains = resp
         .kvs()
@@ -60,21 +157,26 @@ async fn add_subdomain(
     Path((realm, zone_name)): Path<(String, String)>,
     Json(mut subdomain): Json<Subdomain>,
 ) -> Result<Json<Subdomain>, ApiError> {
-    let mut client = state.etcd_client.clone();
-    let key = subdomain_key(&realm, &zone_name, &subdomain.name);
+    let (realm, zone_name) = (RealmName::parse("realm", &realm)?, ZoneName::parse("zone", &zone_name)?);
+    let name = normalize(&mut subdomain, &realm, &zone_name)?;
+    let key = subdomain_key(&realm, &zone_name, &name);
 
+    #[allow(clippy::unnecessary_first_then_check)]
     if get_from_etcd(&state, &key).await?.kvs().first().is_some() {
         return Err(ApiError::Conflict(format!(
             "Subdomain '{}' already exists in zone '{}'.",
             subdomain.name, zone_name
         )));
     }
-    
-    subdomain.zone = Some(format!("urn:chip-in:zone:{}:{}", realm, zone_name));
-    subdomain.fqdn = Some(if subdomain.name == "@" { zone_name.clone() } else { format!("{}.{}", subdomain.name, zone_name) });
-    
-    let value = serde_json::to_vec(&subdomain)?;
-    client.put(key, value, None).await?;
+    validate_records(&subdomain)?;
+
+    // FQDN は全 Realm で一意
+    let mut write = IndexedWrite::new();
+    write.require_absent(&key);
+    write.claim(&state, &host_index::host_key(&fqdn_of(&name, &zone_name)), &Urn::Subdomain(realm.clone(), zone_name.clone(), name.clone()).to_string()).await?;
+    write.put(&key, serde_json::to_vec(&subdomain)?);
+    write.commit(&state).await?;
+    dns::schedule_sync(&state, &realm, &zone_name);
     Ok(Json(subdomain))
 }
 
@@ -83,14 +185,16 @@ async fn update_subdomain(
     Path((realm, zone_name)): Path<(String, String)>,
     Json(mut subdomain): Json<Subdomain>,
 ) -> Result<Json<Subdomain>, ApiError> {
-    let mut client = state.etcd_client.clone();
-    let key = subdomain_key(&realm, &zone_name, &subdomain.name);
-
-    subdomain.zone = Some(format!("urn:chip-in:zone:{}:{}", realm, zone_name));
-    subdomain.fqdn = Some(if subdomain.name == "@" { zone_name.clone() } else { format!("{}.{}", subdomain.name, zone_name) });
+    let (realm, zone_name) = (RealmName::parse("realm", &realm)?, ZoneName::parse("zone", &zone_name)?);
+    let name = normalize(&mut subdomain, &realm, &zone_name)?;
+    let key = subdomain_key(&realm, &zone_name, &name);
+    validate_records(&subdomain)?;
 
-    let value = serde_json::to_vec(&subdomain)?;
-    client.put(key, value, None).await?;
+    let mut write = IndexedWrite::new();
+    write.claim(&state, &host_index::host_key(&fqdn_of(&name, &zone_name)), &Urn::Subdomain(realm.clone(), zone_name.clone(), name.clone()).to_string()).await?;
+    write.put(&key, serde_json::to_vec(&subdomain)?);
+    write.commit(&state).await?;
+    dns::schedule_sync(&state, &realm, &zone_name);
     Ok(Json(subdomain))
 }
 
@@ -98,7 +202,11 @@ async fn get_subdomain(
     State(state): State<AppState>,
     Path((realm, zone_name, subdomain_name)): Path<(String, String, String)>,
 ) -> Result<Json<Subdomain>, ApiError> {
-    let key = subdomain_key(&realm, &zone_name, &subdomain_name);
+    let key = subdomain_key(
+        &RealmName::parse("realm", &realm)?,
+        &ZoneName::parse("zone", &zone_name)?,
+        &SubdomainName::parse("subdomain", &subdomain_name)?,
+    );
     if let Some(kv) = get_from_etcd(&state, &key).await?.kvs().first() {
         let subdomain = serde_json::from_slice(kv.value())?;
         Ok(Json(subdomain))
@@ -113,17 +221,32 @@ async fn delete_subdomain(
     State(state): State<AppState>,
     Path((realm, zone_name, subdomain_name)): Path<(String, String, String)>,
 ) -> Result<Json<Subdomain>, ApiError> {
-    let mut client = state.etcd_client.clone();
-    let key = subdomain_key(&realm, &zone_name, &subdomain_name);
-    let opts = etcd_client::DeleteOptions::new().with_prev_key();
-    let resp = client.delete(key, Some(opts)).await?;
+    let (realm_name, zone, name) = (
+        RealmName::parse("realm", &realm)?,
+        ZoneName::parse("zone", &zone_name)?,
+        SubdomainName::parse("subdomain", &subdomain_name)?,
+    );
+    let urn = Urn::Subdomain(realm_name.clone(), zone.clone(), name.clone()).to_string();
+    let binding = host_index::binding_key(&urn);
+    if let Some(kv) = get_from_etcd(&state, &binding).await?.kvs().first() {
+        return Err(ApiError::Conflict(format!(
+            "Subdomain '{}' is bound to VirtualHost '{}'", subdomain_name, String::from_utf8_lossy(kv.value())
+        )));
+    }
+    let mut write = IndexedWrite::new();
+    // 確認後に VirtualHost が紐付いた場合は 409 になる
+    write.require_absent(&binding);
+    write.release(&state, &host_index::host_key(&fqdn_of(&name, &zone)), &urn).await?;
+    write.delete(&subdomain_key(&realm_name, &zone, &name));
+    let resp = write.commit(&state).await?;
 
-    if let Some(kv) = resp.prev_kvs().first() {
-        let subdomain = serde_json::from_slice(kv.value())?;
+    if let Some(value) = host_index::deleted_value(&resp) {
+        let subdomain = serde_json::from_slice(&value)?;
+        dns::schedule_sync(&state, &realm_name, &zone);
         Ok(Json(subdomain))
     } else {
         Err(ApiError::NotFound(format!(
             "Subdomain '{}' not found in zone '{}'", subdomain_name, zone_name
         )))
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,437 @@
+//! リソースを指す URN の解析と組み立て
+//!
+//! URN は `urn:chip-in:{種類}:{Realm}[:...]` の形式で、種類ごとに Realm からの名前を `:` で区切って並べる。
+//!
+//! | 種類 | 形式 |
+//! |---|---|
+//! | Realm | `urn:chip-in:realm:{realm}` |
+//! | Zone | `urn:chip-in:zone:{realm}:{zone}` |
+//! | Subdomain | `urn:chip-in:subdomain:{realm}:{zone}:{subdomain}` |
+//! | VirtualHost | `urn:chip-in:virtual-host:{realm}:{virtualHost}` |
+//! | RoutingChain | `urn:chip-in:routing-chain:{realm}:{routingChain}` |
+//! | Hub | `urn:chip-in:hub:{realm}:{hub}` |
+//! | Service | `urn:chip-in:service:{realm}:{hub}:{service}` |
+//!
+//! 他のリソースを参照するフィールドはすべて参照先の URN を持つ。
+//!
+//! | フィールド | 参照先 |
+//! |---|---|
+//! | `Zone.realm` / `RoutingChain.realm` / `Hub.realm` / `VirtualHost.realm` / `Service.realm` | Realm (サーバーが設定する) |
+//! | `Subdomain.zone` | Zone (サーバーが設定する) |
+//! | `Subdomain.destinationRealm` | Realm |
+//! | `VirtualHost.subdomain` | Subdomain |
+//! | `VirtualHost.routingChain` | RoutingChain |
+//! | `Service.hub` | Hub (サーバーが設定する) |
+//!
+//! 移行期間中は Realm / RoutingChain / Hub を名前だけで指定する旧形式も受け付け、URN に変換して保存する。
+//! `VirtualHost.subdomain` の名前だけの指定は、Realm 内の同じ名前の Subdomain の URN に変換する。
+//! 旧形式で保存済みのドキュメントは起動時と `POST /admin/migrate-urns` で変換する。
+use crate::db::{AppState, REALM_PREFIX};
+use crate::error::ApiError;
+use crate::hub::{self, Hub};
+use crate::realm::{self, Realm};
+use crate::routing_chain::{self, RoutingChain};
+use crate::secret::{Reveal, SecretFields};
+use crate::service::{self, Service};
+use crate::subdomain::{self, Subdomain};
+use crate::utils::get_from_etcd;
+use crate::validation::{self, HubName, RealmName, RoutingChainName, ServiceName, SubdomainName, VirtualHostName, ZoneName};
+use crate::virtual_host::{self, VirtualHost};
+use crate::zone::{self, Zone};
+use axum::{
+    extract::{Path, State},
+    routing::{get, post},
+    Json, Router,
+};
+use etcd_client::{Compare, CompareOp, GetOptions, Txn, TxnOp, TxnOpResponse};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use tracing::{info, warn};
+
+const URN_PREFIX: &str = "urn:chip-in:";
+/// 一度に解決できる URN の数
+const MAX_BATCH: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Urn {
+    Realm(RealmName),
+    Zone(RealmName, ZoneName),
+    Subdomain(RealmName, ZoneName, SubdomainName),
+    VirtualHost(RealmName, VirtualHostName),
+    RoutingChain(RealmName, RoutingChainName),
+    Hub(RealmName, HubName),
+    Service(RealmName, HubName, ServiceName),
+}
+
+impl Urn {
+    /// URN を解析し、含まれる名前を検証する。`field` はエラーで示すフィールド名
+    pub fn parse(field: &str, value: &str) -> Result<Self, ApiError> {
+        let Some(rest) = value.strip_prefix(URN_PREFIX) else {
+            return Err(validation::invalid(field, format!("'{}' is not a URN starting with '{}'", value, URN_PREFIX)));
+        };
+        let parts: Vec<&str> = rest.split(':').collect();
+        let urn = match parts.as_slice() {
+            ["realm", realm] => Urn::Realm(RealmName::parse(field, realm)?),
+            ["zone", realm, zone] => Urn::Zone(RealmName::parse(field, realm)?, ZoneName::parse(field, zone)?),
+            ["subdomain", realm, zone, name] => Urn::Subdomain(
+                RealmName::parse(field, realm)?,
+                ZoneName::parse(field, zone)?,
+                SubdomainName::parse(field, name)?,
+            ),
+            ["virtual-host", realm, name] => Urn::VirtualHost(RealmName::parse(field, realm)?, VirtualHostName::parse(field, name)?),
+            ["routing-chain", realm, name] => {
+                Urn::RoutingChain(RealmName::parse(field, realm)?, RoutingChainName::parse(field, name)?)
+            }
+            ["hub", realm, name] => Urn::Hub(RealmName::parse(field, realm)?, HubName::parse(field, name)?),
+            ["service", realm, hub, name] => {
+                Urn::Service(RealmName::parse(field, realm)?, HubName::parse(field, hub)?, ServiceName::parse(field, name)?)
+            }
+            [kind, ..] if !KINDS.contains(kind) => {
+                return Err(validation::invalid(field, format!("'{}' has an unknown resource type '{}'", value, kind)));
+            }
+            _ => return Err(validation::invalid(field, format!("'{}' has the wrong number of parts for its type", value))),
+        };
+        Ok(urn)
+    }
+
+    /// `urn:chip-in:` に続く種類
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Urn::Realm(..) => "realm",
+            Urn::Zone(..) => "zone",
+            Urn::Subdomain(..) => "subdomain",
+            Urn::VirtualHost(..) => "virtual-host",
+            Urn::RoutingChain(..) => "routing-chain",
+            Urn::Hub(..) => "hub",
+            Urn::Service(..) => "service",
+        }
+    }
+
+    pub fn realm(&self) -> &RealmName {
+        match self {
+            Urn::Realm(realm)
+            | Urn::Zone(realm, _)
+            | Urn::Subdomain(realm, _, _)
+            | Urn::VirtualHost(realm, _)
+            | Urn::RoutingChain(realm, _)
+            | Urn::Hub(realm, _)
+            | Urn::Service(realm, _, _) => realm,
+        }
+    }
+
+    /// 参照するリソースの etcd のキー
+    pub fn key(&self) -> String {
+        match self {
+            Urn::Realm(realm) => realm::realm_key(realm),
+            Urn::Zone(realm, zone) => zone::zone_key(realm, zone),
+            Urn::Subdomain(realm, zone, name) => subdomain::subdomain_key(realm, zone, name),
+            Urn::VirtualHost(realm, name) => virtual_host::virtual_host_key(realm, name),
+            Urn::RoutingChain(realm, name) => routing_chain::routing_chain_key(realm, name),
+            Urn::Hub(realm, name) => hub::hub_key(realm, name),
+            Urn::Service(realm, hub, name) => service::service_key(realm, hub, name),
+        }
+    }
+
+    /// 保存されているドキュメントを API のレスポンスと同じ形にする
+    fn to_resource(&self, state: &AppState, reveal: &Reveal, value: &[u8]) -> Result<Value, ApiError> {
+        match self {
+            Urn::Realm(..) => secret_resource::<Realm>(state, reveal, value),
+            Urn::Zone(..) => secret_resource::<Zone>(state, reveal, value),
+            Urn::Subdomain(..) => Ok(serde_json::to_value(serde_json::from_slice::<Subdomain>(value)?)?),
+            Urn::VirtualHost(..) => secret_resource::<VirtualHost>(state, reveal, value),
+            Urn::RoutingChain(..) => Ok(serde_json::to_value(serde_json::from_slice::<RoutingChain>(value)?)?),
+            Urn::Hub(..) => secret_resource::<Hub>(state, reveal, value),
+            Urn::Service(..) => Ok(serde_json::to_value(serde_json::from_slice::<Service>(value)?)?),
+        }
+    }
+}
+
+const KINDS: [&str; 7] = ["realm", "zone", "subdomain", "virtual-host", "routing-chain", "hub", "service"];
+
+impl fmt::Display for Urn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}:{}", URN_PREFIX, self.kind(), self.realm())?;
+        match self {
+            Urn::Realm(_) => Ok(()),
+            Urn::Zone(_, zone) => write!(f, ":{}", zone),
+            Urn::Subdomain(_, zone, name) => write!(f, ":{}:{}", zone, name),
+            Urn::VirtualHost(_, name) => write!(f, ":{}", name),
+            Urn::RoutingChain(_, name) => write!(f, ":{}", name),
+            Urn::Hub(_, name) => write!(f, ":{}", name),
+            Urn::Service(_, hub, name) => write!(f, ":{}:{}", hub, name),
+        }
+    }
+}
+
+/// 参照フィールドの値を `kind` の URN に正規化する
+///
+/// 名前だけの旧形式は `realm` 内のリソースとして受け付ける (非推奨)。
+pub fn reference(field: &str, value: &str, kind: &str, realm: &RealmName) -> Result<Urn, ApiError> {
+    let (urn, legacy) = resolve_reference(field, value, kind, realm)?;
+    if legacy {
+        warn!("Accepted the deprecated bare name '{}' in '{}'; use '{}' instead", value, field, urn);
+    }
+    Ok(urn)
+}
+
+/// Subdomain への参照を URN に正規化する
+///
+/// 名前だけの旧形式は Realm 内の同じ名前の Subdomain の URN に変換する (非推奨)。複数の Zone にあれば 400、
+/// 存在しなければ 422。
+pub async fn subdomain_reference(state: &AppState, field: &str, value: &str, realm: &RealmName) -> Result<Urn, ApiError> {
+    if value.starts_with("urn:") {
+        return reference(field, value, "subdomain", realm);
+    }
+    let name = SubdomainName::parse(field, value)?;
+    let prefix = format!("{}{}/zones/", REALM_PREFIX, realm);
+    let mut client = state.etcd_client.clone();
+    let resp = client.get(prefix.as_str(), Some(GetOptions::new().with_keys_only().with_prefix())).await?;
+    let mut found = Vec::new();
+    for kv in resp.kvs() {
+        let key = kv.key_str()?;
+        if let [zone, "subdomains", subdomain] = key.trim_start_matches(prefix.as_str()).split('/').collect::<Vec<_>>().as_slice() {
+            if *subdomain == value {
+                found.push(Urn::Subdomain(realm.clone(), ZoneName::parse(field, zone)?, name.clone()));
+            }
+        }
+    }
+    match found.as_slice() {
+        [] => Err(ApiError::UnprocessableDetails {
+            message: format!("{}: no Subdomain named '{}' exists in realm '{}'", field, value, realm),
+            details: json!({ "field": field }),
+        }),
+        [urn] => {
+            warn!("Accepted the deprecated bare name '{}' in '{}'; use '{}' instead", value, field, urn);
+            Ok(urn.clone())
+        }
+        _ => Err(validation::invalid(
+            field,
+            format!("'{}' names a Subdomain in more than one zone; use a URN of the form '{}subdomain:...'", value, URN_PREFIX),
+        )),
+    }
+}
+
+/// URN を解析するか旧形式から URN を組み立てる (旧形式だった場合は `true` を添える)
+fn resolve_reference(field: &str, value: &str, kind: &str, realm: &RealmName) -> Result<(Urn, bool), ApiError> {
+    if value.starts_with("urn:") {
+        let urn = Urn::parse(field, value)?;
+        if urn.kind() != kind {
+            return Err(validation::invalid(field, format!("'{}' must refer to a {}", value, kind)));
+        }
+        return Ok((urn, false));
+    }
+    let urn = match kind {
+        "realm" => Urn::Realm(RealmName::parse(field, value)?),
+        "routing-chain" => Urn::RoutingChain(realm.clone(), RoutingChainName::parse(field, value)?),
+        "hub" => Urn::Hub(realm.clone(), HubName::parse(field, value)?),
+        _ => {
+            return Err(validation::invalid(
+                field,
+                format!("'{}' must be a URN of the form '{}{}:...'", value, URN_PREFIX, kind),
+            ))
+        }
+    };
+    Ok((urn, true))
+}
+
+fn secret_resource<T>(state: &AppState, reveal: &Reveal, value: &[u8]) -> Result<Value, ApiError>
+where
+    T: SecretFields + Serialize + DeserializeOwned,
+{
+    Ok(serde_json::to_value(reveal.apply(state.keyring.decode::<T>(value)?))?)
+}
+
+fn not_found(urn: &Urn) -> ApiError {
+    ApiError::NotFound(format!("'{}' does not refer to an existing resource", urn))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct ResolveRequest {
+    pub urns: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolveResponse {
+    pub results: Vec<ResolveResult>,
+}
+
+/// URN ごとの解決結果 (`resource` と `error` のどちらか一方を持つ)
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolveResult {
+    pub urn: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+}
+
+impl ResolveResult {
+    fn error(urn: String, err: ApiError) -> Self {
+        Self { urn, resource: None, error: Some(err.into_status_and_body().1) }
+    }
+}
+
+/// URN 関連のエンドポイントをまとめたルーターを返す
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/urn/{urn}", get(get_by_urn))
+        .route("/urn:resolve", post(resolve))
+}
+
+/// GET /urn/{urn}
+///
+/// URN が指すリソースを返す。
+async fn get_by_urn(
+    State(state): State<AppState>,
+    Path(urn): Path<String>,
+    reveal: Reveal,
+) -> Result<Json<Value>, ApiError> {
+    let urn = Urn::parse("urn", &urn)?;
+    match get_from_etcd(&state, &urn.key()).await?.kvs().first() {
+        Some(kv) => Ok(Json(urn.to_resource(&state, &reveal, kv.value())?)),
+        None => Err(not_found(&urn)),
+    }
+}
+
+/// POST /urn:resolve
+///
+/// 複数の URN をまとめて解決する。リソースは同じリビジョンで読み、結果は要求と同じ順に返す。
+/// 個々の URN の誤りや存在しないリソースはその要素の `error` で示す。
+async fn resolve(
+    State(state): State<AppState>,
+    reveal: Reveal,
+    Json(request): Json<ResolveRequest>,
+) -> Result<Json<ResolveResponse>, ApiError> {
+    if request.urns.len() > MAX_BATCH {
+        return Err(validation::invalid("urns", format!("at most {} URNs can be resolved at once", MAX_BATCH)));
+    }
+    let parsed: Vec<Result<Urn, ApiError>> =
+        request.urns.iter().enumerate().map(|(i, urn)| Urn::parse(&format!("urns[{}]", i), urn)).collect();
+
+    let gets: Vec<TxnOp> = parsed.iter().flatten().map(|urn| TxnOp::get(urn.key(), None)).collect();
+    let mut client = state.etcd_client.clone();
+    let mut responses = client.txn(Txn::new().and_then(gets)).await?.op_responses().into_iter();
+
+    let mut results = Vec::with_capacity(request.urns.len());
+    for (text, urn) in request.urns.into_iter().zip(parsed) {
+        let urn = match urn {
+            Ok(urn) => urn,
+            Err(err) => {
+                results.push(ResolveResult::error(text, err));
+                continue;
+            }
+        };
+        let value = match responses.next() {
+            Some(TxnOpResponse::Get(get)) => get.kvs().first().map(|kv| kv.value().to_vec()),
+            _ => None,
+        };
+        let result = match value {
+            Some(value) => urn.to_resource(&state, &reveal, &value),
+            None => Err(not_found(&urn)),
+        };
+        results.push(match result {
+            Ok(resource) => ResolveResult { urn: urn.to_string(), resource: Some(resource), error: None },
+            Err(err) => ResolveResult::error(urn.to_string(), err),
+        });
+    }
+    Ok(Json(ResolveResponse { results }))
+}
+
+/// 参照フィールドの移行の結果
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrationReport {
+    pub migrated: usize,
+    pub unchanged: usize,
+    pub conflicts: usize,
+    /// URN に変換できなかった値 (`{キー}: {フィールド} '{値}'`)
+    pub unconvertible: Vec<String>,
+}
+
+/// ドキュメントの種類ごとの参照フィールドと参照先の種類
+fn reference_fields(segments: &[&str]) -> &'static [(&'static str, &'static str)] {
+    match segments {
+        [_, "zones", _] => &[("realm", "realm")],
+        [_, "zones", _, "subdomains", _] => &[("zone", "zone"), ("destinationRealm", "realm")],
+        [_, "virtual-hosts", _] => &[("realm", "realm"), ("subdomain", "subdomain"), ("routingChain", "routing-chain")],
+        [_, "routing-chains", _] | [_, "hubs", _] => &[("realm", "realm")],
+        [_, "hubs", _, "services", _] => &[("realm", "realm"), ("hub", "hub")],
+        _ => &[],
+    }
+}
+
+/// 旧形式の参照を持つ保存済みのドキュメントを URN に変換する
+///
+/// 参照フィールドだけを書き換えるため、暗号化された秘密情報はそのまま残る。
+/// 読み取り後に更新されたドキュメントは上書きしない。
+pub async fn migrate(state: &AppState) -> Result<MigrationReport, ApiError> {
+    let mut client = state.etcd_client.clone();
+    let resp = client.get(REALM_PREFIX, Some(GetOptions::new().with_prefix())).await?;
+
+    let mut report = MigrationReport::default();
+    for kv in resp.kvs() {
+        let key = kv.key_str()?;
+        let segments: Vec<&str> = key.trim_start_matches(REALM_PREFIX).split('/').collect();
+        let fields = reference_fields(&segments);
+        if fields.is_empty() {
+            continue;
+        }
+        let Ok(realm) = RealmName::parse("realm", segments[0]) else {
+            continue;
+        };
+        let Ok(mut doc) = serde_json::from_slice::<Value>(kv.value()) else {
+            continue;
+        };
+
+        let mut changed = false;
+        for (field, kind) in fields {
+            let Some(Value::String(value)) = doc.get(*field) else {
+                continue;
+            };
+            let resolved = match *kind {
+                "subdomain" => subdomain_reference(state, field, value, &realm).await,
+                _ => resolve_reference(field, value, kind, &realm).map(|(urn, _)| urn),
+            };
+            match resolved {
+                Ok(urn) if urn.to_string() != *value => {
+                    doc[*field] = Value::String(urn.to_string());
+                    changed = true;
+                }
+                Ok(_) => {}
+                Err(_) => report.unconvertible.push(format!("{}: {} '{}'", key, field, value)),
+            }
+        }
+        if !changed {
+            report.unchanged += 1;
+            continue;
+        }
+
+        let txn = Txn::new()
+            .when([Compare::mod_revision(key, CompareOp::Equal, kv.mod_revision())])
+            .and_then([TxnOp::put(key, serde_json::to_vec(&doc)?, None)]);
+        if client.txn(txn).await?.succeeded() {
+            report.migrated += 1;
+        } else {
+            report.conflicts += 1;
+        }
+    }
+    Ok(report)
+}
+
+/// 起動時に旧形式の参照を移行する (失敗しても API の提供は続ける)
+pub async fn migrate_task(state: &AppState) {
+    match migrate(state).await {
+        Ok(report) => {
+            info!("URN migration: {} migrated, {} unchanged, {} conflicts", report.migrated, report.unchanged, report.conflicts);
+            for entry in report.unconvertible {
+                warn!("Reference is not a URN and could not be converted: {}", entry);
+            }
+        }
+        Err(e) => warn!("Failed to migrate references to URNs: {}", e),
+    }
+}
@@ -1,9 +1,51 @@
 use crate::db::AppState;
 use crate::error::ApiError;
-use etcd_client::GetResponse;
+use etcd_client::{GetResponse, KeyValue};
+use crate::secret::SecretFields;
+use serde::de::DeserializeOwned;
+use tracing::warn;
 
 pub async fn get_from_etcd(state: &AppState, key: &str) -> Result<GetResponse, ApiError> {
     let mut client = state.etcd_client.clone();
     let resp = client.get(key, None).await?;
     Ok(resp)
-}
\ No newline at end of file
+}
+
+/// 秘密情報を含むドキュメントを取得し、復号して返す (存在しなければ `None`)
+pub async fn get_secret_document<T: SecretFields + DeserializeOwned>(state: &AppState, key: &str) -> Result<Option<T>, ApiError> {
+    match get_from_etcd(state, key).await?.kvs().first() {
+        Some(kv) => Ok(Some(state.keyring.decode(kv.value())?)),
+        None => Ok(None),
+    }
+}
+
+/// 取得したドキュメントをデシリアライズして返す
+///
+/// 読み取れないドキュメントはキーをログに残して除く。
+pub fn parse_documents<T: DeserializeOwned>(kvs: &[KeyValue]) -> Vec<T> {
+    kvs.iter()
+        .filter_map(|kv| match serde_json::from_slice(kv.value()) {
+            Ok(doc) => Some(doc),
+            Err(e) => {
+                warn!("Skipping '{}' because it could not be decoded: {}", String::from_utf8_lossy(kv.key()), e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// プレフィックス直下の秘密情報を含むドキュメントを復号して返す
+///
+/// 配下のサブリソースは対象外とし、復号できないドキュメントはキーをログに残して除く。
+pub fn decode_children<T: SecretFields + DeserializeOwned>(state: &AppState, prefix: &str, kvs: &[KeyValue]) -> Vec<T> {
+    kvs.iter()
+        .filter(|kv| kv.key_str().is_ok_and(|key| key.strip_prefix(prefix).is_some_and(|name| !name.contains('/'))))
+        .filter_map(|kv| match state.keyring.decode(kv.value()) {
+            Ok(doc) => Some(doc),
+            Err(e) => {
+                warn!("Skipping '{}' because it could not be decoded: {}", String::from_utf8_lossy(kv.key()), e);
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,141 @@
+//! リソース名の検証と、etcd のキーに使う検証済みの名前の型
+//!
+//! キーは `format!("/realms/{}/zones/{}", ...)` で組み立てるため、`/` を含む名前や空の名前はプレフィックスを
+//! 抜け出したり、別の種類のリソースのキーと衝突したりする。キーを組み立てる関数はここで定義する型だけを受け取る。
+//! 検証に失敗した場合はフィールド名付きの 400 (`ApiError::InvalidField`) を返す。
+//!
+//! - Realm / Hub / VirtualHost / RoutingChain / Service: 識別子 (英数字で始まり、英数字と `.` `_` `-` からなる 63 文字以内)
+//! - Zone: FQDN (A-label 形式に正規化する)
+//! - Subdomain: `@` または DNS ラベルの並び (先頭のラベルのみ `*` を許す)
+use crate::error::ApiError;
+use crate::idn;
+use std::fmt;
+use std::net::IpAddr;
+
+/// FQDN の最大長 (RFC 1035 2.3.4)
+pub const MAX_FQDN_LEN: usize = 253;
+/// 識別子の最大長
+const MAX_IDENTIFIER_LEN: usize = 63;
+
+pub fn invalid(field: &str, message: impl Into<String>) -> ApiError {
+    ApiError::InvalidField { field: field.to_string(), message: message.into() }
+}
+
+/// 識別子を確認する
+pub fn identifier(field: &str, value: &str) -> Result<String, ApiError> {
+    if value.is_empty() || value.len() > MAX_IDENTIFIER_LEN {
+        return Err(invalid(field, format!("must be 1 to {} characters long", MAX_IDENTIFIER_LEN)));
+    }
+    let valid = value.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if !valid {
+        return Err(invalid(
+            field,
+            format!("'{}' must start with a letter or digit and contain only letters, digits, '.', '_' and '-'", value),
+        ));
+    }
+    Ok(value.to_string())
+}
+
+/// ドメイン名を A-label 形式に正規化し、FQDN の構文を確認する (`wildcard` なら先頭の `*` ラベルを許す)
+pub fn domain_name(field: &str, value: &str, wildcard: bool) -> Result<String, ApiError> {
+    let ascii = idn::to_ascii(value).map_err(|e| invalid(field, e))?;
+    if ascii.len() > MAX_FQDN_LEN {
+        return Err(invalid(field, format!("'{}' is longer than {} characters", ascii, MAX_FQDN_LEN)));
+    }
+    for (i, label) in ascii.split('.').enumerate() {
+        if wildcard && i == 0 && label == "*" {
+            continue;
+        }
+        if label.contains('*') {
+            let rule = if wildcard { "'*' is only allowed as the whole leftmost label" } else { "wildcards are not allowed" };
+            return Err(invalid(field, format!("'{}' is invalid; {}", value, rule)));
+        }
+        if !idn::is_valid_label(label) {
+            return Err(invalid(field, format!("'{}' has an invalid label '{}'", value, label)));
+        }
+    }
+    Ok(ascii)
+}
+
+/// ホスト名 (FQDN) または IP アドレスを確認する
+pub fn host(field: &str, value: &str) -> Result<String, ApiError> {
+    match value.parse::<IpAddr>() {
+        Ok(ip) => Ok(ip.to_string()),
+        Err(_) => domain_name(field, value, false),
+    }
+}
+
+fn zone_name(field: &str, value: &str) -> Result<String, ApiError> {
+    domain_name(field, value, false)
+}
+
+fn subdomain_name(field: &str, value: &str) -> Result<String, ApiError> {
+    if value == "@" {
+        return Ok(value.to_string());
+    }
+    domain_name(field, value, true)
+}
+
+/// 検証済みの名前の型を定義する
+macro_rules! name_type {
+    ($(#[$doc:meta])* $name:ident, $rule:path) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(String);
+
+        impl $name {
+            /// 名前を検証 (必要なら正規化) する。`field` はエラーで示すフィールド名
+            pub fn parse(field: &str, value: &str) -> Result<Self, ApiError> {
+                $rule(field, value).map(Self)
+            }
+        }
+
+        impl std::ops::Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+name_type!(
+    /// Realm 名
+    RealmName, identifier
+);
+name_type!(
+    /// Zone 名 (A-label 形式の FQDN)
+    ZoneName, zone_name
+);
+name_type!(
+    /// Subdomain 名 (A-label 形式、`@` とワイルドカードを含む)
+    SubdomainName, subdomain_name
+);
+name_type!(
+    /// Hub 名
+    HubName, identifier
+);
+name_type!(
+    /// VirtualHost 名
+    VirtualHostName, identifier
+);
+name_type!(
+    /// RoutingChain 名
+    RoutingChainName, identifier
+);
+name_type!(
+    /// Service 名
+    ServiceName, identifier
+);
+name_type!(
+    /// Blob 名
+    BlobName, identifier
+);
@@ -1,9 +1,19 @@
+use crate::ca::{self, IssueCertificateRequest};
 use crate::db::AppState;
+use crate::dns;
 use crate::error::ApiError;
-use crate::utils::get_from_etcd;
+use crate::host_index::{self, IndexedWrite};
+use crate::pki;
+use crate::routing_chain::{self, Action, RespondAction, Rule};
+use crate::routing_chain_version::{self, ChainVersion};
+use crate::secret::{Reveal, Secret, SecretFields};
+use crate::subdomain;
+use crate::urn::{self, Urn};
+use crate::utils::{decode_children, get_secret_document, get_from_etcd};
+use crate::validation::{RealmName, VirtualHostName};
 use axum::{
     extract::{Path, State},
-    routing::get,
+    routing::{get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
@@ -16,18 +26,53 @@ pub struct VirtualHost {
     pub title: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    // Realm の URN (読み取り専用)
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub realm: Option<String>,
+    // 配信する Subdomain の URN
     pub subdomain: String,
+    // RoutingChain の URN (名前だけの旧形式も受け付ける)
     pub routing_chain: String,
+    // 固定する RoutingChain の版 (省略すると RoutingChain の有効な版を使う)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing_chain_version: Option<u64>,
+    // 有効にすると RoutingChain より先に `response` を返す
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<Maintenance>,
     #[serde(default)]
     pub certificate: Vec<String>,
+    // 秘密情報: レスポンスでは既定で伏せられ、更新時に省略すると保存済みの値が維持される
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub key: Option<String>,
+    pub key: Option<Secret>,
     #[serde(default)]
     pub disabled: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct Maintenance {
+    #[serde(default)]
+    pub enabled: bool,
+    pub response: RespondAction,
+}
+
+impl SecretFields for VirtualHost {
+    fn redact(&mut self) {
+        self.key = None;
+    }
+
+    fn retain_secrets(&mut self, stored: &Self) {
+        if self.key.is_none() {
+            self.key = stored.key.clone();
+        }
+    }
+
+    fn secrets_mut(&mut self) -> Vec<&mut Secret> {
+        self.key.iter_mut().collect()
+    }
+}
+
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/", get(list_virtual_hosts).post(add_virtual_host).put(update_virtual_host))
@@ -35,74 +80,178 @@ pub fn routes() -> Router<AppState> {
             "/{virtual_host_name}",
             get(get_virtual_host).delete(delete_virtual_host),
         )
+        .route("/{virtual_host_name}/issue-certificate", post(issue_virtual_host_certificate))
+        .route("/{virtual_host_name}/routing-chain", get(get_effective_routing_chain))
 }
 
-fn virtual_host_key(realm: &str, name: &str) -> String {
+pub fn virtual_host_key(realm: &RealmName, name: &VirtualHostName) -> String {
     format!("/realms/{}/virtual-hosts/{}", realm, name)
 }
 
-fn virtual_host_prefix(realm: &str) -> String {
+pub fn virtual_host_prefix(realm: &RealmName) -> String {
     format!("/realms/{}/virtual-hosts/", realm)
 }
 
+/// Realm と参照先を URN で設定する
+async fn normalize_references(state: &AppState, host: &mut VirtualHost, realm: &RealmName) -> Result<(), ApiError> {
+    host.realm = Some(Urn::Realm(realm.clone()).to_string());
+    host.subdomain = urn::subdomain_reference(state, "subdomain", &host.subdomain, realm).await?.to_string();
+    host.routing_chain = urn::reference("routingChain", &host.routing_chain, "routing-chain", realm)?.to_string();
+    Ok(())
+}
+
+/// 固定した RoutingChain の版が存在することを確認し、そのキーと mod_revision を返す
+async fn pinned_version(state: &AppState, host: &VirtualHost) -> Result<Option<(String, i64)>, ApiError> {
+    let Some(version) = host.routing_chain_version else {
+        return Ok(None);
+    };
+    let Urn::RoutingChain(realm, name) = Urn::parse("routingChain", &host.routing_chain)? else {
+        return Err(ApiError::Internal(anyhow::anyhow!("routingChain '{}' is not a routing chain URN", host.routing_chain)));
+    };
+    match routing_chain_version::get_chain_version(state, &realm, &name, version).await? {
+        Some((_, revision)) => Ok(Some((routing_chain_version::version_key(&realm, &name, version), revision))),
+        None => Err(ApiError::Unprocessable(format!(
+            "routingChainVersion: version {} of '{}' does not exist",
+            version, host.routing_chain
+        ))),
+    }
+}
+
+/// メンテナンス中の応答を確認する (無効にしていても保存する内容は確認する)
+async fn validate_maintenance(state: &AppState, host: &VirtualHost, realm: &RealmName) -> Result<(), ApiError> {
+    let Some(maintenance) = &host.maintenance else {
+        return Ok(());
+    };
+    routing_chain::validate_respond("maintenance.response", &maintenance.response)?;
+    routing_chain::check_response_body(state, realm, "maintenance.response", &maintenance.response).await
+}
+
+/// VirtualHost が参照する Subdomain の FQDN を求める
+async fn subdomain_fqdn(state: &AppState, host: &VirtualHost) -> Result<String, ApiError> {
+    subdomain::resolve_fqdn(state, &host.subdomain).await?.ok_or_else(|| {
+        ApiError::Unprocessable(format!(
+            "subdomain: SAN check failed: '{}' does not resolve to a Subdomain with an FQDN",
+            host.subdomain
+        ))
+    })
+}
+
+/// 証明書チェーンと秘密鍵が対応し、リーフ証明書が Subdomain の FQDN をカバーしていることを確認する
+async fn validate_certificates(state: &AppState, host: &VirtualHost) -> Result<(), ApiError> {
+    if host.certificate.is_empty() && host.key.is_none() {
+        return Ok(());
+    }
+    if host.certificate.is_empty() {
+        return Err(ApiError::Unprocessable(
+            "certificate: key match check failed: a certificate is required with the key".to_string(),
+        ));
+    }
+    let mut chain = Vec::new();
+    for pem in &host.certificate {
+        chain.extend(pki::parse_certificates("certificate", pem)?);
+    }
+    let fqdn = subdomain_fqdn(state, host).await?;
+    pki::check_server_certificate("certificate", &chain, "key", host.key.as_ref(), &fqdn)
+}
+
 async fn list_virtual_hosts(
     State(state): State<AppState>,
     Path(realm): Path<String>,
+    reveal: Reveal,
 ) -> Result<Json<Vec<VirtualHost>>, ApiError> {
     let mut client = state.etcd_client.clone();
-    let prefix = virtual_host_prefix(&realm);
-    let resp = client.get(prefix, Some(etcd_client::GetOptions::new().with_prefix())).await?;
-    let hosts = resp
-        .kvs()
-        .iter()
-        .filter_map(|kv| serde_json::from_slice(kv.value()).ok())
-        .collect();
-    Ok(Json(hosts))
+    let prefix = virtual_host_prefix(&RealmName::parse("realm", &realm)?);
+    let resp = client.get(prefix.as_str(), Some(etcd_client::GetOptions::new().with_prefix())).await?;
+    let hosts = decode_children(&state, &prefix, resp.kvs());
+    Ok(Json(reveal.apply_all(hosts)))
 }
 
 async fn add_virtual_host(
     State(state): State<AppState>,
     Path(realm): Path<String>,
+    reveal: Reveal,
     Json(mut host): Json<VirtualHost>,
 ) -> Result<Json<VirtualHost>, ApiError> {
-    let mut client = state.etcd_client.clone();
-    let key = virtual_host_key(&realm, &host.name);
+    let realm = RealmName::parse("realm", &realm)?;
+    let name = VirtualHostName::parse("name", &host.name)?;
+    let key = virtual_host_key(&realm, &name);
 
+    #[allow(clippy::unnecessary_first_then_check)]
     if get_from_etcd(&state, &key).await?.kvs().first().is_some() {
         return Err(ApiError::Conflict(format!(
             "VirtualHost '{}' already exists in realm '{}'.",
             host.name, realm
         )));
     }
-    
-    host.certificate = Vec::new(); // Ensure certificate field is present, even if empty
-    host.realm = Some(realm);
-    let value = serde_json::to_vec(&host)?;
-    client.put(key, value, None).await?;
-    Ok(Json(host))
+
+    normalize_references(&state, &mut host, &realm).await?;
+    validate_maintenance(&state, &host, &realm).await?;
+    validate_certificates(&state, &host).await?;
+
+    // 1 つの Subdomain を配信できる有効な VirtualHost は 1 つだけ
+    let pinned = pinned_version(&state, &host).await?;
+    let mut write = IndexedWrite::new();
+    write.require_absent(&key);
+    if let Some((version_key, revision)) = &pinned {
+        write.require_revision(version_key, *revision);
+    }
+    if !host.disabled {
+        write.claim(&state, &host_index::binding_key(&host.subdomain), &Urn::VirtualHost(realm.clone(), name.clone()).to_string()).await?;
+    }
+    write.put(&key, state.keyring.encode(&host)?);
+    write.commit(&state).await?;
+    dns::schedule_sync_for_subdomain(&state, &host.subdomain);
+    Ok(Json(reveal.apply(host)))
 }
 
 async fn update_virtual_host(
     State(state): State<AppState>,
     Path(realm): Path<String>,
+    reveal: Reveal,
     Json(mut host): Json<VirtualHost>,
 ) -> Result<Json<VirtualHost>, ApiError> {
-    let mut client = state.etcd_client.clone();
-    let key = virtual_host_key(&realm, &host.name);
-    host.realm = Some(realm);
-    let value = serde_json::to_vec(&host)?;
-    client.put(key, value, None).await?;
-    Ok(Json(host))
+    let realm = RealmName::parse("realm", &realm)?;
+    let name = VirtualHostName::parse("name", &host.name)?;
+    let key = virtual_host_key(&realm, &name);
+    let stored = get_secret_document::<VirtualHost>(&state, &key).await?;
+    if let Some(stored) = &stored {
+        host.retain_secrets(stored);
+    }
+    normalize_references(&state, &mut host, &realm).await?;
+    validate_maintenance(&state, &host, &realm).await?;
+    validate_certificates(&state, &host).await?;
+
+    let pinned = pinned_version(&state, &host).await?;
+
+    let urn = Urn::VirtualHost(realm.clone(), name.clone()).to_string();
+    let mut write = IndexedWrite::new();
+    if let Some((version_key, revision)) = &pinned {
+        write.require_revision(version_key, *revision);
+    }
+    if let Some(stored) = stored.as_ref().filter(|s| s.subdomain != host.subdomain || host.disabled) {
+        write.release(&state, &host_index::binding_key(&stored.subdomain), &urn).await?;
+    }
+    if !host.disabled {
+        write.claim(&state, &host_index::binding_key(&host.subdomain), &urn).await?;
+    }
+    write.put(&key, state.keyring.encode(&host)?);
+    write.commit(&state).await?;
+    dns::schedule_sync_for_subdomain(&state, &host.subdomain);
+    if let Some(stored) = stored.filter(|s| s.subdomain != host.subdomain) {
+        dns::schedule_sync_for_subdomain(&state, &stored.subdomain);
+    }
+    Ok(Json(reveal.apply(host)))
 }
 
 async fn get_virtual_host(
     State(state): State<AppState>,
     Path((realm, name)): Path<(String, String)>,
+    reveal: Reveal,
 ) -> Result<Json<VirtualHost>, ApiError> {
-    let key = virtual_host_key(&realm, &name);
+    let key = virtual_host_key(&RealmName::parse("realm", &realm)?, &VirtualHostName::parse("virtualHost", &name)?);
     if let Some(kv) = get_from_etcd(&state, &key).await?.kvs().first() {
-        let host = serde_json::from_slice(kv.value())?;
-        Ok(Json(host))
+        let host = state.keyring.decode(kv.value())?;
+        Ok(Json(reveal.apply(host)))
     } else {
         Err(ApiError::NotFound(format!(
             "VirtualHost '{}' not found in realm '{}'", name, realm
@@ -113,18 +262,90 @@ async fn get_virtual_host(
 async fn delete_virtual_host(
     State(state): State<AppState>,
     Path((realm, name)): Path<(String, String)>,
+    reveal: Reveal,
 ) -> Result<Json<VirtualHost>, ApiError> {
-    let mut client = state.etcd_client.clone();
-    let key = virtual_host_key(&realm, &name);
-    let opts = etcd_client::DeleteOptions::new().with_prev_key();
-    let resp = client.delete(key, Some(opts)).await?;
+    let (realm_name, host_name) = (RealmName::parse("realm", &realm)?, VirtualHostName::parse("virtualHost", &name)?);
+    let key = virtual_host_key(&realm_name, &host_name);
+    let Some(kv) = get_from_etcd(&state, &key).await?.kvs().first().cloned() else {
+        return Err(ApiError::NotFound(format!(
+            "VirtualHost '{}' not found in realm '{}'", name, realm
+        )));
+    };
+    let host: VirtualHost = state.keyring.decode(kv.value())?;
 
-    if let Some(kv) = resp.prev_kvs().first() {
-        let host = serde_json::from_slice(kv.value())?;
-        Ok(Json(host))
-    } else {
-        Err(ApiError::NotFound(format!(
+    let mut write = IndexedWrite::new();
+    write.require_revision(&key, kv.mod_revision());
+    write.release(&state, &host_index::binding_key(&host.subdomain), &Urn::VirtualHost(realm_name, host_name).to_string()).await?;
+    write.delete(&key);
+    write.commit(&state).await?;
+    dns::schedule_sync_for_subdomain(&state, &host.subdomain);
+    Ok(Json(reveal.apply(host)))
+}
+
+/// POST /realms/{realm}/virtual-hosts/{virtual_host_name}/issue-certificate
+///
+/// Realm CA で Subdomain の FQDN 用のサーバー証明書を発行 (再発行) し、VirtualHost に書き戻す。
+async fn issue_virtual_host_certificate(
+    State(state): State<AppState>,
+    Path((realm, name)): Path<(String, String)>,
+    reveal: Reveal,
+    request: Option<Json<IssueCertificateRequest>>,
+) -> Result<Json<VirtualHost>, ApiError> {
+    let Json(request) = request.unwrap_or_default();
+    let key = virtual_host_key(&RealmName::parse("realm", &realm)?, &VirtualHostName::parse("virtualHost", &name)?);
+    let Some(kv) = get_from_etcd(&state, &key).await?.kvs().first().cloned() else {
+        return Err(ApiError::NotFound(format!(
             "VirtualHost '{}' not found in realm '{}'", name, realm
-        )))
+        )));
+    };
+    let mut host: VirtualHost = state.keyring.decode(kv.value())?;
+
+    let fqdn = subdomain_fqdn(&state, &host).await?;
+    let current = host.certificate.join("\n");
+    if let Some((cert, cert_key)) = ca::issue_for_host(&state, &realm, &fqdn, &current, &request).await? {
+        host.certificate = vec![cert];
+        host.key = Some(cert_key);
+        // 発行中に他の変更があれば 409 にする
+        let mut write = IndexedWrite::new();
+        write.require_revision(&key, kv.mod_revision());
+        write.put(&key, state.keyring.encode(&host)?);
+        write.commit(&state).await?;
     }
-}
\ No newline at end of file
+    Ok(Json(reveal.apply(host)))
+}
+
+/// GET /realms/{realm}/virtual-hosts/{virtual_host_name}/routing-chain
+///
+/// VirtualHost が配信に使う RoutingChain の版 (固定した版、なければ RoutingChain の有効な版) を返す。
+/// メンテナンス中は、その応答を返すルールを先頭に加える。
+async fn get_effective_routing_chain(
+    State(state): State<AppState>,
+    Path((realm, name)): Path<(String, String)>,
+) -> Result<Json<ChainVersion>, ApiError> {
+    let key = virtual_host_key(&RealmName::parse("realm", &realm)?, &VirtualHostName::parse("virtualHost", &name)?);
+    let Some(kv) = get_from_etcd(&state, &key).await?.kvs().first().cloned() else {
+        return Err(ApiError::NotFound(format!(
+            "VirtualHost '{}' not found in realm '{}'", name, realm
+        )));
+    };
+    let host: VirtualHost = state.keyring.decode(kv.value())?;
+    let not_found = |detail: String| ApiError::NotFound(format!("VirtualHost '{}' has no routing chain to serve: {}", name, detail));
+    let Ok(Urn::RoutingChain(chain_realm, chain_name)) = Urn::parse("routingChain", &host.routing_chain) else {
+        return Err(not_found(format!("'{}' is not a routing chain URN", host.routing_chain)));
+    };
+    // GET なので版を持たない RoutingChain も移行せずに読む
+    let mut version = match host.routing_chain_version {
+        Some(version) => routing_chain_version::get_chain_version(&state, &chain_realm, &chain_name, version)
+            .await?
+            .map(|(version, _)| version)
+            .ok_or_else(|| not_found(format!("version {} of '{}' does not exist", version, host.routing_chain)))?,
+        None => routing_chain_version::load_active(&state, &chain_realm, &chain_name)
+            .await?
+            .ok_or_else(|| not_found(format!("the active version of '{}' does not exist", host.routing_chain)))?,
+    };
+    if let Some(maintenance) = host.maintenance.filter(|maintenance| maintenance.enabled) {
+        let rule = Rule { id: None, match_expr: "true".to_string(), action: Action::Respond(maintenance.response) };
+        version.chain.rules.insert(0, rule);
+    }
+    Ok(Json(version))
+}
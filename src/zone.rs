@@ -1,7 +1,15 @@
+use crate::acme::{self, AcmeChallengeType};
 use crate::db::AppState;
+use crate::dns;
+use crate::dns_provider;
 use crate::error::ApiError;
+use crate::idn;
+use crate::secret::{Reveal, Secret, SecretFields};
 use crate::subdomain;
-use crate::utils::get_from_etcd;
+use crate::urn::Urn;
+use crate::utils::{decode_children, get_secret_document, get_from_etcd};
+use crate::validation::{self, RealmName, ZoneName};
+use crate::zonefile;
 use axum::{
   extract::{Path, State},
     routing::get,
@@ -14,7 +22,11 @@ use serde::{Deserialize, Serialize};
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct Zone {
+    // A-label 形式の Zone 名 (Unicode で指定しても正規化して保存する)
     pub zone: String,
+    // 国際化ドメイン名の場合の U-label 形式 (読み取り専用)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_zone: Option<String>,
     pub title: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
@@ -23,8 +35,48 @@ pub struct Zone {
     pub realm: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub dns_provider: Option<String>,
+    // `dnsProvider` が rfc2136:// の場合に DNS UPDATE を署名する TSIG 鍵
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_tsig_key: Option<TsigKey>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub acme_certificate_provider: Option<String>,
+    // ACME で使うチャレンジ (省略時は http-01)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acme_challenge_type: Option<AcmeChallengeType>,
+}
+
+/// DNS UPDATE 用の TSIG 鍵
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct TsigKey {
+    pub name: String,
+    // hmac-sha256 (既定) / hmac-sha384 / hmac-sha512
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<String>,
+    // 秘密情報 (base64): レスポンスでは既定で伏せられ、更新時に省略すると保存済みの値が維持される
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<Secret>,
+}
+
+impl SecretFields for Zone {
+    fn redact(&mut self) {
+        if let Some(key) = &mut self.dns_tsig_key {
+            key.secret = None;
+        }
+    }
+
+    fn retain_secrets(&mut self, stored: &Self) {
+        if let (Some(key), Some(stored)) = (&mut self.dns_tsig_key, &stored.dns_tsig_key) {
+            if key.secret.is_none() && key.name == stored.name {
+                key.secret = stored.secret.clone();
+            }
+        }
+    }
+
+    fn secrets_mut(&mut self) -> Vec<&mut Secret> {
+        self.dns_tsig_key.iter_mut().filter_map(|key| key.secret.as_mut()).collect()
+    }
 }
 
 /// Zone関連のエンドポイントをまとめたルーターを返す
@@ -32,42 +84,75 @@ pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/", get(list_zones).post(add_zone))
         .route("/{zone}", get(get_zone).put(update_zone).delete(delete_zone)).nest("/{zone}/subdomains", subdomain::routes())
+        .nest("/{zone}/acme", acme::zone_routes())
+        .nest("/{zone}/dns", dns::zone_routes())
+        .route("/{zone}/zonefile", get(zonefile::get_zonefile))
 }
 
 // etcdでのキー構造: /realms/{realm_name}/zones/{zone_name}
-fn zone_key(realm: &str, zone: &str) -> String {
+pub fn zone_key(realm: &RealmName, zone: &ZoneName) -> String {
     format!("/realms/{}/zones/{}", realm, zone)
 }
 
-fn zone_prefix(realm: &str) -> String {
+fn zone_prefix(realm: &RealmName) -> String {
     format!("/realms/{}/zones/", realm)
 }
 
+/// Zone を取得し、秘密情報を復号して返す (存在しなければ 404)
+pub async fn load_zone(state: &AppState, realm: &str, zone_name: &str) -> Result<Zone, ApiError> {
+    let key = zone_key(&RealmName::parse("realm", realm)?, &ZoneName::parse("zone", zone_name)?);
+    get_secret_document(state, &key)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Zone '{}' not found in realm '{}'", zone_name, realm)))
+}
+
+/// Zone 名を検証して A-label 形式に正規化し、表示用の U-label 形式を設定する
+fn normalize_zone_name(zone: &mut Zone) -> Result<ZoneName, ApiError> {
+    let name = ZoneName::parse("zone", &zone.zone)?;
+    zone.zone = name.to_string();
+    zone.display_zone = idn::display(&zone.zone);
+    Ok(name)
+}
+
+/// ACME ディレクトリ URL と DNS プロバイダーの設定を確認する
+fn validate_zone(zone: &Zone) -> Result<(), ApiError> {
+    dns_provider::for_zone(zone)?;
+    if let Some(url) = &zone.acme_certificate_provider {
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(validation::invalid(
+                "acmeCertificateProvider",
+                format!("must be an ACME directory URL, got '{}'", url),
+            ));
+        }
+    }
+    Ok(())
+}
+
 /// GET /realms/{realm}/zones
 async fn list_zones(
     State(state): State<AppState>,
     Path(realm): Path<String>,
+    reveal: Reveal,
 ) -> Result<Json<Vec<Zone>>, ApiError> {
     let mut client = state.etcd_client.clone();
-    let prefix = zone_prefix(&realm);
-    let resp = client.get(prefix, Some(etcd_client::GetOptions::new().with_prefix())).await?;
-    let zones = resp
-        .kvs()
-        .iter()
-        .filter_map(|kv| serde_json::from_slice(kv.value()).ok())
-        .collect();
-    Ok(Json(zones))
+    let prefix = zone_prefix(&RealmName::parse("realm", &realm)?);
+    let resp = client.get(prefix.as_str(), Some(etcd_client::GetOptions::new().with_prefix())).await?;
+    let zones = decode_children(&state, &prefix, resp.kvs());
+    Ok(Json(reveal.apply_all(zones)))
 }
 
 /// POST /realms/{realm}/zones
 async fn add_zone(
     State(state): State<AppState>,
     Path(realm): Path<String>,
+    reveal: Reveal,
     Json(mut zone): Json<Zone>,
 ) -> Result<Json<Zone>, ApiError> {
     let mut client = state.etcd_client.clone();
-    let key = zone_key(&realm, &zone.zone);
+    let realm_name = RealmName::parse("realm", &realm)?;
+    let key = zone_key(&realm_name, &normalize_zone_name(&mut zone)?);
 
+    #[allow(clippy::unnecessary_first_then_check)]
     if get_from_etcd(&state, &key).await?.kvs().first().is_some() {
         return Err(ApiError::Conflict(format!(
             "Zone '{}' in realm '{}' already exists.",
@@ -76,42 +161,58 @@ async fn add_zone(
         )));
     }
     
-    zone.realm = Some(format!("urn:chip-in:realm:{}", realm));
+    validate_zone(&zone)?;
+    zone.realm = Some(Urn::Realm(realm_name).to_string());
 
-    let value = serde_json::to_vec(&zone)?;
+    let value = state.keyring.encode(&zone)?;
     client.put(key, value, None).await?;
+    dns::schedule_sync(&state, &realm, &zone.zone);
 
-    Ok(Json(zone))
+    Ok(Json(reveal.apply(zone)))
 }
 
 /// PUT /realms/{realm}/zones/{zone}
 async fn update_zone(
     State(state): State<AppState>,
     Path((realm, zone_name)): Path<(String, String)>,
+    reveal: Reveal,
     Json(mut zone): Json<Zone>,
 ) -> Result<Json<Zone>, ApiError> {
-    let mut client = state.etcd_client.clone();    let key = zone_key(&realm, &zone.zone);
-
-    if zone.zone != zone_name {        return Err(ApiError::BadRequest(format!("Zone name in path ('{}') does not match name in body ('{}')", zone_name, zone.zone)));
+    let mut client = state.etcd_client.clone();
+    let name = normalize_zone_name(&mut zone)?;
+    let realm_name = RealmName::parse("realm", &realm)?;
+    let key = zone_key(&realm_name, &name);
+
+    if name != ZoneName::parse("zone", &zone_name)? {
+        return Err(validation::invalid(
+            "zone",
+            format!("name in path ('{}') does not match name in body ('{}')", zone_name, zone.zone),
+        ));
+    }
+    if let Some(stored) = get_secret_document::<Zone>(&state, &key).await? {
+        zone.retain_secrets(&stored);
     }
-    zone.realm = Some(format!("urn:chip-in:realm:{}", realm));
+    validate_zone(&zone)?;
+    zone.realm = Some(Urn::Realm(realm_name).to_string());
 
-    let value = serde_json::to_vec(&zone)?;
+    let value = state.keyring.encode(&zone)?;
     client.put(key, value, None).await?;
-    Ok(Json(zone))
+    dns::schedule_sync(&state, &realm, &zone.zone);
+    Ok(Json(reveal.apply(zone)))
 }
 
 /// GET /realms/{realm}/zones/{zone}
 async fn get_zone(
     State(state): State<AppState>,
     Path((realm, zone_name)): Path<(String, String)>,
+    reveal: Reveal,
 ) -> Result<Json<Zone>, ApiError> {
-   let key = zone_key(&realm, &zone_name);
+   let key = zone_key(&RealmName::parse("realm", &realm)?, &ZoneName::parse("zone", &zone_name)?);
 
 
     if let Some(kv) = get_from_etcd(&state, &key).await?.kvs().first() {
-        let zone = serde_json::from_slice(kv.value())?;
-        Ok(Json(zone))
+        let zone = state.keyring.decode(kv.value())?;
+        Ok(Json(reveal.apply(zone)))
     } else {
         Err(ApiError::NotFound(format!("Zone '{}' not found in realm '{}'", zone_name, realm)))
     }
@@ -121,16 +222,20 @@ async fn get_zone(
 async fn delete_zone(
     State(state): State<AppState>,
     Path((realm, zone_name)): Path<(String, String)>,
+    reveal: Reveal,
 ) -> Result<Json<Zone>, ApiError> {
     let mut client = state.etcd_client.clone();    // Retrieve the zone details to ensure we have the correct zone name
-    let zone = get_zone(State(state.clone()), Path((realm.clone(), zone_name.clone()))).await?;
-    let key = zone_key(&realm, &zone.zone); // Use the zone name from the retrieved data
+    let zone = load_zone(&state, &realm, &zone_name).await?;
+    let key = zone_key(&RealmName::parse("realm", &realm)?, &ZoneName::parse("zone", &zone.zone)?); // Use the zone name from the retrieved data
+    // 反映済みのレコードを消してから Zone を削除する (失敗すれば Zone は残る)
+    dns::unpublish_zone(&state, &realm, &zone).await?;
     let opts = etcd_client::DeleteOptions::new().with_prev_key();
     let resp = client.delete(key, Some(opts)).await?;
 
     if let Some(kv) = resp.prev_kvs().first() {
-        let zone = serde_json::from_slice(kv.value())?;
-        Ok(Json(zone))
+        let zone: Zone = state.keyring.decode(kv.value())?;
+        dns::forget_zone(&state, &realm, &zone.zone).await?;
+        Ok(Json(reveal.apply(zone)))
     } else {
         Err(ApiError::NotFound(format!("Zone '{}' not found in realm '{}'", zone_name, realm)))
     }
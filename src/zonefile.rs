@@ -0,0 +1,169 @@
+//! Zone の BIND 形式ゾーンファイル (RFC 1035) の出力
+//!
+//! レコードは DNS 同期と同じく `dns::zone_records` で算出する。
+//! シリアルは内容が変わったときの etcd のリビジョンとし、`/dns/serial/{realm}/{zone}` に保存して
+//! 同じ内容に対しては同じ値を返す。保存は Zone の同期を予約したときにバックグラウンドで行い (mod_revision を条件にした
+//! トランザクション)、ゾーンファイルの取得では書き込まない。保存値が古ければ同じ規則で求めた値を返す。
+use crate::db::AppState;
+use crate::dns::{self, DnsRecord, SoaDefaults};
+use crate::error::ApiError;
+use crate::utils::get_from_etcd;
+use crate::zone::{self, Zone};
+use axum::{
+    extract::{Path, State},
+    http::header,
+    response::IntoResponse,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use etcd_client::{Compare, CompareOp, Txn, TxnOp};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use tracing::warn;
+
+/// 保存済みのシリアルと、その時点の内容のダイジェスト
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ZoneSerial {
+    serial: u32,
+    digest: String,
+}
+
+/// シリアルの保存を競合で諦めるまでの試行回数
+const SERIAL_RETRIES: usize = 5;
+
+pub fn serial_key(realm: &str, zone: &str) -> String {
+    format!("/dns/serial/{}/{}", realm, zone)
+}
+
+/// GET /realms/{realm}/zones/{zone}/zonefile
+pub async fn get_zonefile(
+    State(state): State<AppState>,
+    Path((realm, zone_name)): Path<(String, String)>,
+) -> Result<impl IntoResponse, ApiError> {
+    let zone = zone::load_zone(&state, &realm, &zone_name).await?;
+    let (body, digest, revision) = render_body(&state, &realm, &zone).await?;
+    let stored = load_serial(&state, &realm, &zone.zone).await?;
+    let serial = match &stored {
+        Some((stored, _)) if stored.digest == digest => stored.serial,
+        _ => {
+            schedule_serial_refresh(&state, &realm, &zone.zone);
+            next_serial(stored.as_ref().map(|(stored, _)| stored), revision)
+        }
+    };
+
+    let mut zonefile = format!("; Zone file for {} (generated from the inventory)\n", zone.zone);
+    let _ = writeln!(zonefile, "$ORIGIN {}.", zone.zone);
+    let _ = writeln!(zonefile, "$TTL {}", state.dns.default_ttl);
+    zonefile.push_str(&render_soa(&zone.zone, &state.dns.soa, serial));
+    zonefile.push_str(&body);
+    Ok(([(header::CONTENT_TYPE, "text/dns; charset=utf-8")], zonefile))
+}
+
+/// シリアル以外の内容と、そのダイジェストと算出時点のリビジョン
+async fn render_body(state: &AppState, realm: &str, zone: &Zone) -> Result<(String, String, i64), ApiError> {
+    let records = dns::zone_records(state, realm, zone).await?;
+    let body = render_records(&zone.zone, &state.dns.soa, &records.records, &records.warnings);
+    let digest = BASE64.encode(digest(&SHA256, body.as_bytes()));
+    Ok((body, digest, records.revision))
+}
+
+/// 保存済みのシリアルとその mod_revision
+async fn load_serial(state: &AppState, realm: &str, zone: &str) -> Result<Option<(ZoneSerial, i64)>, ApiError> {
+    match get_from_etcd(state, &serial_key(realm, zone)).await?.kvs().first() {
+        Some(kv) => Ok(Some((serde_json::from_slice(kv.value())?, kv.mod_revision()))),
+        None => Ok(None),
+    }
+}
+
+/// 内容が変わったときのシリアル
+///
+/// リビジョンは単調増加するが、32 ビットに収まらない場合や保存値を下回る場合は保存値の次とする。
+fn next_serial(stored: Option<&ZoneSerial>, revision: i64) -> u32 {
+    match (u32::try_from(revision), stored) {
+        (Ok(rev), Some(stored)) if rev > stored.serial => rev,
+        (Ok(rev), None) => rev,
+        (_, Some(stored)) => stored.serial.wrapping_add(1),
+        (Err(_), None) => 1,
+    }
+}
+
+/// 内容が変わっていればシリアルを進めて保存する (同時に保存されていれば読み直す)
+async fn refresh_serial(state: &AppState, realm: &str, zone_name: &str) -> Result<(), ApiError> {
+    let zone = zone::load_zone(state, realm, zone_name).await?;
+    let key = serial_key(realm, &zone.zone);
+    for _ in 0..SERIAL_RETRIES {
+        let (_, digest, revision) = render_body(state, realm, &zone).await?;
+        let stored = load_serial(state, realm, &zone.zone).await?;
+        let guard = match &stored {
+            Some((stored, _)) if stored.digest == digest => return Ok(()),
+            Some((_, mod_revision)) => Compare::mod_revision(key.as_str(), CompareOp::Equal, *mod_revision),
+            None => Compare::version(key.as_str(), CompareOp::Equal, 0),
+        };
+        let serial = ZoneSerial { serial: next_serial(stored.as_ref().map(|(stored, _)| stored), revision), digest };
+        let txn = Txn::new().when([guard]).and_then([TxnOp::put(key.as_str(), serde_json::to_vec(&serial)?, None)]);
+        let mut client = state.etcd_client.clone();
+        if client.txn(txn).await?.succeeded() {
+            return Ok(());
+        }
+    }
+    Err(ApiError::Conflict(format!("The serial of zone '{}' was updated concurrently", zone.zone)))
+}
+
+/// シリアルの保存をバックグラウンドで行う
+pub fn schedule_serial_refresh(state: &AppState, realm: &str, zone_name: &str) {
+    let (state, realm, zone_name) = (state.clone(), realm.to_string(), zone_name.to_string());
+    tokio::spawn(async move {
+        if let Err(e) = refresh_serial(&state, &realm, &zone_name).await {
+            warn!("Failed to update the zone file serial for zone '{}': {:#}", zone_name, e);
+        }
+    });
+}
+
+fn expand(template: &str, zone: &str) -> String {
+    let name = template.replace("{zone}", zone);
+    if name.ends_with('.') { name } else { format!("{}.", name) }
+}
+
+fn render_soa(zone: &str, soa: &SoaDefaults, serial: u32) -> String {
+    format!(
+        "@\tIN\tSOA\t{} {} (\n\t\t{}\t; serial\n\t\t{}\t; refresh\n\t\t{}\t; retry\n\t\t{}\t; expire\n\t\t{} )\t; minimum\n",
+        expand(&soa.mname, zone),
+        expand(&soa.rname, zone),
+        serial,
+        soa.refresh,
+        soa.retry,
+        soa.expire,
+        soa.minimum
+    )
+}
+
+/// NS とレコード (と算出時の警告) を出力する
+fn render_records(zone: &str, soa: &SoaDefaults, records: &[DnsRecord], warnings: &[String]) -> String {
+    let mut out = String::new();
+    let nameservers = if soa.nameservers.is_empty() { std::slice::from_ref(&soa.mname) } else { soa.nameservers.as_slice() };
+    for ns in nameservers {
+        let _ = writeln!(out, "@\tIN\tNS\t{}", expand(ns, zone));
+    }
+    if !records.is_empty() {
+        out.push('\n');
+    }
+    for record in records {
+        let _ = writeln!(out, "{}\t{}\tIN\t{}\t{}", owner_name(&record.name, zone), record.ttl, record.record_type.as_str(), record.data);
+    }
+    for warning in warnings {
+        let _ = writeln!(out, "; warning: {}", warning);
+    }
+    out
+}
+
+/// FQDN をゾーンの起点からの相対名にする (apex は `@`)
+fn owner_name(fqdn: &str, zone: &str) -> String {
+    if fqdn == zone {
+        return "@".to_string();
+    }
+    match fqdn.strip_suffix(zone).and_then(|label| label.strip_suffix('.')) {
+        Some(label) => label.to_string(),
+        None => format!("{}.", fqdn),
+    }
+}
@@ -24,6 +24,10 @@ step "Running RoutingChain tests..."
 ./test_routing_chain.sh
 ok "RoutingChain tests passed."
 
+step "Running Blob tests..."
+./test_blob.sh
+ok "Blob tests passed."
+
 step "Running VirtualHost tests..."
 ./test_virtual_host.sh
 ok "VirtualHost tests passed."
@@ -36,4 +40,28 @@ step "Running Service tests..."
 ./test_service.sh
 ok "Service tests passed."
 
+step "Running Certificate tests..."
+./test_certificate.sh
+ok "Certificate tests passed."
+
+step "Running ACME tests..."
+./test_acme.sh
+ok "ACME tests passed."
+
+step "Running DNS tests..."
+./test_dns.sh
+ok "DNS tests passed."
+
+step "Running Host Index tests..."
+./test_host_index.sh
+ok "Host Index tests passed."
+
+step "Running URN tests..."
+./test_urn.sh
+ok "URN tests passed."
+
+step "Running Validation tests..."
+./test_validation.sh
+ok "Validation tests passed."
+
 step "\e[1;32mAll API tests passed successfully!\e[0m"
\ No newline at end of file
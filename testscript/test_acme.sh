@@ -0,0 +1,94 @@
+#!/bin/bash
+
+source ./test_helper.sh
+
+# ACME 証明書の自動取得テスト
+#
+# A1-A4 は ACME サーバーなしで実行できる。A5 以降は Pebble などの ACME サーバーを用意し、
+# ACME_TEST_DIRECTORY_URL (例: https://pebble:14000/dir) を指定した場合のみ実行する。
+# API サーバーには ACME_CA_BUNDLE (Pebble の minica 証明書) または ACME_INSECURE_SKIP_VERIFY=true、
+# DNS-01 の場合、テスト用の Zone は dnsProvider を持たないため ACME_DNS01_CHALLTESTSRV_URL (例: http://pebble-challtestsrv:8055) を設定しておくこと。
+ACME_TEST_DIRECTORY_URL="${ACME_TEST_DIRECTORY_URL:-}"
+ACME_TEST_CHALLENGE_TYPE="${ACME_TEST_CHALLENGE_TYPE:-dns-01}"
+
+ZONE_NAME="acme.test"
+SUBDOMAIN_NAME="www"
+VIRTUAL_HOST_NAME="acme-www"
+ROUTING_CHAIN_NAME="acme-chain"
+FQDN="${SUBDOMAIN_NAME}.${ZONE_NAME}"
+
+# --- Main Script ---
+check_jq
+
+step "P. Create prerequisite Realm, Subdomain and VirtualHost for ACME Test"
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${REALM_NAME}"'", "title": "ACME Test Realm", "signingKey": "a-very-long-signing-key"}' "${API_BASE_URL}/realms" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}" > /dev/null || true
+curl -s -X POST -H "Content-Type: application/json" -d '{"zone": "'"${ZONE_NAME}"'", "title": "ACME Test Zone"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones" > /dev/null
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${SUBDOMAIN_NAME}"'", "title": "ACME Subdomain"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains" > /dev/null || true
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${ROUTING_CHAIN_NAME}"'", "title": "ACME Chain"}' "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts/${VIRTUAL_HOST_NAME}" > /dev/null || true
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${VIRTUAL_HOST_NAME}"'", "title": "ACME VH", "subdomain": "urn:chip-in:subdomain:'"${REALM_NAME}"':'"${ZONE_NAME}"':'"${SUBDOMAIN_NAME}"'", "routingChain": "urn:chip-in:routing-chain:'"${REALM_NAME}"':'"${ROUTING_CHAIN_NAME}"'"}' "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts" > /dev/null
+ok "Prerequisites created."
+
+step "A1. PUT zone with a non-URL acmeCertificateProvider (expecting 400)"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X PUT -H "Content-Type: application/json" -d '{"zone": "'"${ZONE_NAME}"'", "title": "ACME Test Zone", "acmeCertificateProvider": "letsencrypt"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}")
+[ "$HTTP_CODE" -eq 400 ] || fail "Expected HTTP 400, but got $HTTP_CODE"
+ok "Invalid ACME directory URL rejected."
+
+step "A2. POST .../acme/orders on a zone without a provider (expecting 422)"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X POST "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/acme/orders")
+[ "$HTTP_CODE" -eq 422 ] || fail "Expected HTTP 422, but got $HTTP_CODE"
+ok "Order without a provider rejected."
+
+step "A3. GET .../acme/orders/${FQDN} before any order (expecting 404)"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/acme/orders/${FQDN}")
+[ "$HTTP_CODE" -eq 404 ] || fail "Expected HTTP 404, but got $HTTP_CODE"
+ok "No order recorded yet."
+
+step "A4. GET /.well-known/acme-challenge/unknown (expecting 404)"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" "${API_BASE_URL}/.well-known/acme-challenge/unknown")
+[ "$HTTP_CODE" -eq 404 ] || fail "Expected HTTP 404, but got $HTTP_CODE"
+ok "Unknown challenge token not served."
+
+if [ -n "$ACME_TEST_DIRECTORY_URL" ]; then
+    step "A5. PUT zone with acmeCertificateProvider=${ACME_TEST_DIRECTORY_URL} (${ACME_TEST_CHALLENGE_TYPE})"
+    HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X PUT -H "Content-Type: application/json" -d '{"zone": "'"${ZONE_NAME}"'", "title": "ACME Test Zone", "acmeCertificateProvider": "'"${ACME_TEST_DIRECTORY_URL}"'", "acmeChallengeType": "'"${ACME_TEST_CHALLENGE_TYPE}"'"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}")
+    [ "$HTTP_CODE" -eq 200 ] || fail "Failed to update zone. Expected 200, got $HTTP_CODE"
+    ok "Zone configured for ACME."
+
+    step "A6. POST .../acme/orders - Starting an order for ${FQDN}"
+    RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d '{"fqdn": "'"${FQDN}"'", "force": true}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/acme/orders")
+    HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+    BODY=$(echo "$RESPONSE" | sed '$d')
+    [ "$HTTP_CODE" -eq 202 ] || fail "Expected HTTP 202, but got $HTTP_CODE. Body: $BODY"
+    echo "$BODY" | jq -e '.[0].fqdn == "'"${FQDN}"'"' > /dev/null || fail "Order for '${FQDN}' was not accepted. Body: $BODY"
+    ok "Order accepted."
+
+    step "A7. GET .../acme/orders/${FQDN} - Waiting for the order to become valid"
+    for _ in $(seq 1 60); do
+        ORDER=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/acme/orders/${FQDN}")
+        STATUS=$(echo "$ORDER" | jq -r '.status')
+        [ "$STATUS" == "valid" ] || [ "$STATUS" == "invalid" ] && break
+        sleep 2
+    done
+    [ "$STATUS" == "valid" ] || fail "Order did not become valid.\nGot: $ORDER"
+    ok "Order is valid."
+
+    step "A8. GET /realms/${REALM_NAME}/certificates - Issued certificate stored on the virtual host"
+    BODY=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/certificates")
+    echo "$BODY" | jq -e '.[] | select(.owner.name == "'"${VIRTUAL_HOST_NAME}"'" and .owner.index == 0 and (.sans | index("'"${FQDN}"'")))' > /dev/null \
+        || fail "Virtual host certificate for '${FQDN}' not found.\nGot: $BODY"
+    ok "Certificate stored on the virtual host."
+else
+    ok "ACME_TEST_DIRECTORY_URL is not set; skipping issuance tests."
+fi
+
+step "Cleanup: Deleting resources used for ACME test..."
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts/${VIRTUAL_HOST_NAME}" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains/${SUBDOMAIN_NAME}" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}" > /dev/null || true
+ok "Cleanup complete."
+
+step "\e[1;32mAll ACME tests passed successfully!\e[0m"
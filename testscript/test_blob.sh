@@ -0,0 +1,53 @@
+#!/bin/bash
+
+source ./test_helper.sh
+
+BLOB_NAME="maintenance-page"
+BLOB_BODY="<html><body>Back soon.</body></html>"
+
+# --- Main Script ---
+check_jq
+
+step "P. Create prerequisite Realm for Blob Test"
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${REALM_NAME}"'", "title": "Blob Test Realm", "cacert": '"${TEST_CA_CERT_JSON}"', "signingKey": "a-very-long-signing-key"}' "${API_BASE_URL}/realms" > /dev/null || true
+ok "Prerequisite Realm for Blob test created or already exists."
+
+step "B1. PUT /realms/${REALM_NAME}/blobs/${BLOB_NAME} - Storing a blob"
+RESPONSE=$(curl -s -w "\n%{http_code}" -X PUT -H "Content-Type: text/html; charset=utf-8" --data-binary "$BLOB_BODY" "${API_BASE_URL}/realms/${REALM_NAME}/blobs/${BLOB_NAME}")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to store blob. Expected 200, got $HTTP_CODE. Body: $BODY"
+[ "$(echo "$BODY" | jq -c '[.name, .contentType, .size]')" == '["'"${BLOB_NAME}"'","text/html; charset=utf-8",'"${#BLOB_BODY}"']' ] || fail "Unexpected blob info. Body: $BODY"
+ok "Blob stored successfully."
+
+step "B2. GET /realms/${REALM_NAME}/blobs/${BLOB_NAME} - Retrieving the blob"
+CONTENT_TYPE=$(curl -s -o /dev/null -w "%{content_type}" "${API_BASE_URL}/realms/${REALM_NAME}/blobs/${BLOB_NAME}")
+[ "$CONTENT_TYPE" == "text/html; charset=utf-8" ] || fail "Expected the stored content type, got '$CONTENT_TYPE'."
+[ "$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/blobs/${BLOB_NAME}")" == "$BLOB_BODY" ] || fail "Expected the stored body."
+[ "$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/blobs" | jq -r '.[].name')" == "$BLOB_NAME" ] || fail "Expected the blob to be listed."
+ok "Blob retrieved successfully."
+
+step "B3. DELETE a blob used by a routing chain (expecting 409)"
+CHAIN_JSON='{"name": "blob-chain", "title": "Blob Chain", "rules": [{"match": "true", "action": {"type": "respond", "status": 503, "body": {"blob": "'"${BLOB_NAME}"'"}}}]}'
+curl -s -X POST -H "Content-Type: application/json" -d "$CHAIN_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains" > /dev/null
+RESPONSE=$(curl -s -w "\n%{http_code}" -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/blobs/${BLOB_NAME}")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 409 ] || fail "Expected 409 while the blob is in use, got $HTTP_CODE."
+echo "$RESPONSE" | sed '$d' | jq -e '.message | contains("blob-chain")' > /dev/null || fail "Expected the message to name the routing chain. Body: $(echo "$RESPONSE" | sed '$d')"
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/blob-chain" > /dev/null
+ok "Blob in use was not deleted."
+
+step "B4. DELETE /realms/${REALM_NAME}/blobs/${BLOB_NAME} - Deleting the blob"
+RESPONSE=$(curl -s -w "\n%{http_code}" -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/blobs/${BLOB_NAME}")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to delete blob. Expected 200, got $HTTP_CODE."
+RESPONSE=$(curl -s -w "\n%{http_code}" "${API_BASE_URL}/realms/${REALM_NAME}/blobs/${BLOB_NAME}")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 404 ] || fail "Expected 404 after deletion, got $HTTP_CODE."
+ok "Blob deleted successfully."
+
+step "Cleanup: Deleting prerequisite Realm..."
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}" > /dev/null || true
+ok "Cleanup complete."
+
+step "\e[1;32mAll Blob API tests passed successfully!\e[0m"
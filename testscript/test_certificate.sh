@@ -0,0 +1,50 @@
+#!/bin/bash
+
+source ./test_helper.sh
+
+HUB_NAME="cert-test-hub"
+issue_test_cert cert-hub "cert-hub.test.local"
+
+# --- Main Script ---
+check_jq
+
+step "P. Create prerequisite Realm and Hub for Certificate Inventory Test"
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${REALM_NAME}"'", "title": "Certificate Test Realm", "cacert": '"${TEST_CA_CERT_JSON}"', "signingKey": "a-very-long-signing-key"}' "${API_BASE_URL}/realms" > /dev/null || true
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${HUB_NAME}"'", "title": "Cert Hub", "fqdn": "cert-hub.test.local", "serverCert": '"$(pem_json "$TEST_PKI_DIR/cert-hub.pem")"', "serverCertKey": '"$(pem_json "$TEST_PKI_DIR/cert-hub.key")"'}' "${API_BASE_URL}/realms/${REALM_NAME}/hubs" > /dev/null || true
+ok "Prerequisites for Certificate test created or already exist."
+
+step "C1. GET /realms/${REALM_NAME}/certificates - Listing certificates of the realm"
+RESPONSE=$(curl -s -w "\n%{http_code}" "${API_BASE_URL}/realms/${REALM_NAME}/certificates")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to list certificates. Expected 200, got $HTTP_CODE. Body: $BODY"
+echo "$BODY" | jq -e '.[] | select(.owner.kind=="Realm" and .owner.field=="cacert")' > /dev/null || fail "Realm CA certificate is missing. Body: $BODY"
+echo "$BODY" | jq -e '.[] | select(.owner.kind=="Hub" and .owner.name=="'"${HUB_NAME}"'" and (.sans | index("cert-hub.test.local")) and .daysRemaining >= 29)' > /dev/null || fail "Hub certificate is missing or incomplete. Body: $BODY"
+ok "Certificates of the realm are listed."
+
+step "C2. GET /certificates?expiringWithin=7d - Filtering by expiry"
+BODY=$(curl -s "${API_BASE_URL}/certificates?expiringWithin=7d")
+if echo "$BODY" | jq -e '.[] | select(.owner.name=="'"${HUB_NAME}"'")' > /dev/null; then
+    fail "Hub certificate valid for 30 days should not be listed as expiring within 7 days."
+fi
+BODY=$(curl -s "${API_BASE_URL}/certificates?expiringWithin=60d")
+echo "$BODY" | jq -e '.[] | select(.owner.name=="'"${HUB_NAME}"'")' > /dev/null || fail "Hub certificate should be listed as expiring within 60 days."
+ok "expiringWithin filter works."
+
+step "C3. GET /certificates?expiringWithin=soon - Invalid filter (expecting 400)"
+RESPONSE=$(curl -s -w "\n%{http_code}" "${API_BASE_URL}/certificates?expiringWithin=soon")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 400 ] || fail "Expected HTTP 400, but got $HTTP_CODE"
+for VALUE in 9223372036854775807d 3651d; do
+  RESPONSE=$(curl -s -w "\n%{http_code}" "${API_BASE_URL}/certificates?expiringWithin=${VALUE}")
+  HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+  [ "$HTTP_CODE" -eq 400 ] || fail "Expected HTTP 400 for expiringWithin=${VALUE}, but got $HTTP_CODE"
+done
+ok "Correctly received 400 Bad Request, including for out-of-range periods."
+
+step "Cleanup: Deleting prerequisite Hub and Realm..."
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/hubs/${HUB_NAME}" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}" > /dev/null || true
+ok "Cleanup complete."
+
+step "\e[1;32mAll Certificate API tests passed successfully!\e[0m"
@@ -0,0 +1,132 @@
+#!/bin/bash
+
+source ./test_helper.sh
+
+# DNS 同期テスト
+#
+# D1-D5 は DNS サーバーなしで実行できる。D6 以降は DNS UPDATE を受け付けるローカルの DNS サーバー
+# (例: allow-update { key "test-key"; }; を設定した BIND) を用意し、DNS_TEST_SERVER (例: 127.0.0.1:5353) と
+# DNS_TEST_TSIG_SECRET (base64、未設定なら TSIG なし) を指定した場合のみ実行する。
+DNS_TEST_SERVER="${DNS_TEST_SERVER:-}"
+DNS_TEST_TSIG_NAME="${DNS_TEST_TSIG_NAME:-test-key}"
+DNS_TEST_TSIG_SECRET="${DNS_TEST_TSIG_SECRET:-}"
+
+ZONE_NAME="dns.test"
+SUBDOMAIN_NAME="www"
+VIRTUAL_HOST_NAME="dns-www"
+ROUTING_CHAIN_NAME="dns-chain"
+HUB_NAME="dns-hub"
+HUB_FQDN="hub.dns.test"
+FQDN="${SUBDOMAIN_NAME}.${ZONE_NAME}"
+
+# --- Main Script ---
+check_jq
+
+step "P. Create prerequisite Realm, Hub, Zone, Subdomain and VirtualHost for DNS Test"
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${REALM_NAME}"'", "title": "DNS Test Realm", "signingKey": "a-very-long-signing-key"}' "${API_BASE_URL}/realms" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/hubs/${HUB_NAME}" > /dev/null || true
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${HUB_NAME}"'", "title": "DNS Hub", "fqdn": "'"${HUB_FQDN}"'"}' "${API_BASE_URL}/realms/${REALM_NAME}/hubs" > /dev/null
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}" > /dev/null || true
+curl -s -X POST -H "Content-Type: application/json" -d '{"zone": "'"${ZONE_NAME}"'", "title": "DNS Test Zone"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones" > /dev/null
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${SUBDOMAIN_NAME}"'", "title": "DNS Subdomain"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains" > /dev/null || true
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${ROUTING_CHAIN_NAME}"'", "title": "DNS Chain"}' "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts/${VIRTUAL_HOST_NAME}" > /dev/null || true
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${VIRTUAL_HOST_NAME}"'", "title": "DNS VH", "subdomain": "urn:chip-in:subdomain:'"${REALM_NAME}"':'"${ZONE_NAME}"':'"${SUBDOMAIN_NAME}"'", "routingChain": "urn:chip-in:routing-chain:'"${REALM_NAME}"':'"${ROUTING_CHAIN_NAME}"'"}' "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts" > /dev/null
+ok "Prerequisites created."
+
+step "D1. PUT zone with a malformed rfc2136 dnsProvider (expecting 400)"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X PUT -H "Content-Type: application/json" -d '{"zone": "'"${ZONE_NAME}"'", "title": "DNS Test Zone", "dnsProvider": "rfc2136://"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}")
+[ "$HTTP_CODE" -eq 400 ] || fail "Expected HTTP 400, but got $HTTP_CODE"
+ok "Malformed DNS provider rejected."
+
+step "D2. PUT zone with an unsupported TSIG algorithm (expecting 400)"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X PUT -H "Content-Type: application/json" -d '{"zone": "'"${ZONE_NAME}"'", "title": "DNS Test Zone", "dnsProvider": "rfc2136://127.0.0.1:53", "dnsTsigKey": {"name": "k", "algorithm": "hmac-md5", "secret": "c2VjcmV0"}}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}")
+[ "$HTTP_CODE" -eq 400 ] || fail "Expected HTTP 400, but got $HTTP_CODE"
+ok "Unsupported TSIG algorithm rejected."
+
+step "D3. GET .../dns/plan - Dry-run plan points ${FQDN} at the hub"
+BODY=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/dns/plan")
+echo "$BODY" | jq -e '.create[] | select(.name == "'"${FQDN}"'" and .type == "CNAME" and .data == "'"${HUB_FQDN}"'.")' > /dev/null \
+    || fail "Plan should create a CNAME from '${FQDN}' to '${HUB_FQDN}'.\nGot: $BODY"
+echo "$BODY" | jq -e '.delete == []' > /dev/null || fail "Plan should not delete anything.\nGot: $BODY"
+ok "Plan contains the hub record."
+
+step "D4. POST .../dns/sync on a zone without a supported provider (expecting 422)"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X POST "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/dns/sync")
+[ "$HTTP_CODE" -eq 422 ] || fail "Expected HTTP 422, but got $HTTP_CODE"
+ok "Sync without a provider rejected."
+
+step "D5. GET .../dns - Nothing has been synced yet"
+BODY=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/dns")
+echo "$BODY" | jq -e '.records == [] and (has("syncedAt") | not)' > /dev/null || fail "Sync state should be empty.\nGot: $BODY"
+ok "Sync state is empty."
+
+step "D5a. GET .../zonefile - RFC 1035 zone file with SOA, NS and the hub record"
+ZONEFILE=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/zonefile")
+echo "$ZONEFILE" | grep -q "^\$ORIGIN ${ZONE_NAME}\.$" || fail "Zone file should start with \$ORIGIN ${ZONE_NAME}.\nGot: $ZONEFILE"
+echo "$ZONEFILE" | grep -qE "^@[[:space:]]+IN[[:space:]]+SOA[[:space:]]" || fail "Zone file should contain an SOA record.\nGot: $ZONEFILE"
+echo "$ZONEFILE" | grep -qE "^@[[:space:]]+IN[[:space:]]+NS[[:space:]]" || fail "Zone file should contain an NS record.\nGot: $ZONEFILE"
+echo "$ZONEFILE" | grep -qE "^${SUBDOMAIN_NAME}[[:space:]]+[0-9]+[[:space:]]+IN[[:space:]]+CNAME[[:space:]]+${HUB_FQDN}\.$" \
+    || fail "Zone file should contain '${SUBDOMAIN_NAME} IN CNAME ${HUB_FQDN}.'.\nGot: $ZONEFILE"
+SERIAL=$(echo "$ZONEFILE" | awk '/; serial/ {print $1}')
+SERIAL_AGAIN=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/zonefile" | awk '/; serial/ {print $1}')
+[ -n "$SERIAL" ] && [ "$SERIAL" == "$SERIAL_AGAIN" ] || fail "Serial should be stable while nothing changes ('$SERIAL' vs '$SERIAL_AGAIN')."
+if command -v named-checkzone &> /dev/null; then
+    echo "$ZONEFILE" | named-checkzone "${ZONE_NAME}" /dev/stdin > /dev/null || fail "named-checkzone rejected the zone file.\nGot: $ZONEFILE"
+fi
+ok "Zone file rendered with stable serial ${SERIAL}."
+
+if [ -n "$DNS_TEST_SERVER" ]; then
+    step "D6. PUT zone with dnsProvider=rfc2136://${DNS_TEST_SERVER}"
+    TSIG_JSON=""
+    [ -n "$DNS_TEST_TSIG_SECRET" ] && TSIG_JSON=', "dnsTsigKey": {"name": "'"${DNS_TEST_TSIG_NAME}"'", "secret": "'"${DNS_TEST_TSIG_SECRET}"'"}'
+    HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X PUT -H "Content-Type: application/json" -d '{"zone": "'"${ZONE_NAME}"'", "title": "DNS Test Zone", "dnsProvider": "rfc2136://'"${DNS_TEST_SERVER}"'"'"${TSIG_JSON}"'}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}")
+    [ "$HTTP_CODE" -eq 200 ] || fail "Failed to update zone. Expected 200, got $HTTP_CODE"
+    ok "Zone configured for RFC 2136."
+
+    step "D7. POST .../dns/sync - Applying the plan"
+    RESPONSE=$(curl -s -w "\n%{http_code}" -X POST "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/dns/sync")
+    HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+    BODY=$(echo "$RESPONSE" | sed '$d')
+    [ "$HTTP_CODE" -eq 200 ] || fail "Sync failed. Expected 200, got $HTTP_CODE. Body: $BODY"
+    if command -v dig &> /dev/null; then
+        ANSWER=$(dig +short -p "${DNS_TEST_SERVER##*:}" "@${DNS_TEST_SERVER%:*}" "${FQDN}" CNAME)
+        [ "$ANSWER" == "${HUB_FQDN}." ] || fail "DNS server should answer '${HUB_FQDN}.' for '${FQDN}', got '$ANSWER'"
+    fi
+    ok "Records synced."
+
+    step "D8. DELETE virtual host - The record is removed on the next sync"
+    curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts/${VIRTUAL_HOST_NAME}" > /dev/null
+    BODY=$(curl -s -X POST "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/dns/sync")
+    BODY=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/dns")
+    echo "$BODY" | jq -e '.records == []' > /dev/null || fail "Records should be removed after the virtual host is deleted.\nGot: $BODY"
+    if command -v dig &> /dev/null; then
+        ANSWER=$(dig +short -p "${DNS_TEST_SERVER##*:}" "@${DNS_TEST_SERVER%:*}" "${FQDN}" CNAME)
+        [ -z "$ANSWER" ] || fail "DNS server should no longer answer for '${FQDN}', got '$ANSWER'"
+    fi
+    ok "Records removed."
+
+    step "D9. DELETE zone - Published records are removed with the zone"
+    curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${VIRTUAL_HOST_NAME}"'", "title": "DNS VH", "subdomain": "urn:chip-in:subdomain:'"${REALM_NAME}"':'"${ZONE_NAME}"':'"${SUBDOMAIN_NAME}"'", "routingChain": "urn:chip-in:routing-chain:'"${REALM_NAME}"':'"${ROUTING_CHAIN_NAME}"'"}' "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts" > /dev/null
+    curl -s -X POST "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/dns/sync" > /dev/null
+    HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}")
+    [ "$HTTP_CODE" -eq 200 ] || fail "Failed to delete zone. Expected 200, got $HTTP_CODE"
+    if command -v dig &> /dev/null; then
+        ANSWER=$(dig +short -p "${DNS_TEST_SERVER##*:}" "@${DNS_TEST_SERVER%:*}" "${FQDN}" CNAME)
+        [ -z "$ANSWER" ] || fail "DNS server should no longer answer for '${FQDN}' after the zone is deleted, got '$ANSWER'"
+    fi
+    ok "Zone records removed."
+else
+    ok "DNS_TEST_SERVER is not set; skipping DNS UPDATE tests."
+fi
+
+step "Cleanup: Deleting resources used for DNS test..."
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts/${VIRTUAL_HOST_NAME}" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains/${SUBDOMAIN_NAME}" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/hubs/${HUB_NAME}" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}" > /dev/null || true
+ok "Cleanup complete."
+
+step "\e[1;32mAll DNS tests passed successfully!\e[0m"
@@ -10,6 +10,12 @@ set -o pipefail
 step() { echo -e "\n\n\e[1;34m>>> $1\e[0m"; }
 ok() { echo -e "\e[32mOK: $1\e[0m"; }
 fail() { echo -e "\e[1;31mFAIL: $1\e[0m"; exit 1; }
+check_openssl() {
+    if ! command -v openssl &> /dev/null; then
+        echo "openssl could not be found, please install it to run the tests."
+        exit 1
+    fi
+}
 check_jq() {
     if ! command -v jq &> /dev/null; then
         echo "jq could not be found, please install it to run the tests."
@@ -20,5 +26,42 @@ check_jq() {
 # --- Common Configuration ---
 API_BASE_URL="http://127.0.0.1:8080"
 REALM_NAME="test-realm"
+# 秘密情報の開示テスト用 (サーバーの REVEAL_SECRETS_TOKEN と同じ値。未設定なら開示の検証を省略)
+REVEAL_SECRETS_TOKEN="${REVEAL_SECRETS_TOKEN:-}"
+# 管理用エンドポイントのテスト用 (サーバーの ADMIN_TOKEN と同じ値。未設定なら拒否されることだけを検証)
+ADMIN_TOKEN="${ADMIN_TOKEN:-}"
+
+export API_BASE_URL REALM_NAME REVEAL_SECRETS_TOKEN ADMIN_TOKEN
+
+# --- Test Certificates ---
+# 証明書の検証 (PEM の解析、秘密鍵との対応、SAN) を通過するテスト用の CA とサーバー証明書
+TEST_PKI_DIR="${TEST_PKI_DIR:-/tmp/chip-in-test-pki}"
+
+# テスト用 CA を生成する (生成済みなら再利用)
+ensure_test_ca() {
+    check_openssl
+    mkdir -p "$TEST_PKI_DIR"
+    [ -f "$TEST_PKI_DIR/ca.pem" ] && return
+    openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -days 30 \
+        -subj "/CN=chip-in test CA" -addext "basicConstraints=critical,CA:TRUE" \
+        -keyout "$TEST_PKI_DIR/ca.key" -out "$TEST_PKI_DIR/ca.pem" 2> /dev/null
+}
+
+# $1: 出力名, $2: SAN に含める FQDN -> $TEST_PKI_DIR/$1.pem, $TEST_PKI_DIR/$1.key
+issue_test_cert() {
+    ensure_test_ca
+    [ -f "$TEST_PKI_DIR/$1.pem" ] && return
+    openssl req -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -subj "/CN=$2" \
+        -keyout "$TEST_PKI_DIR/$1.key" -out "$TEST_PKI_DIR/$1.csr" 2> /dev/null
+    openssl x509 -req -in "$TEST_PKI_DIR/$1.csr" -CA "$TEST_PKI_DIR/ca.pem" -CAkey "$TEST_PKI_DIR/ca.key" \
+        -CAcreateserial -days 30 -extfile <(printf "subjectAltName=DNS:%s" "$2") \
+        -out "$TEST_PKI_DIR/$1.pem" 2> /dev/null
+}
+
+# ファイルの内容を JSON 文字列 (引用符付き) として出力する
+pem_json() { jq -Rs . < "$1"; }
+
+ensure_test_ca
+TEST_CA_CERT_JSON=$(pem_json "$TEST_PKI_DIR/ca.pem")
 
-export API_BASE_URL REALM_NAME
\ No newline at end of file
+export TEST_PKI_DIR TEST_CA_CERT_JSON
@@ -0,0 +1,121 @@
+#!/bin/bash
+
+source ./test_helper.sh
+
+# ホスト名の一意性の索引のテスト
+#
+# Subdomain の FQDN、VirtualHost が配信する Subdomain、Hub の FQDN:ポートが Realm をまたいで一意であることと、
+# GET /lookup/host/{fqdn} がそれを配信するリソースを返すことを確認する。
+
+OTHER_REALM="${REALM_NAME}-other"
+GONE_REALM="${REALM_NAME}-gone"
+ZONE_NAME="hostidx.test"
+SUBDOMAIN_URN="urn:chip-in:subdomain:${REALM_NAME}:${ZONE_NAME}:www"
+ROUTING_CHAIN_URN="urn:chip-in:routing-chain:${REALM_NAME}:test-chain"
+HUB_FQDN="hub.${ZONE_NAME}"
+
+# POST して HTTP ステータスを返す
+post_status() {
+    curl -s -o /dev/null -w "%{http_code}" -X POST -H "Content-Type: application/json" -d "$2" "$1"
+}
+
+cleanup() {
+    curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts/idx-vh1" > /dev/null || true
+    curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts/idx-vh2" > /dev/null || true
+    curl -s -X DELETE "${API_BASE_URL}/realms/${OTHER_REALM}/virtual-hosts/idx-vh3" > /dev/null || true
+    curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/hubs/idx-hub1" > /dev/null || true
+    curl -s -X DELETE "${API_BASE_URL}/realms/${OTHER_REALM}/hubs/idx-hub2" > /dev/null || true
+    curl -s -X DELETE "${API_BASE_URL}/realms/${OTHER_REALM}/hubs/idx-hub3" > /dev/null || true
+    curl -s -X DELETE "${API_BASE_URL}/realms/${GONE_REALM}/hubs/idx-hub3" > /dev/null || true
+    for realm in "${REALM_NAME}" "${OTHER_REALM}"; do
+        curl -s -X DELETE "${API_BASE_URL}/realms/${realm}/zones/${ZONE_NAME}/subdomains/www" > /dev/null || true
+        curl -s -X DELETE "${API_BASE_URL}/realms/${realm}/zones/${ZONE_NAME}" > /dev/null || true
+    done
+}
+
+# --- Main Script ---
+check_jq
+
+step "P. Create prerequisite Realms and Zones for Host Index Test"
+cleanup
+for realm in "${REALM_NAME}" "${OTHER_REALM}"; do
+    curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${realm}"'", "title": "Host Index Test Realm", "signingKey": "a-very-long-signing-key"}' "${API_BASE_URL}/realms" > /dev/null || true
+    curl -s -X POST -H "Content-Type: application/json" -d '{"zone": "'"${ZONE_NAME}"'", "title": "Host Index Test Zone"}' "${API_BASE_URL}/realms/${realm}/zones" > /dev/null || true
+done
+ok "Prerequisites created."
+
+step "HI1. POST the same subdomain FQDN in two realms (expecting 409 for the second)"
+HTTP_CODE=$(post_status "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains" '{"name": "www", "title": "WWW"}')
+[ "$HTTP_CODE" -eq 200 ] || fail "Expected HTTP 200 for the first subdomain, but got $HTTP_CODE"
+HTTP_CODE=$(post_status "${API_BASE_URL}/realms/${OTHER_REALM}/zones/${ZONE_NAME}/subdomains" '{"name": "www", "title": "WWW"}')
+[ "$HTTP_CODE" -eq 409 ] || fail "Expected HTTP 409 for the duplicate FQDN, but got $HTTP_CODE"
+ok "Duplicate subdomain FQDN rejected."
+
+step "HI2. Bind two virtual hosts to the same subdomain (expecting 409 for the second)"
+VH_JSON='{"name": "NAME", "title": "Index VH", "subdomain": "'"${SUBDOMAIN_URN}"'", "routingChain": "'"${ROUTING_CHAIN_URN}"'"}'
+HTTP_CODE=$(post_status "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts" "${VH_JSON/NAME/idx-vh1}")
+[ "$HTTP_CODE" -eq 200 ] || fail "Expected HTTP 200 for the first virtual host, but got $HTTP_CODE"
+HTTP_CODE=$(post_status "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts" "${VH_JSON/NAME/idx-vh2}")
+[ "$HTTP_CODE" -eq 409 ] || fail "Expected HTTP 409 for the second binding, but got $HTTP_CODE"
+HTTP_CODE=$(post_status "${API_BASE_URL}/realms/${OTHER_REALM}/virtual-hosts" "${VH_JSON/NAME/idx-vh3}")
+[ "$HTTP_CODE" -eq 409 ] || fail "Expected HTTP 409 for a binding from another realm, but got $HTTP_CODE"
+ok "Duplicate binding rejected."
+
+step "HI3. A disabled virtual host does not claim the subdomain"
+DISABLED_JSON=$(echo "${VH_JSON/NAME/idx-vh2}" | jq -c '. + {disabled: true}')
+HTTP_CODE=$(post_status "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts" "$DISABLED_JSON")
+[ "$HTTP_CODE" -eq 200 ] || fail "Expected HTTP 200 for a disabled virtual host, but got $HTTP_CODE"
+ENABLED_JSON=$(echo "$DISABLED_JSON" | jq -c '. + {disabled: false}')
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X PUT -H "Content-Type: application/json" -d "$ENABLED_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts")
+[ "$HTTP_CODE" -eq 409 ] || fail "Expected HTTP 409 when enabling the second binding, but got $HTTP_CODE"
+ok "Disabled virtual host allowed; enabling it is rejected."
+
+step "HI4. POST two hubs with the same fqdn and port (expecting 409 for the second)"
+HTTP_CODE=$(post_status "${API_BASE_URL}/realms/${REALM_NAME}/hubs" '{"name": "idx-hub1", "title": "Index Hub", "fqdn": "'"${HUB_FQDN}"'"}')
+[ "$HTTP_CODE" -eq 200 ] || fail "Expected HTTP 200 for the first hub, but got $HTTP_CODE"
+HTTP_CODE=$(post_status "${API_BASE_URL}/realms/${OTHER_REALM}/hubs" '{"name": "idx-hub2", "title": "Index Hub", "fqdn": "'"${HUB_FQDN}"'", "serverPort": 443}')
+[ "$HTTP_CODE" -eq 409 ] || fail "Expected HTTP 409 for the duplicate endpoint, but got $HTTP_CODE"
+HTTP_CODE=$(post_status "${API_BASE_URL}/realms/${OTHER_REALM}/hubs" '{"name": "idx-hub2", "title": "Index Hub", "fqdn": "'"${HUB_FQDN}"'", "serverPort": 8443}')
+[ "$HTTP_CODE" -eq 200 ] || fail "Expected HTTP 200 for a different port, but got $HTTP_CODE"
+ok "Duplicate hub endpoint rejected."
+
+step "HI5. GET /lookup/host/{fqdn} - Who serves this name"
+BODY=$(curl -s "${API_BASE_URL}/lookup/host/www.${ZONE_NAME}")
+echo "$BODY" | jq -e --arg sub "$SUBDOMAIN_URN" --arg vh "urn:chip-in:virtual-host:${REALM_NAME}:idx-vh1" \
+    '.subdomain == $sub and .virtualHost == $vh and .wildcard == false' > /dev/null || fail "Lookup should return the subdomain and its virtual host.\nGot: $BODY"
+BODY=$(curl -s "${API_BASE_URL}/lookup/host/${HUB_FQDN}")
+echo "$BODY" | jq -e '(.hubs | map(.port) | sort) == [443, 8443] and (.subdomain | not)' > /dev/null || fail "Lookup should return both hub endpoints.\nGot: $BODY"
+ok "Lookup returns the owners."
+
+step "HI6. Deleting the subdomain releases its FQDN"
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains/www" > /dev/null
+HTTP_CODE=$(post_status "${API_BASE_URL}/realms/${OTHER_REALM}/zones/${ZONE_NAME}/subdomains" '{"name": "www", "title": "WWW"}')
+[ "$HTTP_CODE" -eq 200 ] || fail "Expected HTTP 200 after the FQDN was released, but got $HTTP_CODE"
+ok "FQDN released on delete."
+
+step "HI7. POST /admin/rebuild-host-index leaves a consistent index unchanged"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X POST "${API_BASE_URL}/admin/rebuild-host-index")
+[ "$HTTP_CODE" -eq 403 ] || fail "Expected HTTP 403 without an admin token, but got $HTTP_CODE"
+if [ -n "$ADMIN_TOKEN" ]; then
+    BODY=$(curl -s -X POST -H "Authorization: Bearer ${ADMIN_TOKEN}" "${API_BASE_URL}/admin/rebuild-host-index")
+    echo "$BODY" | jq -e '.conflicts | map(select(.key | contains("'"${ZONE_NAME}"'"))) | length == 0' > /dev/null || fail "No conflicts expected.\nGot: $BODY"
+fi
+ok "Index rebuilt."
+
+step "HI8. Deleting a realm releases the entries owned by its resources"
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${GONE_REALM}"'", "title": "Host Index Test Realm", "signingKey": "a-very-long-signing-key"}' "${API_BASE_URL}/realms" > /dev/null
+HTTP_CODE=$(post_status "${API_BASE_URL}/realms/${GONE_REALM}/hubs" '{"name": "idx-hub3", "title": "Index Hub", "fqdn": "gone.'"${ZONE_NAME}"'"}')
+[ "$HTTP_CODE" -eq 200 ] || fail "Expected HTTP 200 for the hub in the realm to delete, but got $HTTP_CODE"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X DELETE "${API_BASE_URL}/realms/${GONE_REALM}")
+[ "$HTTP_CODE" -eq 200 ] || fail "Expected HTTP 200 when deleting the realm, but got $HTTP_CODE"
+HTTP_CODE=$(post_status "${API_BASE_URL}/realms/${OTHER_REALM}/hubs" '{"name": "idx-hub3", "title": "Index Hub", "fqdn": "gone.'"${ZONE_NAME}"'"}')
+[ "$HTTP_CODE" -eq 200 ] || fail "Expected HTTP 200 after the realm released the endpoint, but got $HTTP_CODE"
+ok "Realm deletion released its index entries."
+
+step "Cleanup: Deleting resources used for Host Index test..."
+cleanup
+curl -s -X DELETE "${API_BASE_URL}/realms/${OTHER_REALM}" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}" > /dev/null || true
+ok "Cleanup complete."
+
+step "\e[1;32mAll Host Index tests passed successfully!\e[0m"
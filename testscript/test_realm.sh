@@ -8,7 +8,7 @@ REALM_JSON=$(cat <<EOF
   "name": "${REALM_NAME}",
   "title": "Test Realm",
   "description": "This is a realm for testing.",
-  "cacert": "-----BEGIN CERTIFICATE-----\nMIIC....\n-----END CERTIFICATE-----",
+  "cacert": ${TEST_CA_CERT_JSON},
   "signingKey": "super-secret-key-for-testing-123",
   "sessionTimeout": 3600,
   "administrators": ["admin@test.com"],
@@ -22,7 +22,7 @@ UPDATED_REALM_JSON=$(cat <<EOF
   "name": "${REALM_NAME}",
   "title": "Updated Test Realm",
   "description": "This is an UPDATED realm for testing.",
-  "cacert": "-----BEGIN CERTIFICATE-----\nMIIC....\n-----END CERTIFICATE-----",
+  "cacert": ${TEST_CA_CERT_JSON},
   "signingKey": "super-secret-key-for-testing-123",
   "sessionTimeout": 7200,
   "administrators": ["admin@test.com", "another-admin@test.com"],
@@ -68,7 +68,8 @@ RESPONSE=$(curl -s -w "\n%{http_code}" "${API_BASE_URL}/realms/${REALM_NAME}")
 HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
 BODY=$(echo "$RESPONSE" | sed '$d')
 [ "$HTTP_CODE" -eq 200 ] || fail "Failed to get realm. Expected 200, got $HTTP_CODE"
-EXPECTED_BODY=$(echo "$REALM_JSON" | jq -S '.')
+# signingKey は秘密情報のため、既定ではレスポンスから除去される
+EXPECTED_BODY=$(echo "$REALM_JSON" | jq -S 'del(.signingKey)')
 ACTUAL_BODY=$(echo "$BODY" | jq -S '.')
 [ "$EXPECTED_BODY" == "$ACTUAL_BODY" ] || fail "Retrieved realm does not match created realm.\nExpected: $EXPECTED_BODY\nGot:      $ACTUAL_BODY"
 ok "Retrieved realm matches created one."
@@ -78,17 +79,33 @@ RESPONSE=$(curl -s -w "\n%{http_code}" -X PUT -H "Content-Type: application/json
 HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
 BODY=$(echo "$RESPONSE" | sed '$d')
 [ "$HTTP_CODE" -eq 200 ] || fail "Failed to update realm. Expected 200, got $HTTP_CODE. Body: $BODY"
-EXPECTED_BODY=$(echo "$UPDATED_REALM_JSON" | jq -S '.')
+EXPECTED_BODY=$(echo "$UPDATED_REALM_JSON" | jq -S 'del(.signingKey)')
 ACTUAL_BODY=$(echo "$BODY" | jq -S '.')
 [ "$EXPECTED_BODY" == "$ACTUAL_BODY" ] || fail "Updated response body does not match.\nExpected: $EXPECTED_BODY\nGot:      $ACTUAL_BODY"
 ok "Realm updated successfully."
 
 step "7. GET /realms/${REALM_NAME} - Verifying the updated realm"
 BODY=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}")
-EXPECTED_BODY=$(echo "$UPDATED_REALM_JSON" | jq -S '.')
+EXPECTED_BODY=$(echo "$UPDATED_REALM_JSON" | jq -S 'del(.signingKey)')
 ACTUAL_BODY=$(echo "$BODY" | jq -S '.')
 [ "$EXPECTED_BODY" == "$ACTUAL_BODY" ] || fail "Retrieved realm after update does not match.\nExpected: $EXPECTED_BODY\nGot:      $ACTUAL_BODY"
 
+step "7a. GET /realms/${REALM_NAME}?reveal=secrets - Revealing without a token (expecting 403)"
+RESPONSE=$(curl -s -w "\n%{http_code}" "${API_BASE_URL}/realms/${REALM_NAME}?reveal=secrets")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 403 ] || fail "Expected HTTP 403, but got $HTTP_CODE"
+ok "Correctly received 403 Forbidden."
+
+step "7b. PUT /realms - Updating without signingKey keeps the stored value"
+RESPONSE=$(curl -s -w "\n%{http_code}" -X PUT -H "Content-Type: application/json" -d "$(echo "$UPDATED_REALM_JSON" | jq 'del(.signingKey)')" "${API_BASE_URL}/realms")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to update realm without signingKey. Expected 200, got $HTTP_CODE"
+if [ -n "$REVEAL_SECRETS_TOKEN" ]; then
+    BODY=$(curl -s -H "Authorization: Bearer ${REVEAL_SECRETS_TOKEN}" "${API_BASE_URL}/realms/${REALM_NAME}?reveal=secrets")
+    [ "$(echo "$BODY" | jq -r '.signingKey')" == "$(echo "$UPDATED_REALM_JSON" | jq -r '.signingKey')" ] || fail "Stored signingKey was not kept."
+fi
+ok "signingKey was kept."
+
 step "8. DELETE /realms/${REALM_NAME} - Deleting the realm"
 RESPONSE=$(curl -s -w "\n%{http_code}" -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}")
 HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
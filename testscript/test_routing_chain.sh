@@ -36,7 +36,7 @@ EOF
 check_jq
 
 step "P. Create prerequisite Realm for RoutingChain Test"
-curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${REALM_NAME}"'", "title": "RC Test Realm", "cacert": "cert", "signingKey": "a-very-long-signing-key"}' "${API_BASE_URL}/realms" > /dev/null || true
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${REALM_NAME}"'", "title": "RC Test Realm", "cacert": '"${TEST_CA_CERT_JSON}"', "signingKey": "a-very-long-signing-key"}' "${API_BASE_URL}/realms" > /dev/null || true
 ok "Prerequisite Realm for RoutingChain test created or already exists."
 
 step "RC1. Cleanup: Deleting routing chain '${ROUTING_CHAIN_NAME}' if it exists..."
@@ -52,7 +52,7 @@ ok "Routing chain created successfully."
 
 step "RC3. GET /realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME} - Retrieving the created routing chain"
 BODY=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}")
-ACTUAL_BODY=$(echo "$BODY" | jq 'del(.urn) | del(.realm)' | jq -S '.')
+ACTUAL_BODY=$(echo "$BODY" | jq 'del(.urn) | del(.realm) | del(.version) | del(.activeVersion)' | jq -S '.')
 EXPECTED_BODY=$(echo "$ROUTING_CHAIN_JSON" | jq -S '.')
 [ "$EXPECTED_BODY" == "$ACTUAL_BODY" ] || fail "Retrieved routing chain does not match created one."
 ok "Retrieved routing chain matches."
@@ -69,6 +69,289 @@ HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
 [ "$HTTP_CODE" -eq 200 ] || fail "Failed to delete routing chain. Expected 200, got $HTTP_CODE"
 ok "Routing chain deleted successfully."
 
+step "RC6. POST /realms/${REALM_NAME}/routing-chains - Rejecting an invalid match expression"
+INVALID_MATCH_JSON=$(echo "$ROUTING_CHAIN_JSON" | jq '.rules += [{"match": "request.path ==", "action": {"type": "proxy", "target": "urn:chip-in:service:test:test-service"}}]')
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$INVALID_MATCH_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 422 ] || fail "Expected 422 for an invalid match expression, got $HTTP_CODE. Body: $BODY"
+[ "$(echo "$BODY" | jq -r '.field')" == "rules[1].match" ] || fail "Expected field 'rules[1].match'. Body: $BODY"
+[ "$(echo "$BODY" | jq -r '.rule')" == "1" ] || fail "Expected rule index 1. Body: $BODY"
+[ "$(echo "$BODY" | jq -r '.column')" == "16" ] || fail "Expected column 16. Body: $BODY"
+echo "$BODY" | jq -e '.expected | length > 0' > /dev/null || fail "Expected the expected token in the error. Body: $BODY"
+DEEP_MATCH_JSON=$(echo "$ROUTING_CHAIN_JSON" | jq --arg m "$(printf '!%.0s' $(seq 1 100000))true" '.rules[0].match = $m')
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$DEEP_MATCH_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 422 ] || fail "Expected 422 for a deeply nested match expression, got $HTTP_CODE. Body: $BODY"
+[ "$(echo "$BODY" | jq -r '.column')" == "65" ] || fail "Expected the nesting limit to be reported at column 65. Body: $BODY"
+ok "Invalid match expression rejected with rule, column and expected token, and nesting is limited."
+
+step "RC7. POST /realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/simulate - Tracing a synthetic request"
+SIMULATE_CHAIN_JSON=$(cat <<EOF
+{
+  "name": "${ROUTING_CHAIN_NAME}",
+  "title": "Simulated Routing Chain",
+  "rules": [
+    { "match": "request.path.startsWith(\\"/api/\\")", "action": { "type": "setVariables", "variables": { "backend": "api" } } },
+    { "match": "variables.backend == \\"api\\"", "action": { "type": "setHeaders", "target": "request", "headers": { "X-Backend": "api" } } },
+    { "match": "request.headers[\\"x-backend\\"] == \\"api\\"", "action": { "type": "proxy", "target": "urn:chip-in:service:${REALM_NAME}:test-hub:api" } },
+    { "match": "true", "action": { "type": "redirect", "target": "https://www.example.com/" } }
+  ]
+}
+EOF
+)
+curl -s -X POST -H "Content-Type: application/json" -d "$SIMULATE_CHAIN_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains" > /dev/null
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d '{"method": "GET", "url": "https://www.example.com/api/items?id=1", "clientIp": "192.0.2.1"}' "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/simulate")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to simulate routing chain. Expected 200, got $HTTP_CODE. Body: $BODY"
+[ "$(echo "$BODY" | jq -r '.decision.action.type')" == "proxy" ] || fail "Expected a proxy decision. Body: $BODY"
+[ "$(echo "$BODY" | jq -r '.decision.rule')" == "2" ] || fail "Expected rule 2 to decide. Body: $BODY"
+[ "$(echo "$BODY" | jq -r '.steps | length')" == "3" ] || fail "Expected 3 steps in the trace. Body: $BODY"
+[ "$(echo "$BODY" | jq -r '.steps[1].headers.request["x-backend"]')" == "api" ] || fail "Expected the header set by rule 1 in the trace. Body: $BODY"
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d '{"url": "https://www.example.com/"}' "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/simulate")
+[ "$(echo "$BODY" | jq -r '.decision.action.type')" == "redirect" ] || fail "Expected a redirect decision. Body: $BODY"
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d '{"url": "not a url"}' "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/simulate")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 400 ] || fail "Expected 400 for an invalid URL, got $HTTP_CODE."
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}" > /dev/null
+ok "Simulation trace shows the matched rules, headers and final decision."
+
+step "RC8. Routing chain tests gate updates and can run against a proposed chain"
+TESTED_CHAIN_JSON=$(cat <<EOF
+{
+  "name": "${ROUTING_CHAIN_NAME}",
+  "title": "Tested Routing Chain",
+  "rules": [
+    { "match": "request.path.startsWith(\\"/api/\\")", "action": { "type": "proxy", "target": "urn:chip-in:service:${REALM_NAME}:test-hub:api" } },
+    { "match": "true", "action": { "type": "redirect", "target": "https://www.example.com/" } }
+  ],
+  "tests": [
+    { "name": "api", "request": { "url": "https://www.example.com/api/items" }, "expect": { "action": "proxy", "target": "urn:chip-in:service:${REALM_NAME}:test-hub:api" } },
+    { "name": "fallback", "request": { "url": "https://www.example.com/" }, "expect": { "action": "redirect" } }
+  ]
+}
+EOF
+)
+BROKEN_CHAIN_JSON=$(echo "$TESTED_CHAIN_JSON" | jq '.rules |= [.[1]]')
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$TESTED_CHAIN_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to create a routing chain with passing tests. Expected 200, got $HTTP_CODE."
+RESPONSE=$(curl -s -w "\n%{http_code}" -X PUT -H "Content-Type: application/json" -d "$BROKEN_CHAIN_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 422 ] || fail "Expected 422 for an update that breaks a test, got $HTTP_CODE. Body: $BODY"
+[ "$(echo "$BODY" | jq -r '.tests[0].name')" == "api" ] || fail "Expected the failing test 'api' in the error. Body: $BODY"
+BODY=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}")
+[ "$(echo "$BODY" | jq -r '.rules | length')" == "2" ] || fail "The rejected update must not be saved. Body: $BODY"
+RESPONSE=$(curl -s -w "\n%{http_code}" -X PUT -H "Content-Type: application/json" -d "$(echo "$BROKEN_CHAIN_JSON" | jq 'del(.tests)')" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 422 ] || fail "Expected an update without tests to run the stored tests and fail with 422, got $HTTP_CODE."
+RESPONSE=$(curl -s -w "\n%{http_code}" -X PUT -H "Content-Type: application/json" -d "$(echo "$TESTED_CHAIN_JSON" | jq 'del(.tests) | .title = "Retitled"')" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains?promote=true")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to update a routing chain without tests. Expected 200, got $HTTP_CODE. Body: $BODY"
+[ "$(echo "$BODY" | jq -r '.tests | length')" == "2" ] || fail "Expected an update without tests to keep the stored tests. Body: $BODY"
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$BROKEN_CHAIN_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/test")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to run tests against a proposed chain. Expected 200, got $HTTP_CODE. Body: $BODY"
+[ "$(echo "$BODY" | jq -r '.passed')" == "false" ] || fail "Expected the proposed chain to fail its tests. Body: $BODY"
+[ "$(echo "$BODY" | jq -r '[.results[] | select(.passed)] | length')" == "1" ] || fail "Expected exactly one passing test. Body: $BODY"
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d "$(echo "$BROKEN_CHAIN_JSON" | jq 'del(.tests)')" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/test")
+[ "$(echo "$BODY" | jq -c '[.passed, (.results | length)]')" == '[false,2]' ] || fail "Expected a proposed chain without tests to run the stored tests. Body: $BODY"
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}" > /dev/null
+ok "Routing chain tests reject breaking updates and run against proposed chains."
+
+step "RC9. GET /realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/lint - Flagging shadowed and unreachable rules"
+LINTED_CHAIN_JSON=$(cat <<EOF
+{
+  "name": "${ROUTING_CHAIN_NAME}",
+  "title": "Linted Routing Chain",
+  "rules": [
+    { "match": "request.path.startsWith(\\"/api/\\")", "action": { "type": "proxy", "target": "urn:chip-in:service:${REALM_NAME}:test-hub:api" } },
+    { "match": "request.path.startsWith(\\"/api/v1/\\")", "action": { "type": "proxy", "target": "urn:chip-in:service:${REALM_NAME}:test-hub:api-v1" } },
+    { "match": "true", "action": { "type": "redirect", "target": "https://www.example.com/" } },
+    { "match": "request.path == \\"/\\"", "action": { "type": "proxy", "target": "urn:chip-in:service:${REALM_NAME}:test-hub:root" } }
+  ]
+}
+EOF
+)
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$LINTED_CHAIN_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains?lint=true")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 422 ] || fail "Expected 422 when lint-on-write finds problems, got $HTTP_CODE. Body: $BODY"
+curl -s -X POST -H "Content-Type: application/json" -d "$LINTED_CHAIN_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains" > /dev/null
+RESPONSE=$(curl -s -w "\n%{http_code}" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/lint")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to lint routing chain. Expected 200, got $HTTP_CODE. Body: $BODY"
+[ "$(echo "$BODY" | jq -c '[.findings[] | [.rule, .code]]')" == '[[1,"shadowed"],[3,"unreachable"]]' ] || fail "Unexpected lint findings. Body: $BODY"
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}" > /dev/null
+BARRIER_CHAIN_JSON='{"name": "'"${ROUTING_CHAIN_NAME}"'", "title": "Header Rewrite", "rules": [
+  {"match": "request.headers[\"x-tier\"] == \"gold\"", "action": {"type": "proxy", "target": "urn:chip-in:service:'"${REALM_NAME}"':test-hub:gold"}},
+  {"match": "request.path.startsWith(\"/vip/\")", "action": {"type": "setHeaders", "target": "request", "headers": {"X-Tier": "gold"}}},
+  {"match": "request.headers[\"x-tier\"] == \"gold\" && request.path.startsWith(\"/vip/\")", "action": {"type": "proxy", "target": "urn:chip-in:service:'"${REALM_NAME}"':test-hub:vip"}}]}'
+curl -s -X POST -H "Content-Type: application/json" -d "$BARRIER_CHAIN_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains" > /dev/null
+BODY=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/lint")
+[ "$(echo "$BODY" | jq -c '[.findings[] | select(.code == "shadowed")]')" == '[]' ] || fail "Expected no shadowing across a header rewrite. Body: $BODY"
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}" > /dev/null
+ok "Lint flags shadowed and unreachable rules, and lint-on-write rejects them."
+
+step "RC10. /realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/rules - Editing individual rules"
+RULES_URL="${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/rules"
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${ROUTING_CHAIN_NAME}"'", "title": "Rule CRUD", "rules": [{"id": "fallback", "match": "true", "action": {"type": "redirect", "target": "https://www.example.com/"}}]}' "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains" > /dev/null
+API_RULE_JSON='{"id": "api", "match": "request.path.startsWith(\"/api/\")", "action": {"type": "proxy", "target": "urn:chip-in:service:'"${REALM_NAME}"':test-hub:api"}}'
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$API_RULE_JSON" "${RULES_URL}/0")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to insert a rule. Expected 200, got $HTTP_CODE. Body: $BODY"
+REVISION=$(echo "$BODY" | jq -r '.revision')
+BODY=$(curl -s "${RULES_URL}")
+[ "$(echo "$BODY" | jq -c '[.rules[].id]')" == '["api","fallback"]' ] || fail "Expected the inserted rule first. Body: $BODY"
+[ "$(curl -s "${RULES_URL}/fallback" | jq -r '.index')" == "1" ] || fail "Expected to address a rule by id."
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d '{"to": 0}' "${RULES_URL}/fallback/move?revision=$((REVISION - 1))")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 409 ] || fail "Expected 409 for a stale revision, got $HTTP_CODE."
+RESPONSE=$(curl -s -w "\n%{http_code}" -X PUT -H "Content-Type: application/json" -d '{"id": "fallback", "match": "true", "action": {"type": "redirect", "target": "https://www.example.org/"}}' "${RULES_URL}/fallback?revision=${REVISION}")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to replace a rule at the current revision. Expected 200, got $HTTP_CODE."
+RESPONSE=$(curl -s -w "\n%{http_code}" -X DELETE "${RULES_URL}/api")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to delete a rule. Expected 200, got $HTTP_CODE."
+BODY=$(curl -s "${RULES_URL}")
+[ "$(echo "$BODY" | jq -c '[.rules[] | [.id, .action.target]]')" == '[["fallback","https://www.example.org/"]]' ] || fail "Unexpected rules after editing. Body: $BODY"
+BODY=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}")
+[ "$(echo "$BODY" | jq -c '[.rules[] | [.id, .action.target]]')" == '[["fallback","https://www.example.com/"]]' ] || fail "Expected unpromoted edits to leave the active rules. Body: $BODY"
+RESPONSE=$(curl -s -w "\n%{http_code}" "${RULES_URL}/5")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 404 ] || fail "Expected 404 for a missing rule, got $HTTP_CODE."
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}" > /dev/null
+ok "Individual rules can be inserted, addressed by id, replaced, moved atomically and deleted."
+
+step "RC11. Routing chain versions - Updates create versions that are promoted and rolled back independently"
+CHAIN_URL="${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}"
+V1_JSON='{"name": "'"${ROUTING_CHAIN_NAME}"'", "title": "Versioned", "rules": [{"id": "fallback", "match": "true", "action": {"type": "redirect", "target": "https://www.example.com/"}}]}'
+V2_JSON=$(echo "$V1_JSON" | jq '.rules[0].action.target = "https://www.example.org/" | .rules = [{"id": "api", "match": "request.path.startsWith(\"/api/\")", "action": {"type": "proxy", "target": "urn:chip-in:service:'"${REALM_NAME}"':test-hub:api"}}] + .rules')
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d "$V1_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+[ "$(echo "$BODY" | jq -c '[.version, .activeVersion]')" == '[1,1]' ] || fail "Expected a new chain to activate version 1. Body: $BODY"
+BODY=$(curl -s -X PUT -H "Content-Type: application/json" -d "$V2_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+[ "$(echo "$BODY" | jq -c '[.version, .activeVersion]')" == '[2,1]' ] || fail "Expected an update to create version 2 without activating it. Body: $BODY"
+[ "$(curl -s "${CHAIN_URL}/versions/active" | jq -r '.chain.rules | length')" == "1" ] || fail "Expected the active version to keep the old rules."
+[ "$(curl -s "${CHAIN_URL}" | jq -r '.rules | length')" == "1" ] || fail "Expected the routing chain to keep serving the active version."
+BODY=$(curl -s "${CHAIN_URL}/versions/1/diff/2")
+[ "$(echo "$BODY" | jq -c '[.rules[] | [.op, .from, .to]]')" == '[["added",null,0],["changed",0,1]]' ] || fail "Unexpected diff between versions 1 and 2. Body: $BODY"
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d '{"version": 2}' "${CHAIN_URL}/promote")
+[ "$(echo "$BODY" | jq -c '[.activeVersion, .previousActiveVersion]')" == '[2,1]' ] || fail "Expected version 2 to be promoted. Body: $BODY"
+[ "$(curl -s "${CHAIN_URL}" | jq -r '.rules | length')" == "2" ] || fail "Expected the routing chain to serve the promoted version."
+BODY=$(curl -s -X POST "${CHAIN_URL}/rollback")
+[ "$(echo "$BODY" | jq -r '.activeVersion')" == "1" ] || fail "Expected rollback to version 1. Body: $BODY"
+[ "$(curl -s "${CHAIN_URL}" | jq -c '[.version, (.rules | length)]')" == '[2,1]' ] || fail "Expected the routing chain to serve version 1 after rollback."
+[ "$(curl -s "${CHAIN_URL}/versions" | jq -c '[.[] | [.version, .active]]')" == '[[1,true],[2,false]]' ] || fail "Unexpected version list."
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d '{"version": 9}' "${CHAIN_URL}/promote")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 404 ] || fail "Expected 404 when promoting a missing version, got $HTTP_CODE."
+curl -s -X DELETE "${CHAIN_URL}" > /dev/null
+RESPONSE=$(curl -s -w "\n%{http_code}" "${CHAIN_URL}/versions/1")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 404 ] || fail "Expected versions to be deleted with the chain, got $HTTP_CODE."
+ok "Versions are immutable, diffable, and promoted or rolled back independently of edits."
+
+step "RC12. Weighted proxy targets - Splitting traffic between services with a sticky key"
+issue_test_cert rc-hub "rc-hub.test"
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "rc-hub", "title": "RC Hub", "fqdn": "rc-hub.test", "serverCert": '"$(pem_json "$TEST_PKI_DIR/rc-hub.pem")"', "serverCertKey": '"$(pem_json "$TEST_PKI_DIR/rc-hub.key")"'}' "${API_BASE_URL}/realms/${REALM_NAME}/hubs" > /dev/null || true
+for SERVICE in blue green; do
+  curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${SERVICE}"'", "title": "'"${SERVICE}"'", "realm": "'"${REALM_NAME}"'", "hubName": "rc-hub", "providers": [], "consumers": []}' "${API_BASE_URL}/realms/${REALM_NAME}/hubs/rc-hub/services" > /dev/null || true
+done
+SERVICE_URN="urn:chip-in:service:${REALM_NAME}:rc-hub"
+WEIGHTED_JSON='{"name": "'"${ROUTING_CHAIN_NAME}"'", "title": "Canary", "rules": [{"match": "true", "action": {"type": "proxy", "targets": [{"target": "'"${SERVICE_URN}"':blue", "weight": 90}, {"target": "'"${SERVICE_URN}"':green", "weight": 10}], "sticky": {"cookie": "sid"}}}]}'
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$(echo "$WEIGHTED_JSON" | jq '.rules[0].action.targets[1].weight = 0')" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 400 ] || fail "Expected 400 for a zero weight, got $HTTP_CODE. Body: $BODY"
+[ "$(echo "$BODY" | jq -r '.field')" == "rules[0].action.targets[1].weight" ] || fail "Expected the zero weight to be reported. Body: $BODY"
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$(echo "$WEIGHTED_JSON" | jq '.rules[0].action.targets[1].target += "-missing"')" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 422 ] || fail "Expected 422 for a missing target service, got $HTTP_CODE."
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$WEIGHTED_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to create a weighted routing chain. Expected 200, got $HTTP_CODE. Body: $(echo "$RESPONSE" | sed '$d')"
+SIMULATE_URL="${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/simulate"
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d '{"url": "https://www.example.com/", "hash": 95}' "$SIMULATE_URL")
+[ "$(echo "$BODY" | jq -c '.decision.selection | [.source, .index, .target]')" == '["request",1,"'"${SERVICE_URN}"':green"]' ] || fail "Expected hash 95 to select green. Body: $BODY"
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d '{"url": "https://www.example.com/", "cookies": {"sid": "abc"}}' "$SIMULATE_URL")
+[ "$(echo "$BODY" | jq -c '.decision.selection | [.source, .target]')" == '["cookie","'"${SERVICE_URN}"':blue"]' ] || fail "Expected the sticky cookie to select blue. Body: $BODY"
+echo "$BODY" | grep -q '"hash":16654208175385433931' || fail "Expected the FNV-1a hash of the cookie value. Body: $BODY"
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}" > /dev/null
+for SERVICE in blue green; do
+  curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/hubs/rc-hub/services/${SERVICE}" > /dev/null || true
+done
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/hubs/rc-hub" > /dev/null || true
+ok "Weighted targets are validated and the simulator shows the target chosen for a request hash."
+
+step "RC13. Proxy connection options - Timeouts, retries, upstream TLS and protocol flags"
+OPTIONS_ACTION='{"type": "proxy", "target": "https://reports.internal/", "timeouts": {"connectMs": 2000, "readMs": 300000}, "retry": {"count": 2, "statuses": [502, 503], "backoff": {"initialMs": 100, "maxMs": 2000}}, "tls": {"sni": "reports.internal", "caBundle": "urn:chip-in:realm:'"${REALM_NAME}"'"}, "websocket": true, "maxBodySize": 10485760}'
+OPTIONS_JSON='{"name": "'"${ROUTING_CHAIN_NAME}"'", "title": "Options", "rules": [{"match": "true", "action": '"${OPTIONS_ACTION}"'}]}'
+for CASE in '.timeouts.connectMs = 0|timeouts.connectMs' '.retry.statuses = [200]|retry.statuses[0]' '.http2 = true|http2' '.tls.verify = false|tls.caBundle'; do
+  RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$(echo "$OPTIONS_JSON" | jq ".rules[0].action${CASE%%|*}")" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+  HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+  BODY=$(echo "$RESPONSE" | sed '$d')
+  [ "$HTTP_CODE" -eq 400 ] || fail "Expected 400 for '${CASE%%|*}', got $HTTP_CODE. Body: $BODY"
+  [ "$(echo "$BODY" | jq -r '.field')" == "rules[0].action.${CASE#*|}" ] || fail "Expected '${CASE#*|}' to be reported. Body: $BODY"
+done
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$(echo "$OPTIONS_JSON" | jq '.rules[0].action.tls.caBundle = "urn:chip-in:realm:missing-realm"')" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 422 ] || fail "Expected 422 for a missing CA bundle realm, got $HTTP_CODE."
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$OPTIONS_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to create a routing chain with proxy options. Expected 200, got $HTTP_CODE. Body: $(echo "$RESPONSE" | sed '$d')"
+BODY=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/versions/active")
+[ "$(echo "$BODY" | jq -S -c '.chain.rules[0].action')" == "$(echo "$OPTIONS_ACTION" | jq -S -c .)" ] || fail "Expected the proxy options to be stored as given. Body: $BODY"
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}" > /dev/null
+ok "Proxy connection options are validated and carried through unchanged."
+
+step "RC14. Authenticate action - Requiring a realm session with roles and forwarding identity headers"
+AUTH_JSON='{"name": "'"${ROUTING_CHAIN_NAME}"'", "title": "Auth", "rules": [
+  {"match": "request.path.startsWith(\"/admin/\")", "action": {"type": "authenticate", "roles": ["admin"], "unauthenticated": "redirect", "loginUrl": "/login", "identityHeaders": {"X-User": "sub"}}},
+  {"match": "true", "action": {"type": "proxy", "target": "https://backend.internal/"}}],
+  "tests": [{"name": "anonymous admin", "request": {"url": "https://www.example.com/admin/"}, "expect": {"action": "authenticate", "status": 302}}]}'
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$(echo "$AUTH_JSON" | jq 'del(.rules[0].action.loginUrl)')" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 400 ] || fail "Expected 400 for a redirect without a login URL, got $HTTP_CODE. Body: $BODY"
+[ "$(echo "$BODY" | jq -r '.field')" == "rules[0].action.loginUrl" ] || fail "Expected loginUrl to be reported. Body: $BODY"
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$AUTH_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to create a routing chain with an authenticate rule. Expected 200, got $HTTP_CODE. Body: $(echo "$RESPONSE" | sed '$d')"
+SIMULATE_URL="${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/simulate"
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d '{"url": "https://www.example.com/admin/", "identity": {"roles": ["viewer"], "claims": {"sub": "alice"}}}' "$SIMULATE_URL")
+[ "$(echo "$BODY" | jq -c '.decision | [.action.type, .status, .reason]')" == '["authenticate",403,"missing role '"'admin'"'"]' ] || fail "Expected a user without the role to be forbidden. Body: $BODY"
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d '{"url": "https://www.example.com/admin/", "headers": {"X-User": "mallory"}, "identity": {"roles": ["admin"], "claims": {"sub": "alice"}}}' "$SIMULATE_URL")
+[ "$(echo "$BODY" | jq -c '[.decision.action.type, .headers.request["x-user"]]')" == '["proxy","alice"]' ] || fail "Expected an admin to be proxied with the identity header. Body: $BODY"
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}" > /dev/null
+ok "Authenticate rules are validated, enforce roles and replace identity headers."
+
+step "RC15. Respond action and redirect options - Static responses from blobs and preserved queries"
+curl -s -X PUT -H "Content-Type: text/html" --data-binary "<p>Back soon.</p>" "${API_BASE_URL}/realms/${REALM_NAME}/blobs/rc-page" > /dev/null
+RESPOND_JSON='{"name": "'"${ROUTING_CHAIN_NAME}"'", "title": "Respond", "rules": [
+  {"match": "request.path == \"/old\"", "action": {"type": "redirect", "target": "https://www.example.com/new#top", "status": 308, "preserveQuery": true}},
+  {"match": "true", "action": {"type": "respond", "status": 503, "headers": {"Retry-After": "120"}, "body": {"blob": "rc-page"}}}],
+  "tests": [{"name": "maintenance", "request": {"url": "https://www.example.com/"}, "expect": {"action": "respond", "status": 503}}]}'
+for CASE in '.rules[0].action.status = 303|400' '.rules[1].action.status = 204|400' '.rules[1].action.body.blob = "missing-page"|422'; do
+  RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$(echo "$RESPOND_JSON" | jq "${CASE%%|*}")" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+  HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+  [ "$HTTP_CODE" -eq "${CASE#*|}" ] || fail "Expected ${CASE#*|} for '${CASE%%|*}', got $HTTP_CODE. Body: $(echo "$RESPONSE" | sed '$d')"
+done
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$RESPOND_JSON" "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to create a routing chain with a respond rule. Expected 200, got $HTTP_CODE. Body: $(echo "$RESPONSE" | sed '$d')"
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d '{"url": "https://www.example.com/old?page=2"}' "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}/simulate")
+[ "$(echo "$BODY" | jq -c '.decision | [.status, .location]')" == '[308,"https://www.example.com/new?page=2#top"]' ] || fail "Expected a 308 redirect keeping the query before the fragment. Body: $BODY"
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains/${ROUTING_CHAIN_NAME}" > /dev/null
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/blobs/rc-page" > /dev/null
+ok "Respond rules return static responses and redirects choose their status and keep the query."
+
 step "Cleanup: Deleting prerequisite Realm..."
 curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}" > /dev/null || true
 ok "Cleanup complete."
@@ -22,7 +22,7 @@ UPDATED_SERVICE_JSON=$(cat <<EOF
   "name": "${SERVICE_NAME}",
   "title": "Updated Test Service",
   "description": "An updated service.",
-  "realm": "${REALM_NAME}",
+  "realm": "urn:chip-in:realm:${REALM_NAME}",
   "hubName": "${HUB_NAME}",
   "providers": ["provider1", "provider2"],
   "consumers": ["consumer1", "consumer2"]
@@ -35,9 +35,10 @@ check_jq
 
 step "P. Create prerequisite Realm and Hub for Service Test"
 # Realm
-curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${REALM_NAME}"'", "title": "Service Test Realm", "cacert": "cert", "signingKey": "a-very-long-signing-key"}' "${API_BASE_URL}/realms" > /dev/null || true
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${REALM_NAME}"'", "title": "Service Test Realm", "cacert": '"${TEST_CA_CERT_JSON}"', "signingKey": "a-very-long-signing-key"}' "${API_BASE_URL}/realms" > /dev/null || true
 # Hub
-curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${HUB_NAME}"'", "title": "Test Hub", "fqdn": "h.test", "serverCert": "c", "serverCertKey": "k"}' "${API_BASE_URL}/realms/${REALM_NAME}/hubs" > /dev/null || true
+issue_test_cert service-hub "h.test"
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${HUB_NAME}"'", "title": "Test Hub", "fqdn": "h.test", "serverCert": '"$(pem_json "$TEST_PKI_DIR/service-hub.pem")"', "serverCertKey": '"$(pem_json "$TEST_PKI_DIR/service-hub.key")"'}' "${API_BASE_URL}/realms/${REALM_NAME}/hubs" > /dev/null || true
 ok "Prerequisites for Service test created or already exist."
 
 step "S1. Cleanup: Deleting service '${SERVICE_NAME}' if it exists..."
@@ -54,7 +55,7 @@ ok "Service created successfully."
 step "S3. GET /realms/${REALM_NAME}/hubs/${HUB_NAME}/services/${SERVICE_NAME} - Retrieving the created service"
 BODY=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/hubs/${HUB_NAME}/services/${SERVICE_NAME}")
 ACTUAL_BODY=$(echo "$BODY" | jq 'del(.urn) | del(.hub)' | jq -S '.')
-EXPECTED_BODY=$(echo "$SERVICE_JSON" | jq -S '.')
+EXPECTED_BODY=$(echo "$SERVICE_JSON" | jq --arg realm "urn:chip-in:realm:${REALM_NAME}" '. + {realm: $realm}' | jq -S '.')
 [ "$EXPECTED_BODY" == "$ACTUAL_BODY" ] || fail "Retrieved service does not match created one."
 ok "Retrieved service matches."
 
@@ -31,7 +31,7 @@ check_jq
 
 step "P. Create prerequisite Realm and Zone for Subdomain Test"
 # Realm
-curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${REALM_NAME}"'", "title": "Subdomain Test Realm", "cacert": "cert", "signingKey": "a-very-long-signing-key"}' "${API_BASE_URL}/realms" > /dev/null || true
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${REALM_NAME}"'", "title": "Subdomain Test Realm", "cacert": '"${TEST_CA_CERT_JSON}"', "signingKey": "a-very-long-signing-key"}' "${API_BASE_URL}/realms" > /dev/null || true
 # Zone
 curl -s -X POST -H "Content-Type: application/json" -d '{"zone": "'"${ZONE_NAME}"'", "title": "Test Zone"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones" > /dev/null || true
 ok "Prerequisites for Subdomain test created or already exist."
@@ -81,6 +81,56 @@ HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
 [ "$HTTP_CODE" -eq 404 ] || fail "Expected HTTP 404, but got $HTTP_CODE"
 ok "Correctly received 404 Not Found for deleted subdomain."
 
+step "SD7. POST subdomain with an invalid A record (expecting 400)"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X POST -H "Content-Type: application/json" -d '{"name": "'"${SUBDOMAIN_NAME}"'", "title": "Records", "records": [{"type": "A", "data": "2001:db8::1"}]}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains")
+[ "$HTTP_CODE" -eq 400 ] || fail "Expected HTTP 400, but got $HTTP_CODE"
+ok "Invalid A record rejected."
+
+step "SD8. POST subdomain with a CNAME next to another record (expecting 400)"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X POST -H "Content-Type: application/json" -d '{"name": "'"${SUBDOMAIN_NAME}"'", "title": "Records", "records": [{"type": "CNAME", "data": "target.example.net"}, {"type": "TXT", "data": "hello"}]}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains")
+[ "$HTTP_CODE" -eq 400 ] || fail "Expected HTTP 400, but got $HTTP_CODE"
+ok "CNAME exclusivity enforced."
+
+step "SD9. POST subdomain with typed records"
+RECORDS_JSON='[{"type": "A", "data": "192.0.2.10", "ttl": 60}, {"type": "AAAA", "data": "2001:db8::10"}, {"type": "TXT", "data": "v=spf1 -all"}, {"type": "MX", "data": "10 mail.example.com"}, {"type": "CAA", "data": "0 issue \"letsencrypt.org\""}]'
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d '{"name": "'"${SUBDOMAIN_NAME}"'", "title": "Records", "records": '"${RECORDS_JSON}"'}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 200 ] || fail "Failed to create subdomain with records. Expected 200, got $HTTP_CODE. Body: $BODY"
+echo "$BODY" | jq -e --argjson records "$RECORDS_JSON" '.records == $records' > /dev/null || fail "Records should be stored as given.\nGot: $BODY"
+ZONEFILE=$(curl -s "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/zonefile")
+echo "$ZONEFILE" | grep -qE "^${SUBDOMAIN_NAME}[[:space:]]+60[[:space:]]+IN[[:space:]]+A[[:space:]]+192\.0\.2\.10$" || fail "Zone file should contain the A record.\nGot: $ZONEFILE"
+echo "$ZONEFILE" | grep -qE "^${SUBDOMAIN_NAME}[[:space:]]+[0-9]+[[:space:]]+IN[[:space:]]+MX[[:space:]]+10 mail\.example\.com\.$" || fail "Zone file should contain the MX record.\nGot: $ZONEFILE"
+echo "$ZONEFILE" | grep -qE "^${SUBDOMAIN_NAME}[[:space:]]+[0-9]+[[:space:]]+IN[[:space:]]+TXT[[:space:]]+\"v=spf1 -all\"$" || fail "Zone file should contain the TXT record.\nGot: $ZONEFILE"
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains/${SUBDOMAIN_NAME}" > /dev/null
+ok "Typed records stored and exported."
+
+step "SD10. POST subdomain with a misplaced wildcard (expecting 400)"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X POST -H "Content-Type: application/json" -d '{"name": "apps.*", "title": "Bad Wildcard"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains")
+[ "$HTTP_CODE" -eq 400 ] || fail "Expected HTTP 400, but got $HTTP_CODE"
+ok "Misplaced wildcard rejected."
+
+step "SD11. POST wildcard subdomains '*' and '*.apps'"
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains/*" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains/*.apps" > /dev/null || true
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d '{"name": "*", "title": "Catch-all"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains")
+echo "$BODY" | jq -e '.fqdn == "*.'"${ZONE_NAME}"'"' > /dev/null || fail "Wildcard fqdn should be '*.${ZONE_NAME}'.\nGot: $BODY"
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d '{"name": "*.apps", "title": "Tenant Apps"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains")
+echo "$BODY" | jq -e '.fqdn == "*.apps.'"${ZONE_NAME}"'"' > /dev/null || fail "Wildcard fqdn should be '*.apps.${ZONE_NAME}'.\nGot: $BODY"
+ok "Wildcard subdomains created."
+
+step "SD12. GET /lookup/host/{hostname} - The most specific wildcard wins"
+BODY=$(curl -s "${API_BASE_URL}/lookup/host/tenant1.apps.${ZONE_NAME}")
+echo "$BODY" | jq -e '.subdomain == "urn:chip-in:subdomain:'"${REALM_NAME}"':'"${ZONE_NAME}"':*.apps" and .wildcard == true' > /dev/null \
+    || fail "tenant1.apps.${ZONE_NAME} should resolve to '*.apps'.\nGot: $BODY"
+BODY=$(curl -s "${API_BASE_URL}/lookup/host/other.${ZONE_NAME}")
+echo "$BODY" | jq -e '.fqdn == "*.'"${ZONE_NAME}"'"' > /dev/null || fail "other.${ZONE_NAME} should resolve to '*'.\nGot: $BODY"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" "${API_BASE_URL}/lookup/host/unknown.invalid")
+[ "$HTTP_CODE" -eq 404 ] || fail "Expected HTTP 404 for an unknown host, but got $HTTP_CODE"
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains/*" > /dev/null
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains/*.apps" > /dev/null
+ok "Host lookup resolves wildcards."
+
 step "Cleanup: Deleting prerequisite Realm and Zone..."
 curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}" > /dev/null || true
 curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}" > /dev/null || true
@@ -0,0 +1,86 @@
+#!/bin/bash
+
+source ./test_helper.sh
+
+# URN リゾルバーのテスト
+
+ZONE_NAME="urn.test"
+HUB_NAME="urn-hub"
+SERVICE_NAME="urn-service"
+
+# --- Main Script ---
+check_jq
+
+step "P. Create prerequisite resources for URN Test"
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${REALM_NAME}"'", "title": "URN Test Realm", "signingKey": "a-very-long-signing-key"}' "${API_BASE_URL}/realms" > /dev/null || true
+curl -s -X POST -H "Content-Type: application/json" -d '{"zone": "'"${ZONE_NAME}"'", "title": "URN Test Zone"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones" > /dev/null || true
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${HUB_NAME}"'", "title": "URN Hub", "fqdn": "hub.'"${ZONE_NAME}"'"}' "${API_BASE_URL}/realms/${REALM_NAME}/hubs" > /dev/null || true
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${SERVICE_NAME}"'", "title": "URN Service", "realm": "'"${REALM_NAME}"'", "hubName": "'"${HUB_NAME}"'", "providers": [], "consumers": []}' "${API_BASE_URL}/realms/${REALM_NAME}/hubs/${HUB_NAME}/services" > /dev/null || true
+ok "Prerequisites created."
+
+step "U1. GET /urn/{urn} - Resolve a zone"
+RESPONSE=$(curl -s -w "\n%{http_code}" "${API_BASE_URL}/urn/urn:chip-in:zone:${REALM_NAME}:${ZONE_NAME}")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 200 ] || fail "Expected HTTP 200, but got $HTTP_CODE. Body: $BODY"
+echo "$BODY" | jq -e '.zone == "'"${ZONE_NAME}"'"' > /dev/null || fail "Resolved resource should be the zone.\nGot: $BODY"
+ok "Zone resolved."
+
+step "U2. GET /urn/{urn} - Resolve a service; the service URN round-trips"
+BODY=$(curl -s "${API_BASE_URL}/urn/urn:chip-in:service:${REALM_NAME}:${HUB_NAME}:${SERVICE_NAME}")
+echo "$BODY" | jq -e '.urn == "urn:chip-in:service:'"${REALM_NAME}"':'"${HUB_NAME}"':'"${SERVICE_NAME}"'"' > /dev/null || fail "Resolved service should carry its URN.\nGot: $BODY"
+ok "Service resolved."
+
+step "U3. GET /urn/{urn} - Malformed and unknown URNs"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" "${API_BASE_URL}/urn/urn:chip-in:widget:${REALM_NAME}")
+[ "$HTTP_CODE" -eq 400 ] || fail "Expected HTTP 400 for an unknown type, but got $HTTP_CODE"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" "${API_BASE_URL}/urn/urn:chip-in:hub:${REALM_NAME}:missing-hub")
+[ "$HTTP_CODE" -eq 404 ] || fail "Expected HTTP 404 for a missing hub, but got $HTTP_CODE"
+ok "Errors reported."
+
+step "U4. POST /urn:resolve - Resolve several URNs in one request"
+REQUEST='{"urns": ["urn:chip-in:realm:'"${REALM_NAME}"'", "urn:chip-in:hub:'"${REALM_NAME}"':'"${HUB_NAME}"'", "urn:chip-in:hub:'"${REALM_NAME}"':missing-hub", "not-a-urn"]}'
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d "$REQUEST" "${API_BASE_URL}/urn:resolve")
+echo "$BODY" | jq -e '
+    (.results | length) == 4
+    and .results[0].resource.name == "'"${REALM_NAME}"'"
+    and .results[1].resource.name == "'"${HUB_NAME}"'"
+    and .results[2].error.code == "404"
+    and .results[3].error.field == "urns[3]"' > /dev/null || fail "Batch results do not match.\nGot: $BODY"
+ok "Batch resolution works."
+
+step "U5. Bare names in reference fields are converted to URNs (deprecated form)"
+RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d '{"name": "urn-vh", "title": "URN VH", "subdomain": "urn:chip-in:subdomain:'"${REALM_NAME}"':'"${ZONE_NAME}"':www", "routingChain": "legacy-chain"}' "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts")
+HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+BODY=$(echo "$RESPONSE" | sed '$d')
+[ "$HTTP_CODE" -eq 200 ] || fail "Expected HTTP 200, but got $HTTP_CODE. Body: $BODY"
+echo "$BODY" | jq -e '.realm == "urn:chip-in:realm:'"${REALM_NAME}"'" and .routingChain == "urn:chip-in:routing-chain:'"${REALM_NAME}"':legacy-chain"' > /dev/null \
+    || fail "realm and routingChain should be stored as URNs.\nGot: $BODY"
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d '{"name": "legacy", "title": "Legacy", "destinationRealm": "'"${REALM_NAME}"'"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains")
+echo "$BODY" | jq -e '.destinationRealm == "urn:chip-in:realm:'"${REALM_NAME}"'"' > /dev/null || fail "destinationRealm should be stored as a URN.\nGot: $BODY"
+BODY=$(curl -s -X PUT -H "Content-Type: application/json" -d '{"name": "urn-vh", "title": "URN VH", "subdomain": "legacy", "routingChain": "legacy-chain"}' "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts")
+echo "$BODY" | jq -e '.subdomain == "urn:chip-in:subdomain:'"${REALM_NAME}"':'"${ZONE_NAME}"':legacy"' > /dev/null || fail "A bare subdomain name should be stored as a URN.
+Got: $BODY"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X PUT -H "Content-Type: application/json" -d '{"name": "urn-vh", "title": "URN VH", "subdomain": "no-such-subdomain", "routingChain": "legacy-chain"}' "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts")
+[ "$HTTP_CODE" -eq 422 ] || fail "Expected HTTP 422 for a bare name without a Subdomain, but got $HTTP_CODE"
+ok "Deprecated forms accepted and converted."
+
+step "U6. POST /admin/migrate-urns - Stored documents already use URNs"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X POST "${API_BASE_URL}/admin/migrate-urns")
+[ "$HTTP_CODE" -eq 403 ] || fail "Expected HTTP 403 without an admin token, but got $HTTP_CODE"
+if [ -n "$ADMIN_TOKEN" ]; then
+    BODY=$(curl -s -X POST -H "Authorization: Bearer ${ADMIN_TOKEN}" "${API_BASE_URL}/admin/migrate-urns")
+    echo "$BODY" | jq -e '.migrated == 0 and .conflicts == 0' > /dev/null || fail "Nothing should be left to migrate.\nGot: $BODY"
+fi
+ok "Migration report returned."
+
+step "Cleanup: Deleting resources used for URN test..."
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts/urn-vh" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}/subdomains/legacy" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/hubs/${HUB_NAME}/services/${SERVICE_NAME}" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/hubs/${HUB_NAME}" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ZONE_NAME}" > /dev/null || true
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}" > /dev/null || true
+ok "Cleanup complete."
+
+step "\e[1;32mAll URN tests passed successfully!\e[0m"
@@ -0,0 +1,55 @@
+#!/bin/bash
+
+source ./test_helper.sh
+
+# 名前の検証テスト
+#
+# etcd のキーに使う名前が種類ごとの規則で検証され、フィールド名付きの 400 が返ることを確認する。
+
+# POST して 400 と field を確認する
+expect_invalid() {
+    local url="$1" body="$2" field="$3"
+    RESPONSE=$(curl -s -w "\n%{http_code}" -X POST -H "Content-Type: application/json" -d "$body" "$url")
+    HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
+    BODY=$(echo "$RESPONSE" | sed '$d')
+    [ "$HTTP_CODE" -eq 400 ] || fail "Expected HTTP 400 for $body, but got $HTTP_CODE. Body: $BODY"
+    echo "$BODY" | jq -e --arg field "$field" '.field == $field' > /dev/null || fail "Expected field '$field'.\nGot: $BODY"
+}
+
+# --- Main Script ---
+check_jq
+
+step "P. Create prerequisite Realm for Validation Test"
+curl -s -X POST -H "Content-Type: application/json" -d '{"name": "'"${REALM_NAME}"'", "title": "Validation Test Realm", "signingKey": "a-very-long-signing-key"}' "${API_BASE_URL}/realms" > /dev/null || true
+ok "Prerequisites created."
+
+step "V1. Realm name with a slash (expecting 400, field=name)"
+expect_invalid "${API_BASE_URL}/realms" '{"name": "evil/zones/x", "title": "Bad", "signingKey": "k"}' "name"
+ok "Realm name rejected."
+
+step "V2. Empty routing chain name (expecting 400, field=name)"
+expect_invalid "${API_BASE_URL}/realms/${REALM_NAME}/routing-chains" '{"name": "", "title": "Bad"}' "name"
+ok "Empty routing chain name rejected."
+
+step "V3. Zone name that is not an FQDN (expecting 400, field=zone)"
+expect_invalid "${API_BASE_URL}/realms/${REALM_NAME}/zones" '{"zone": "bad..example", "title": "Bad"}' "zone"
+ok "Zone name rejected."
+
+step "V4. Hub with an invalid fqdn (expecting 400, field=fqdn)"
+expect_invalid "${API_BASE_URL}/realms/${REALM_NAME}/hubs" '{"name": "bad-hub", "title": "Bad", "fqdn": "hub/../x"}' "fqdn"
+ok "Hub fqdn rejected."
+
+step "V5. Service name with a colon (expecting 400, field=name)"
+expect_invalid "${API_BASE_URL}/realms/${REALM_NAME}/hubs/any-hub/services" '{"name": "a:b", "title": "Bad", "realm": "'"${REALM_NAME}"'", "hubName": "any-hub", "providers": [], "consumers": []}' "name"
+ok "Service name rejected."
+
+step "V6. Path segment that is not a valid name (expecting 400)"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" "${API_BASE_URL}/realms/${REALM_NAME}/virtual-hosts/%2E%2E")
+[ "$HTTP_CODE" -eq 400 ] || fail "Expected HTTP 400, but got $HTTP_CODE"
+ok "Invalid path name rejected."
+
+step "Cleanup: Deleting resources used for Validation test..."
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}" > /dev/null || true
+ok "Cleanup complete."
+
+step "\e[1;32mAll Validation tests passed successfully!\e[0m"
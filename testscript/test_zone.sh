@@ -34,7 +34,7 @@ REALM_JSON=$(cat <<EOF
 {
   "name": "${REALM_NAME}",
   "title": "Test Realm for Zone",
-  "cacert": "-----BEGIN CERTIFICATE-----\nMIIC....\n-----END CERTIFICATE-----",
+  "cacert": ${TEST_CA_CERT_JSON},
   "signingKey": "test-realm-signing-key",
   "disabled": false
 }
@@ -97,6 +97,37 @@ HTTP_CODE=$(echo "$RESPONSE" | tail -n1)
 [ "$HTTP_CODE" -eq 404 ] || fail "Expected HTTP 404, but got $HTTP_CODE"
 ok "Correctly received 404 Not Found for deleted zone."
 
+step "Z8. POST zone with a Unicode (IDN) name - Stored as A-label with a display U-label"
+IDN_ZONE="例え.jp"
+IDN_ZONE_ASCII="xn--r8jz45g.jp"
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${IDN_ZONE_ASCII}" > /dev/null || true
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d '{"zone": "'"${IDN_ZONE}"'", "title": "IDN Zone"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones")
+echo "$BODY" | jq -e '.zone == "'"${IDN_ZONE_ASCII}"'" and .displayZone == "'"${IDN_ZONE}"'"' > /dev/null \
+    || fail "IDN zone should be stored as '${IDN_ZONE_ASCII}' with displayZone '${IDN_ZONE}'.\nGot: $BODY"
+ok "IDN zone normalised."
+
+step "Z9. POST the same zone as punycode (expecting 409)"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X POST -H "Content-Type: application/json" -d '{"zone": "'"${IDN_ZONE_ASCII}"'", "title": "IDN Zone"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones")
+[ "$HTTP_CODE" -eq 409 ] || fail "Expected HTTP 409, but got $HTTP_CODE"
+ok "Unicode and punycode forms are the same zone."
+
+step "Z10. Subdomain under the IDN zone, addressed by its Unicode name"
+ENCODED_ZONE=$(jq -rn --arg v "$IDN_ZONE" '$v|@uri')
+BODY=$(curl -s -X POST -H "Content-Type: application/json" -d '{"name": "テスト", "title": "IDN Subdomain"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ENCODED_ZONE}/subdomains")
+echo "$BODY" | jq -e '.name == "xn--zckzah" and .fqdn == "xn--zckzah.'"${IDN_ZONE_ASCII}"'" and .displayFqdn == "テスト.'"${IDN_ZONE}"'"' > /dev/null \
+    || fail "IDN subdomain should be stored in A-label form with a display U-label.\nGot: $BODY"
+ENCODED_SUBDOMAIN=$(jq -rn --arg v "テスト" '$v|@uri')
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" "${API_BASE_URL}/realms/${REALM_NAME}/zones/${ENCODED_ZONE}/subdomains/${ENCODED_SUBDOMAIN}")
+[ "$HTTP_CODE" -eq 200 ] || fail "Subdomain should be reachable by its Unicode name, got $HTTP_CODE"
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${IDN_ZONE_ASCII}/subdomains/xn--zckzah" > /dev/null
+curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}/zones/${IDN_ZONE_ASCII}" > /dev/null
+ok "IDN subdomain normalised."
+
+step "Z11. POST zone with an invalid label (expecting 400)"
+HTTP_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X POST -H "Content-Type: application/json" -d '{"zone": "bad zone.example", "title": "Bad"}' "${API_BASE_URL}/realms/${REALM_NAME}/zones")
+[ "$HTTP_CODE" -eq 400 ] || fail "Expected HTTP 400, but got $HTTP_CODE"
+ok "Invalid zone name rejected."
+
 # 最後に Realm を削除 (cleanup)
 step "Cleanup: Deleting realm '${REALM_NAME}' used for Zone test..."
 curl -s -X DELETE "${API_BASE_URL}/realms/${REALM_NAME}" > /dev/null || true